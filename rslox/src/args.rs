@@ -1,4 +1,4 @@
-﻿use clap::Parser;
+﻿use clap::{Parser, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(name = "rslox")]
@@ -11,4 +11,63 @@ pub struct Args {
     /// Run in interactive REPL mode
     #[arg(short, long, default_value_t = false)]
     pub interactive: bool,
+
+    /// Execution backend
+    #[arg(long, value_enum, default_value_t = Backend::Tree)]
+    pub backend: Backend,
+
+    /// Print the bytecode compiled for the vm backend instead of running it
+    #[arg(long, default_value_t = false)]
+    pub disassemble: bool,
+
+    /// Print garbage collector statistics when the program ends. Only the tree backend has a
+    /// collector, so this cannot be combined with `--backend vm` or `--disassemble`
+    #[arg(long, default_value_t = false)]
+    pub gc_stats: bool,
+
+    /// How errors are reported
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+
+    /// How deep calls may nest before they fail with a stack overflow error
+    #[arg(long, default_value_t = 10_000)]
+    pub max_call_depth: usize,
+
+    /// Stack size in MiB of the thread running the script, which deep recursion needs
+    #[arg(long, default_value_t = 256)]
+    pub stack_size: usize,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// Tree-walking interpreter
+    Tree,
+    /// Bytecode compiler and virtual machine
+    Vm,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ErrorFormat {
+    /// Source snippets with the error underlined
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+impl From<Backend> for rslox::Backend {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Tree => rslox::Backend::Tree,
+            Backend::Vm => rslox::Backend::Vm,
+        }
+    }
+}
+
+impl From<ErrorFormat> for rslox::ErrorFormat {
+    fn from(error_format: ErrorFormat) -> Self {
+        match error_format {
+            ErrorFormat::Human => rslox::ErrorFormat::Human,
+            ErrorFormat::Json => rslox::ErrorFormat::Json,
+        }
+    }
 }
@@ -0,0 +1,264 @@
+use crate::output::Output;
+use crate::span::Span;
+use crate::value::Value;
+use crate::vm_object::VmObject;
+
+/// Instructions of the bytecode VM. Operands follow the opcode byte; the comment on each
+/// instruction lists them. Constant and jump operands are two bytes, big-endian.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    /// constant
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// slot
+    GetLocal,
+    /// slot
+    SetLocal,
+    /// name constant
+    GetGlobal,
+    /// name constant
+    DefineGlobal,
+    /// name constant
+    SetGlobal,
+    /// upvalue index
+    GetUpvalue,
+    /// upvalue index
+    SetUpvalue,
+    /// name constant
+    GetProperty,
+    /// name constant
+    SetProperty,
+    /// name constant
+    GetSuper,
+    GetIndex,
+    SetIndex,
+    /// flags: 1 if a start bound is on the stack, 2 if an end bound is
+    Slice,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// forward offset
+    Jump,
+    /// forward offset
+    JumpIfFalse,
+    /// backward offset
+    Loop,
+    /// argument count
+    Call,
+    /// function constant, then an (is_local, index) byte pair per upvalue
+    Closure,
+    CloseUpvalue,
+    Return,
+    /// name constant
+    Class,
+    Inherit,
+    /// name constant
+    Method,
+    /// element count
+    List,
+    /// entry count
+    Map,
+    /// part count
+    Interpolate,
+    Throw,
+    /// forward offset of the handler
+    PushHandler,
+    PopHandler,
+    /// path constant
+    Import,
+    /// local slot holding what a finally handler caught, which is unwound again
+    Rethrow,
+}
+
+impl OpCode {
+    /// Every opcode, in discriminant order.
+    const ALL: [OpCode; OpCode::Rethrow as usize + 1] = [
+        OpCode::Constant, OpCode::Nil, OpCode::True, OpCode::False, OpCode::Pop,
+        OpCode::GetLocal, OpCode::SetLocal, OpCode::GetGlobal, OpCode::DefineGlobal, OpCode::SetGlobal,
+        OpCode::GetUpvalue, OpCode::SetUpvalue, OpCode::GetProperty, OpCode::SetProperty, OpCode::GetSuper,
+        OpCode::GetIndex, OpCode::SetIndex, OpCode::Slice, OpCode::Equal, OpCode::Greater,
+        OpCode::GreaterEqual, OpCode::Less, OpCode::LessEqual, OpCode::Add, OpCode::Subtract,
+        OpCode::Multiply, OpCode::Divide, OpCode::Not, OpCode::Negate, OpCode::Print,
+        OpCode::Jump, OpCode::JumpIfFalse, OpCode::Loop, OpCode::Call, OpCode::Closure,
+        OpCode::CloseUpvalue, OpCode::Return, OpCode::Class, OpCode::Inherit, OpCode::Method,
+        OpCode::List, OpCode::Map, OpCode::Interpolate, OpCode::Throw, OpCode::PushHandler,
+        OpCode::PopHandler, OpCode::Import, OpCode::Rethrow,
+    ];
+
+    pub fn from_byte(byte: u8) -> OpCode {
+        Self::ALL[byte as usize]
+    }
+}
+
+/// A sequence of bytecode with the constants it refers to and the source line and span of
+/// every byte.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    code: Vec<u8>,
+    lines: Vec<u32>,
+    spans: Vec<Span>,
+    constants: Vec<Value>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::default()
+    }
+
+    pub fn code(&self) -> &Vec<u8> {
+        &self.code
+    }
+
+    pub fn line(&self, offset: usize) -> u32 {
+        self.lines[offset]
+    }
+
+    /// Returns the span of the source the byte was compiled from, which runtime errors point at.
+    pub fn span(&self, offset: usize) -> Span {
+        self.spans[offset]
+    }
+
+    pub fn constant(&self, index: usize) -> &Value {
+        &self.constants[index]
+    }
+
+    pub fn write(&mut self, byte: u8, line: u32, span: Span) {
+        self.code.push(byte);
+        self.lines.push(line);
+        self.spans.push(span);
+    }
+
+    pub fn patch(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte;
+    }
+
+    /// Adds a constant and returns its index.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    pub fn read_short(&self, offset: usize) -> usize {
+        ((self.code[offset] as usize) << 8) | self.code[offset + 1] as usize
+    }
+
+    /// Prints every instruction of the chunk to `output`, followed by the chunks of the functions
+    /// it defines.
+    pub fn disassemble(&self, name: &str, output: &mut dyn Output) {
+        output.print(&format!("== {} ==", name));
+        let mut offset = 0;
+        while offset < self.code.len() {
+            offset = self.disassemble_instruction(offset, output);
+        }
+
+        for constant in &self.constants {
+            if let Value::VmObject(VmObject::Function(function)) = constant {
+                output.print("");
+                function.chunk().disassemble(&function.to_string(), output);
+            }
+        }
+    }
+
+    /// Prints the instruction at the given offset and returns the offset of the next one.
+    pub fn disassemble_instruction(&self, offset: usize, output: &mut dyn Output) -> usize {
+        let line = if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
+            "   |".to_string()
+        } else {
+            format!("{:4}", self.lines[offset])
+        };
+        let mut print = |instruction: String| output.print(&format!("{:04} {} {}", offset, line, instruction));
+
+        let op = OpCode::from_byte(self.code[offset]);
+        let name = format!("{:?}", op);
+        match op {
+            OpCode::Constant | OpCode::GetGlobal | OpCode::DefineGlobal | OpCode::SetGlobal
+            | OpCode::GetProperty | OpCode::SetProperty | OpCode::GetSuper | OpCode::Class | OpCode::Method
+            | OpCode::Import => {
+                let constant = self.read_short(offset + 1);
+                print(format!("{:<16} {:4} '{}'", name, constant, self.constants[constant]));
+                offset + 3
+            },
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::GetUpvalue | OpCode::SetUpvalue
+            | OpCode::Call | OpCode::Slice | OpCode::Rethrow => {
+                print(format!("{:<16} {:4}", name, self.code[offset + 1]));
+                offset + 2
+            },
+            OpCode::List | OpCode::Map | OpCode::Interpolate => {
+                print(format!("{:<16} {:4}", name, self.read_short(offset + 1)));
+                offset + 3
+            },
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::PushHandler => {
+                let target = offset + 3 + self.read_short(offset + 1);
+                print(format!("{:<16} {:4} -> {}", name, offset, target));
+                offset + 3
+            },
+            OpCode::Loop => {
+                let target = offset + 3 - self.read_short(offset + 1);
+                print(format!("{:<16} {:4} -> {}", name, offset, target));
+                offset + 3
+            },
+            OpCode::Closure => {
+                let constant = self.read_short(offset + 1);
+                print(format!("{:<16} {:4} {}", name, constant, self.constants[constant]));
+                let upvalue_count = match &self.constants[constant] {
+                    Value::VmObject(VmObject::Function(function)) => function.upvalue_count(),
+                    _ => 0,
+                };
+                let mut offset = offset + 3;
+                for _ in 0..upvalue_count {
+                    let kind = if self.code[offset] == 1 { "local" } else { "upvalue" };
+                    output.print(&format!("{:04}    |                     {} {}", offset, kind, self.code[offset + 1]));
+                    offset += 2;
+                }
+                offset
+            },
+            _ => {
+                print(name);
+                offset + 1
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, BufferOutput, Lox};
+
+    #[test]
+    fn disassembly_goes_to_the_output() {
+        let output = BufferOutput::new();
+        let mut lox = Lox::new(Backend::Vm);
+        lox.set_output(output.clone());
+        lox.set_disassemble(true);
+        lox.run("fun f() {\n  return 1;\n}\nprint f();");
+        assert_eq!(output.take(), "\
+== script ==
+0000    2 Closure             0 <fn f>
+0003    | DefineGlobal        1 'f'
+0006    4 GetGlobal           2 'f'
+0009    | Call                0
+0011    | Print
+0012    | Nil
+0013    | Return
+
+== <fn f> ==
+0000    2 Constant            0 '1'
+0003    | Return
+0004    | Nil
+0005    | Return
+");
+    }
+}
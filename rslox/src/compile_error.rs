@@ -0,0 +1,38 @@
+use crate::span::Span;
+
+/// An error found while compiling to bytecode, such as a function with too many local variables.
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    line: u32,
+    span: Span,
+    message: String,
+}
+
+impl CompileError {
+    pub fn new(line: u32, span: Span, message: String) -> Self {
+        CompileError {
+            line,
+            span,
+            message,
+        }
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// The source the compiler was working on when it failed.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
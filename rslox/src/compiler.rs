@@ -0,0 +1,920 @@
+use std::rc::Rc;
+use crate::{expr, stmt};
+use crate::chunk::{Chunk, OpCode};
+use crate::compile_error::CompileError;
+use crate::expr::{Assign, Binary, Call, Expr, Get, Grouping, Index, IndexSet, Interpolation, Lambda, List, Literal, Logical, Map, Set, Slice, Super, This, Unary, Variable};
+use crate::literal::LiteralValue;
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::span::Span;
+use crate::stmt::{Block, Break, Class, Continue, Expression, Function, If, Import, Print, Return, Stmt, Throw, Try, Var, While};
+use crate::token::{Token, TokenType};
+use crate::value::Value;
+use crate::vm_object::{VmFunction, VmObject};
+
+/// Compiles the resolved AST into bytecode for the VM.
+pub struct Compiler {
+    /// The functions being compiled, from the script to the innermost one.
+    functions: Vec<FunctionState>,
+    /// Source line of the instructions being emitted.
+    line: u32,
+    /// Source of the node being compiled, which errors point at.
+    span: Span,
+    /// The error that stopped compilation. The visitor methods unwind with a `LoxRuntime`
+    /// only because the visitor traits require one.
+    error: Option<CompileError>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct FunctionState {
+    name: Option<String>,
+    kind: FunctionKind,
+    arity: usize,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+    tries: Vec<TryState>,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+#[derive(PartialEq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+struct LoopState {
+    label: Option<String>,
+    /// Scope depth outside the loop body. Locals deeper than this are discarded by `break` and `continue`.
+    scope_depth: usize,
+    /// Number of enclosing try statements when the loop started.
+    tries: usize,
+    break_jumps: Vec<usize>,
+    continue_jumps: Vec<usize>,
+}
+
+struct TryState {
+    /// Number of exception handlers the try statement has pushed at the current point.
+    handlers: usize,
+    /// Code that must also run when `return`, `break` or `continue` leaves the statement, as
+    /// the syntax tree stores it.
+    #[allow(clippy::vec_box)]
+    finally_body: Option<Vec<Box<Stmt>>>,
+}
+
+impl FunctionState {
+    fn new(name: Option<String>, kind: FunctionKind) -> Self {
+        // Slot 0 holds the receiver in methods, and the called function otherwise.
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        FunctionState {
+            name,
+            kind,
+            arity: 0,
+            chunk: Chunk::new(),
+            locals: vec![Local { name: receiver.to_string(), depth: 0, is_captured: false }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            tries: Vec::new(),
+        }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            functions: Vec::new(),
+            line: 1,
+            span: Span::default(),
+            error: None,
+        }
+    }
+
+    /// Compiles a script into the function that runs its top-level code.
+    pub fn compile(&mut self, statements: &[Box<Stmt>]) -> Result<Rc<VmFunction>, CompileError> {
+        self.compile_script(statements, false)
+    }
+
+    /// Like `compile`, but the function returns the value of the last statement if that is an
+    /// expression statement.
+    pub fn compile_eval(&mut self, statements: &[Box<Stmt>]) -> Result<Rc<VmFunction>, CompileError> {
+        self.compile_script(statements, true)
+    }
+
+    fn compile_script(&mut self, statements: &[Box<Stmt>], return_last: bool) -> Result<Rc<VmFunction>, CompileError> {
+        self.functions = vec![FunctionState::new(None, FunctionKind::Script)];
+        if self.compile_top_level(statements, return_last).is_err() {
+            return Err(self.error.take().expect("Compile errors are recorded before unwinding."));
+        }
+
+        let state = self.functions.pop().unwrap();
+        Ok(Rc::new(VmFunction::new(state.name, state.arity, state.upvalues.len(), state.chunk)))
+    }
+
+    fn compile_top_level(&mut self, statements: &[Box<Stmt>], return_last: bool) -> Result<(), LoxRuntime> {
+        let last_expression = match statements.last().map(|statement| &**statement) {
+            Some(Stmt::Expression(statement)) if return_last => Some(statement.expression()),
+            _ => None,
+        };
+        let count = statements.len() - usize::from(last_expression.is_some());
+        for statement in &statements[..count] {
+            self.compile_stmt(statement)?;
+        }
+        match last_expression {
+            Some(expression) => {
+                self.compile_expr(expression)?;
+                self.emit_op(OpCode::Return);
+            },
+            None => self.emit_return(),
+        }
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxRuntime> {
+        stmt.accept(self)
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), LoxRuntime> {
+        expr.accept(self)
+    }
+
+    fn compile_block(&mut self, statements: &[Box<Stmt>]) -> Result<(), LoxRuntime> {
+        self.begin_scope();
+        for statement in statements {
+            self.compile_stmt(statement)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    /// Makes the instructions emitted next, and errors, belong to the source of the token.
+    fn at(&mut self, token: &Token) {
+        self.line = token.line();
+        self.span = *token.span();
+    }
+
+    /// Records an error at the node being compiled, and returns what unwinds to `compile`.
+    fn error(&mut self, message: &str) -> LoxRuntime {
+        self.error = Some(CompileError::new(self.line, self.span, message.to_string()));
+        LoxRuntime::Error(RuntimeError::new(
+            Token::new(TokenType::Eof, String::new(), None, self.line),
+            message.to_string(),
+        ))
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let (line, span) = (self.line, self.span);
+        self.current().chunk.write(byte, line, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_short(&mut self, value: usize) {
+        self.emit_byte((value >> 8) as u8);
+        self.emit_byte(value as u8);
+    }
+
+    fn emit_with_short(&mut self, op: OpCode, operand: usize) {
+        self.emit_op(op);
+        self.emit_short(operand);
+    }
+
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn make_constant(&mut self, value: Value) -> Result<usize, LoxRuntime> {
+        let index = self.current().chunk.add_constant(value);
+        if index > u16::MAX as usize {
+            return Err(self.error("Too many constants in one chunk."));
+        }
+        Ok(index)
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> Result<usize, LoxRuntime> {
+        self.make_constant(Value::String(name.to_string()))
+    }
+
+    fn emit_constant(&mut self, value: Value) -> Result<(), LoxRuntime> {
+        let index = self.make_constant(value)?;
+        self.emit_with_short(OpCode::Constant, index);
+        Ok(())
+    }
+
+    /// Emits a jump with a placeholder offset and returns the position of the offset.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_with_short(op, 0xffff);
+        self.current().chunk.code().len() - 2
+    }
+
+    /// Points a jump emitted by `emit_jump` at the next instruction.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), LoxRuntime> {
+        let jump = self.current().chunk.code().len() - offset - 2;
+        if jump > u16::MAX as usize {
+            return Err(self.error("Too much code to jump over."));
+        }
+        let chunk = &mut self.current().chunk;
+        chunk.patch(offset, (jump >> 8) as u8);
+        chunk.patch(offset + 1, jump as u8);
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<(), LoxRuntime> {
+        let offset = self.current().chunk.code().len() + 3 - loop_start;
+        if offset > u16::MAX as usize {
+            return Err(self.error("Loop body too large."));
+        }
+        self.emit_with_short(OpCode::Loop, offset);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+            self.emit_op(op);
+            self.current().locals.pop();
+        }
+    }
+
+    /// Emits the instructions that discard the locals deeper than `depth`, without forgetting them.
+    fn discard_locals(&mut self, depth: usize) {
+        let ops: Vec<OpCode> = self.current().locals.iter().rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| if local.is_captured { OpCode::CloseUpvalue } else { OpCode::Pop })
+            .collect();
+        for op in ops {
+            self.emit_op(op);
+        }
+    }
+
+    /// Emits what leaving the try statements above `remaining` requires: removing their handlers
+    /// and running their finally blocks, innermost first.
+    fn exit_tries(&mut self, remaining: usize) -> Result<(), LoxRuntime> {
+        for i in (remaining..self.current().tries.len()).rev() {
+            for _ in 0..self.current().tries[i].handlers {
+                self.emit_op(OpCode::PopHandler);
+            }
+
+            if let Some(finally_body) = self.current().tries[i].finally_body.clone() {
+                // The finally block runs outside of its own try statement.
+                let inner = self.current().tries.split_off(i);
+                self.compile_block(&finally_body)?;
+                self.current().tries.extend(inner);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_local(&mut self, name: &str) -> Result<u8, LoxRuntime> {
+        if self.current().locals.len() > u8::MAX as usize {
+            return Err(self.error("Too many local variables in function."));
+        }
+        let state = self.current();
+        let depth = state.scope_depth;
+        state.locals.push(Local { name: name.to_string(), depth, is_captured: false });
+        Ok((state.locals.len() - 1) as u8)
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        self.functions[function].locals.iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Result<Option<u8>, LoxRuntime> {
+        if function == 0 {
+            return Ok(None);
+        }
+
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(function, UpvalueRef { index: slot, is_local: true }).map(Some);
+        }
+
+        match self.resolve_upvalue(function - 1, name)? {
+            Some(index) => self.add_upvalue(function, UpvalueRef { index, is_local: false }).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueRef) -> Result<u8, LoxRuntime> {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|existing| *existing == upvalue) {
+            return Ok(index as u8);
+        }
+        if upvalues.len() > u8::MAX as usize {
+            return Err(self.error("Too many closure variables in function."));
+        }
+        upvalues.push(upvalue);
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    /// Emits a read of a variable, or a write of the value on top of the stack if `assign` is true.
+    fn named_variable(&mut self, name: &str, assign: bool) -> Result<(), LoxRuntime> {
+        let function = self.functions.len() - 1;
+        let (get, set, operand) = if let Some(slot) = self.resolve_local(function, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(function, name)? {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            let constant = self.identifier_constant(name)?;
+            let op = if assign { OpCode::SetGlobal } else { OpCode::GetGlobal };
+            self.emit_with_short(op, constant);
+            return Ok(());
+        };
+
+        self.emit_op(if assign { set } else { get });
+        self.emit_byte(operand);
+        Ok(())
+    }
+
+    /// Declares a variable. Locals get a slot now; globals are defined by `define_variable`.
+    fn declare_variable(&mut self, name: &Token) -> Result<(), LoxRuntime> {
+        if self.current().scope_depth > 0 {
+            self.add_local(name.lexeme())?;
+        }
+        Ok(())
+    }
+
+    /// Binds the value on top of the stack to a declared variable.
+    fn define_variable(&mut self, name: &Token) -> Result<(), LoxRuntime> {
+        if self.current().scope_depth == 0 {
+            let constant = self.identifier_constant(name.lexeme())?;
+            self.emit_with_short(OpCode::DefineGlobal, constant);
+        }
+        Ok(())
+    }
+
+    /// Compiles a function body and emits the closure that creates it at runtime.
+    fn function(&mut self, function: &Function, kind: FunctionKind) -> Result<(), LoxRuntime> {
+        self.functions.push(FunctionState::new(Some(function.name().lexeme().clone()), kind));
+        self.begin_scope();
+
+        for param in function.params() {
+            self.current().arity += 1;
+            self.add_local(param.lexeme())?;
+        }
+        for statement in function.body() {
+            self.compile_stmt(statement)?;
+        }
+        self.emit_return();
+
+        let state = self.functions.pop().unwrap();
+        let compiled = VmFunction::new(state.name, state.arity, state.upvalues.len(), state.chunk)
+            .with_definition(*function.name().span());
+        let constant = self.make_constant(Value::VmObject(VmObject::Function(Rc::new(compiled))))?;
+        self.emit_with_short(OpCode::Closure, constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+
+    /// Finds the loop a `break` or `continue` with the given label applies to.
+    fn target_loop(&mut self, label: &Option<Token>) -> usize {
+        let loops = &self.current().loops;
+        match label {
+            Some(label) => loops.iter().rposition(|l| l.label.as_deref() == Some(label.lexeme().as_str())).unwrap(),
+            None => loops.len() - 1,
+        }
+    }
+
+    /// Emits the jump out of the body of a loop, leaving the try statements and scopes inside it.
+    fn loop_jump(&mut self, keyword: &Token, label: &Option<Token>) -> Result<usize, LoxRuntime> {
+        self.at(keyword);
+        let target = self.target_loop(label);
+        let (tries, scope_depth) = {
+            let state = &self.current().loops[target];
+            (state.tries, state.scope_depth)
+        };
+
+        self.exit_tries(tries)?;
+        self.discard_locals(scope_depth);
+        Ok(self.emit_jump(OpCode::Jump))
+    }
+}
+
+impl expr::Visitor<()> for Compiler {
+    fn visit_assign_expr(&mut self, expr: &Assign) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.value())?;
+        self.at(expr.name());
+        self.named_variable(expr.name().lexeme(), true)
+    }
+
+    fn visit_binary_expr(&mut self, expr: &Binary) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.left())?;
+        self.compile_expr(expr.right())?;
+        self.at(expr.operator());
+
+        match expr.operator().token_type() {
+            TokenType::Plus => self.emit_op(OpCode::Add),
+            TokenType::Minus => self.emit_op(OpCode::Subtract),
+            TokenType::Star => self.emit_op(OpCode::Multiply),
+            TokenType::Slash => self.emit_op(OpCode::Divide),
+            TokenType::Greater => self.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => self.emit_op(OpCode::GreaterEqual),
+            TokenType::Less => self.emit_op(OpCode::Less),
+            TokenType::LessEqual => self.emit_op(OpCode::LessEqual),
+            TokenType::EqualEqual => self.emit_op(OpCode::Equal),
+            TokenType::BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            },
+            _ => return Err(self.error("Unknown binary operator.")),
+        }
+        Ok(())
+    }
+
+    fn visit_call_expr(&mut self, expr: &Call) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.callee())?;
+        for argument in expr.arguments() {
+            self.compile_expr(argument)?;
+        }
+        self.at(expr.paren());
+        self.emit_op(OpCode::Call);
+        self.emit_byte(expr.arguments().len() as u8);
+        Ok(())
+    }
+
+    fn visit_get_expr(&mut self, expr: &Get) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.object())?;
+        self.at(expr.name());
+        let name = self.identifier_constant(expr.name().lexeme())?;
+        self.emit_with_short(OpCode::GetProperty, name);
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &Grouping) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.expression())
+    }
+
+    fn visit_index_expr(&mut self, expr: &Index) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.object())?;
+        self.compile_expr(expr.index())?;
+        self.at(expr.bracket());
+        self.emit_op(OpCode::GetIndex);
+        Ok(())
+    }
+
+    fn visit_index_set_expr(&mut self, expr: &IndexSet) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.object())?;
+        self.compile_expr(expr.index())?;
+        self.compile_expr(expr.value())?;
+        self.at(expr.bracket());
+        self.emit_op(OpCode::SetIndex);
+        Ok(())
+    }
+
+    fn visit_interpolation_expr(&mut self, expr: &Interpolation) -> anyhow::Result<(), LoxRuntime> {
+        for part in expr.parts() {
+            self.compile_expr(part)?;
+        }
+        self.emit_with_short(OpCode::Interpolate, expr.parts().len());
+        Ok(())
+    }
+
+    fn visit_lambda_expr(&mut self, expr: &Lambda) -> anyhow::Result<(), LoxRuntime> {
+        self.function(expr.function(), FunctionKind::Function)
+    }
+
+    fn visit_list_expr(&mut self, expr: &List) -> anyhow::Result<(), LoxRuntime> {
+        for element in expr.elements() {
+            self.compile_expr(element)?;
+        }
+        self.emit_with_short(OpCode::List, expr.elements().len());
+        Ok(())
+    }
+
+    fn visit_literal_expr(&mut self, expr: &Literal) -> anyhow::Result<(), LoxRuntime> {
+        // Only the span moves: the line of the instructions stays that of the enclosing
+        // operation, which runtime errors report.
+        self.span = *expr.span();
+        match expr.value() {
+            LiteralValue::Nil => self.emit_op(OpCode::Nil),
+            LiteralValue::Boolean(true) => self.emit_op(OpCode::True),
+            LiteralValue::Boolean(false) => self.emit_op(OpCode::False),
+            LiteralValue::Number(n) => self.emit_constant(Value::Number(*n))?,
+            LiteralValue::String(s) => self.emit_constant(Value::String(s.clone()))?,
+        }
+        Ok(())
+    }
+
+    fn visit_logical_expr(&mut self, expr: &Logical) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.left())?;
+        self.at(expr.operator());
+
+        let end_jump = if expr.operator().token_type() == &TokenType::Or {
+            let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+            let end_jump = self.emit_jump(OpCode::Jump);
+            self.patch_jump(else_jump)?;
+            end_jump
+        } else {
+            self.emit_jump(OpCode::JumpIfFalse)
+        };
+
+        self.emit_op(OpCode::Pop);
+        self.compile_expr(expr.right())?;
+        self.patch_jump(end_jump)
+    }
+
+    fn visit_map_expr(&mut self, expr: &Map) -> anyhow::Result<(), LoxRuntime> {
+        for (key, value) in expr.keys().iter().zip(expr.values()) {
+            self.compile_expr(key)?;
+            self.compile_expr(value)?;
+        }
+        self.at(expr.brace());
+        self.emit_with_short(OpCode::Map, expr.keys().len());
+        Ok(())
+    }
+
+    fn visit_set_expr(&mut self, expr: &Set) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.object())?;
+        self.compile_expr(expr.value())?;
+        self.at(expr.name());
+        let name = self.identifier_constant(expr.name().lexeme())?;
+        self.emit_with_short(OpCode::SetProperty, name);
+        Ok(())
+    }
+
+    fn visit_slice_expr(&mut self, expr: &Slice) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.object())?;
+        let mut flags = 0;
+        if let Some(start) = expr.start() {
+            self.compile_expr(start)?;
+            flags |= 1;
+        }
+        if let Some(end) = expr.end() {
+            self.compile_expr(end)?;
+            flags |= 2;
+        }
+        self.at(expr.bracket());
+        self.emit_op(OpCode::Slice);
+        self.emit_byte(flags);
+        Ok(())
+    }
+
+    fn visit_super_expr(&mut self, expr: &Super) -> anyhow::Result<(), LoxRuntime> {
+        self.at(expr.keyword());
+        self.named_variable("this", false)?;
+        self.named_variable("super", false)?;
+        let name = self.identifier_constant(expr.method().lexeme())?;
+        self.at(expr.method());
+        self.emit_with_short(OpCode::GetSuper, name);
+        Ok(())
+    }
+
+    fn visit_this_expr(&mut self, expr: &This) -> anyhow::Result<(), LoxRuntime> {
+        self.at(expr.keyword());
+        self.named_variable("this", false)
+    }
+
+    fn visit_unary_expr(&mut self, expr: &Unary) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(expr.right())?;
+        self.at(expr.operator());
+        match expr.operator().token_type() {
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            TokenType::Bang => self.emit_op(OpCode::Not),
+            _ => return Err(self.error("Unknown unary operator.")),
+        }
+        Ok(())
+    }
+
+    fn visit_variable_expr(&mut self, expr: &Variable) -> anyhow::Result<(), LoxRuntime> {
+        self.at(expr.name());
+        self.named_variable(expr.name().lexeme(), false)
+    }
+}
+
+impl stmt::Visitor<()> for Compiler {
+    fn visit_block_stmt(&mut self, stmt: &Block) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_block(stmt.statements())
+    }
+
+    fn visit_break_stmt(&mut self, stmt: &Break) -> anyhow::Result<(), LoxRuntime> {
+        let jump = self.loop_jump(stmt.keyword(), stmt.label())?;
+        let target = self.target_loop(stmt.label());
+        self.current().loops[target].break_jumps.push(jump);
+        Ok(())
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &Class) -> anyhow::Result<(), LoxRuntime> {
+        self.at(stmt.name());
+        let name = self.identifier_constant(stmt.name().lexeme())?;
+        self.declare_variable(stmt.name())?;
+        self.emit_with_short(OpCode::Class, name);
+        self.define_variable(stmt.name())?;
+
+        if let Some(superclass) = stmt.superclass() {
+            self.compile_expr(superclass)?;
+            self.begin_scope();
+            self.add_local("super")?;
+
+            self.named_variable(stmt.name().lexeme(), false)?;
+            self.emit_op(OpCode::Inherit);
+        }
+
+        self.named_variable(stmt.name().lexeme(), false)?;
+        for method in stmt.methods() {
+            let kind = if method.name().lexeme() == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind)?;
+            self.at(method.name());
+            let name = self.identifier_constant(method.name().lexeme())?;
+            self.emit_with_short(OpCode::Method, name);
+        }
+        self.emit_op(OpCode::Pop);
+
+        if stmt.superclass().is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_continue_stmt(&mut self, stmt: &Continue) -> anyhow::Result<(), LoxRuntime> {
+        let jump = self.loop_jump(stmt.keyword(), stmt.label())?;
+        let target = self.target_loop(stmt.label());
+        self.current().loops[target].continue_jumps.push(jump);
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &Expression) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(stmt.expression())?;
+        self.emit_op(OpCode::Pop);
+        Ok(())
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Function) -> anyhow::Result<(), LoxRuntime> {
+        self.at(stmt.name());
+        // Declared before the body is compiled so that the function can call itself.
+        self.declare_variable(stmt.name())?;
+        self.function(stmt, FunctionKind::Function)?;
+        self.define_variable(stmt.name())
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &If) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(stmt.condition())?;
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.compile_stmt(stmt.then_branch())?;
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump)?;
+        self.emit_op(OpCode::Pop);
+        if let Some(else_branch) = stmt.else_branch() {
+            self.compile_stmt(else_branch)?;
+        }
+        self.patch_jump(else_jump)
+    }
+
+    fn visit_import_stmt(&mut self, stmt: &Import) -> anyhow::Result<(), LoxRuntime> {
+        self.at(stmt.path());
+        let path = match stmt.path().literal() {
+            Some(LiteralValue::String(path)) => self.identifier_constant(path)?,
+            _ => unreachable!("Import paths are string literals."),
+        };
+
+        if let Some(alias) = stmt.alias() {
+            self.declare_variable(alias)?;
+            self.emit_with_short(OpCode::Import, path);
+            self.define_variable(alias)?;
+        }
+        // Modules are only run once, so importing the module again for each name just looks
+        // it up.
+        for name in stmt.names() {
+            self.declare_variable(name)?;
+            self.emit_with_short(OpCode::Import, path);
+            let member = self.identifier_constant(name.lexeme())?;
+            self.emit_with_short(OpCode::GetProperty, member);
+            self.define_variable(name)?;
+        }
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &Print) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(stmt.expression())?;
+        self.emit_op(OpCode::Print);
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &Return) -> anyhow::Result<(), LoxRuntime> {
+        self.at(stmt.keyword());
+        match stmt.value() {
+            Some(value) => self.compile_expr(value)?,
+            None if self.current().kind == FunctionKind::Initializer => {
+                self.emit_op(OpCode::GetLocal);
+                self.emit_byte(0);
+            },
+            None => self.emit_op(OpCode::Nil),
+        }
+
+        if self.current().tries.iter().all(|t| t.finally_body.is_none()) {
+            self.emit_op(OpCode::Return);
+            return Ok(());
+        }
+
+        // Keep the value in a slot of its own while the finally blocks run.
+        self.begin_scope();
+        let slot = self.add_local("")?;
+        self.exit_tries(0)?;
+        self.at(stmt.keyword());
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(slot);
+        self.emit_op(OpCode::Return);
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, stmt: &Throw) -> anyhow::Result<(), LoxRuntime> {
+        self.compile_expr(stmt.value())?;
+        self.at(stmt.keyword());
+        self.emit_op(OpCode::Throw);
+        Ok(())
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &Try) -> anyhow::Result<(), LoxRuntime> {
+        // The finally handler catches anything thrown by the try and catch blocks. It runs the
+        // finally block and unwinds again with what it caught.
+        let finally_handler = match stmt.finally_body() {
+            Some(finally_body) => {
+                let handler = self.emit_jump(OpCode::PushHandler);
+                self.current().tries.push(TryState { handlers: 1, finally_body: Some(finally_body.clone()) });
+                Some(handler)
+            },
+            None => None,
+        };
+
+        let catch_handler = match stmt.catch_body() {
+            Some(_) => {
+                let handler = self.emit_jump(OpCode::PushHandler);
+                if finally_handler.is_some() {
+                    self.current().tries.last_mut().unwrap().handlers += 1;
+                } else {
+                    self.current().tries.push(TryState { handlers: 1, finally_body: None });
+                }
+                Some(handler)
+            },
+            None => None,
+        };
+
+        self.compile_block(stmt.body())?;
+
+        if let (Some(handler), Some(catch_body)) = (catch_handler, stmt.catch_body()) {
+            self.emit_op(OpCode::PopHandler);
+            self.current().tries.last_mut().unwrap().handlers -= 1;
+            let end_jump = self.emit_jump(OpCode::Jump);
+
+            // The VM pushes the caught value, which becomes the catch variable.
+            self.patch_jump(handler)?;
+            self.begin_scope();
+            let name = stmt.catch_name().as_ref().map_or("", |name| name.lexeme().as_str());
+            self.add_local(name)?;
+            for statement in catch_body {
+                self.compile_stmt(statement)?;
+            }
+            self.end_scope();
+            self.patch_jump(end_jump)?;
+
+            if finally_handler.is_none() {
+                self.current().tries.pop();
+            }
+        }
+
+        if let (Some(handler), Some(finally_body)) = (finally_handler, stmt.finally_body()) {
+            self.emit_op(OpCode::PopHandler);
+            self.current().tries.pop();
+            self.compile_block(finally_body)?;
+            let end_jump = self.emit_jump(OpCode::Jump);
+
+            self.patch_jump(handler)?;
+            self.begin_scope();
+            let slot = self.add_local("")?;
+            self.compile_block(finally_body)?;
+            self.emit_op(OpCode::Rethrow);
+            self.emit_byte(slot);
+            self.end_scope();
+            self.patch_jump(end_jump)?;
+        }
+        Ok(())
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &Var) -> anyhow::Result<(), LoxRuntime> {
+        self.at(stmt.name());
+        // A local's slot is where its initializer leaves the value. Declaring it first lets
+        // closures in the initializer refer to the variable, as they can in the interpreter.
+        self.declare_variable(stmt.name())?;
+        match stmt.initializer() {
+            Some(initializer) => self.compile_expr(initializer)?,
+            None => self.emit_op(OpCode::Nil),
+        }
+        self.at(stmt.name());
+        self.define_variable(stmt.name())
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &While) -> anyhow::Result<(), LoxRuntime> {
+        let loop_start = self.current().chunk.code().len();
+        self.compile_expr(stmt.condition())?;
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+
+        let state = self.current();
+        let loop_state = LoopState {
+            label: stmt.label().as_ref().map(|label| label.lexeme().clone()),
+            scope_depth: state.scope_depth,
+            tries: state.tries.len(),
+            break_jumps: Vec::new(),
+            continue_jumps: Vec::new(),
+        };
+        state.loops.push(loop_state);
+
+        self.compile_stmt(stmt.body())?;
+
+        let loop_state = self.current().loops.pop().unwrap();
+        for jump in loop_state.continue_jumps {
+            self.patch_jump(jump)?;
+        }
+        if let Some(increment) = stmt.increment() {
+            self.compile_expr(increment)?;
+            self.emit_op(OpCode::Pop);
+        }
+        self.emit_loop(loop_start)?;
+
+        self.patch_jump(exit_jump)?;
+        self.emit_op(OpCode::Pop);
+        for jump in loop_state.break_jumps {
+            self.patch_jump(jump)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, DiagnosticKind};
+    use crate::testing::{diagnostics, run};
+
+    #[test]
+    fn compile_errors_point_at_the_offending_code() {
+        let locals: String = (0..256).map(|i| format!("var v{};", i)).collect();
+        let source = format!("print \"unreached\";\nfun f() {{\n{}\n}}", locals);
+        let diagnostics = diagnostics(Backend::Vm, &source);
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.kind(), DiagnosticKind::Compile);
+        assert_eq!(diagnostic.message(), "Too many local variables in function.");
+        assert_eq!(diagnostic.line(), 3);
+        let span = diagnostic.span().unwrap();
+        assert_eq!(&source[span.start()..span.end()], "v255");
+    }
+
+    #[test]
+    fn compile_errors_stop_the_script_from_running() {
+        let locals: String = (0..256).map(|i| format!("var v{};", i)).collect();
+        let output = run(Backend::Vm, &format!("print \"unreached\";\nfun f() {{ {} }}", locals));
+        assert!(output.starts_with("error: Too many local variables in function."), "{}", output);
+        assert!(!output.contains("runtime error"), "{}", output);
+        assert!(!output.contains("unreached\n"), "{}", output);
+    }
+}
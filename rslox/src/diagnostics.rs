@@ -0,0 +1,498 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter, Write};
+use std::rc::Rc;
+use crate::compile_error::CompileError;
+use crate::output::Output;
+use crate::parse_error::ParseError;
+use crate::resolve_error::ResolveError;
+use crate::runtime_error::RuntimeError;
+use crate::span::Span;
+use crate::stack_frame::StackFrame;
+use crate::token_type::TokenType;
+
+/// How many times the same frame is shown in a row in a traceback.
+const MAX_REPEATED_FRAMES: usize = 3;
+
+/// The phase of a run that found an error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    /// Found while scanning or parsing.
+    Syntax,
+    /// Found while resolving variables, e.g. `return` outside of a function.
+    Resolve,
+    /// Found while compiling to bytecode.
+    Compile,
+    /// Raised while running, including uncaught exceptions.
+    Runtime,
+}
+
+impl DiagnosticKind {
+    fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::Syntax => "syntax",
+            DiagnosticKind::Resolve => "resolve",
+            DiagnosticKind::Compile => "compile",
+            DiagnosticKind::Runtime => "runtime",
+        }
+    }
+}
+
+/// How diagnostics are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorFormat {
+    /// Compiler-style reports quoting the source, for people.
+    #[default]
+    Human,
+    /// One JSON object per diagnostic, for tools.
+    Json,
+}
+
+/// A secondary message pointing at related source, such as where a variable was declared.
+#[derive(Debug, Clone)]
+pub struct Note {
+    span: Span,
+    message: String,
+}
+
+impl Note {
+    pub fn new(span: Span, message: &str) -> Self {
+        Note {
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+/// An error found in a script.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    kind: DiagnosticKind,
+    line: u32,
+    /// The source the error is about, if known. Errors about code the interpreter made up, and
+    /// runtime errors of the vm backend, only have a line.
+    span: Option<Span>,
+    /// Where on the line the error is, such as " at end" or " at 'x'", if known.
+    location: String,
+    message: String,
+    notes: Vec<Note>,
+    /// Calls a runtime error unwound out of, innermost first.
+    traceback: Vec<StackFrame>,
+    /// File a runtime error was raised in, if it is not the script being run but a module.
+    file: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(kind: DiagnosticKind, line: u32, span: Span, location: String, message: String) -> Self {
+        Diagnostic {
+            kind,
+            line,
+            // Tokens the interpreter makes up have an empty span on line 0.
+            span: Some(span).filter(|span| span.line() > 0),
+            location,
+            message,
+            notes: Vec::new(),
+            traceback: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub fn kind(&self) -> DiagnosticKind {
+        self.kind
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// Returns the calls a runtime error unwound out of, innermost first.
+    pub fn traceback(&self) -> &[StackFrame] {
+        &self.traceback
+    }
+
+    /// Returns the file a runtime error was raised in, if it is not the script being run.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Formats the diagnostic for a script named `file` with the given source. Runtime errors
+    /// raised in another file are shown without source.
+    pub fn render(&self, format: ErrorFormat, file: &str, source: &str) -> String {
+        let (file, source) = match &self.file {
+            Some(other) => (other.as_str(), ""),
+            None => (file, source),
+        };
+        match format {
+            ErrorFormat::Human => self.render_human(file, source),
+            ErrorFormat::Json => self.render_json(file),
+        }
+    }
+
+    fn render_human(&self, file: &str, source: &str) -> String {
+        let heading = match self.kind {
+            DiagnosticKind::Runtime => "runtime error",
+            _ => "error",
+        };
+        let mut text = format!("{}: {}", heading, self.message);
+        write_snippet(&mut text, file, source, self.line, self.span.as_ref());
+        for note in &self.notes {
+            write!(text, "\nnote: {}", note.message).unwrap();
+            write_snippet(&mut text, file, source, note.span.line(), Some(&note.span));
+        }
+        if !self.traceback.is_empty() {
+            text.push_str("\ntraceback (innermost call first):");
+            // Like Python, only the first few of a run of the same frame, as recursion leaves,
+            // are shown.
+            let mut frames = self.traceback.iter().peekable();
+            while let Some(frame) = frames.next() {
+                let mut repeats = 0;
+                while frames.next_if_eq(&frame).is_some() {
+                    repeats += 1;
+                }
+                for _ in 0..=repeats.min(MAX_REPEATED_FRAMES - 1) {
+                    write!(text, "\n  {}", frame).unwrap();
+                }
+                if repeats >= MAX_REPEATED_FRAMES {
+                    write!(text, "\n  [previous frame repeated {} more times]", repeats + 1 - MAX_REPEATED_FRAMES).unwrap();
+                }
+            }
+        }
+        text
+    }
+
+    fn render_json(&self, file: &str) -> String {
+        let mut text = format!(
+            "{{\"kind\":\"{}\",\"message\":{},\"file\":{},",
+            self.kind.name(), json_string(&self.message), json_string(file),
+        );
+        write_json_location(&mut text, self.line, self.span.as_ref());
+        text.push_str(",\"notes\":[");
+        for (i, note) in self.notes.iter().enumerate() {
+            if i > 0 {
+                text.push(',');
+            }
+            write!(text, "{{\"message\":{},", json_string(&note.message)).unwrap();
+            write_json_location(&mut text, note.span.line(), Some(&note.span));
+            text.push('}');
+        }
+        text.push_str("],\"traceback\":[");
+        for (i, frame) in self.traceback.iter().enumerate() {
+            if i > 0 {
+                text.push(',');
+            }
+            write!(
+                text,
+                "{{\"function\":{},\"file\":{},\"line\":{}}}",
+                json_string(frame.function()), json_string(frame.file()), frame.line(),
+            ).unwrap();
+        }
+        text.push_str("]}");
+        text
+    }
+}
+
+/// Writes where a span is and the source line it starts on, underlining the span.
+fn write_snippet(text: &mut String, file: &str, source: &str, line: u32, span: Option<&Span>) {
+    let line = span.map_or(line, |span| span.line());
+    let gutter = " ".repeat(line.to_string().len());
+    match span {
+        Some(span) => write!(text, "\n{}--> {}:{}:{}", gutter, file, line, span.column()).unwrap(),
+        None => write!(text, "\n{}--> {}:{}", gutter, file, line).unwrap(),
+    }
+
+    let Some(source_line) = source.lines().nth((line as usize).wrapping_sub(1)) else {
+        return;
+    };
+    write!(text, "\n{} |\n{} | {}", gutter, line, source_line).unwrap();
+
+    if let Some(span) = span {
+        let indent = span.column().saturating_sub(1) as usize;
+        // Only the part of the span on its first line is underlined, and at least one character
+        // so that empty spans, like the end of the file, are still marked.
+        let length = source.get(span.start()..span.end())
+            .map_or(0, |spanned| spanned.chars().take_while(|&c| c != '\n').count())
+            .max(1);
+        let padding: String = source_line.chars().take(indent).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        write!(text, "\n{} | {}{}", gutter, padding, "^".repeat(length)).unwrap();
+    }
+}
+
+fn write_json_location(text: &mut String, line: u32, span: Option<&Span>) {
+    match span {
+        Some(span) => write!(
+            text,
+            "\"line\":{},\"column\":{},\"start\":{},\"end\":{}",
+            span.line(), span.column(), span.start(), span.end(),
+        ).unwrap(),
+        None => write!(text, "\"line\":{},\"column\":null,\"start\":null,\"end\":null", line).unwrap(),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            DiagnosticKind::Runtime => write!(f, "{}\n[line {}]", self.message, self.line),
+            _ => write!(f, "[line {}] Error{}: {}", self.line, self.location, self.message),
+        }
+    }
+}
+
+/// The errors found during one run, in the order they were found.
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    reporter: Option<Reporter>,
+}
+
+/// Writes diagnostics out as they are recorded.
+#[derive(Clone)]
+struct Reporter {
+    output: Rc<RefCell<dyn Output>>,
+    format: ErrorFormat,
+    file: String,
+    source: String,
+}
+
+impl Diagnostics {
+    /// Creates diagnostics that are only collected, for the host to inspect.
+    pub fn new() -> Self {
+        Diagnostics::default()
+    }
+
+    /// Creates diagnostics that are also written to `output` as soon as they are found, quoting
+    /// the source of the script named `file`.
+    pub fn reporting(output: Rc<RefCell<dyn Output>>, format: ErrorFormat, file: &str, source: &str) -> Self {
+        Diagnostics {
+            diagnostics: Vec::new(),
+            reporter: Some(Reporter {
+                output,
+                format,
+                file: file.to_string(),
+                source: source.to_string(),
+            }),
+        }
+    }
+
+    /// Records an error found outside of the parser, such as an unexpected character.
+    pub fn error(&mut self, kind: DiagnosticKind, line: u32, span: Span, message: &str) {
+        self.push(Diagnostic::new(kind, line, span, String::new(), message.to_string()));
+    }
+
+    pub fn parse_error(&mut self, parse_error: &ParseError) {
+        let location = if parse_error.token().token_type() == &TokenType::Eof {
+            " at end".to_string()
+        } else {
+            format!(" at '{}'", parse_error.token().lexeme())
+        };
+        self.push(Diagnostic::new(DiagnosticKind::Syntax, parse_error.token().line(), *parse_error.span(), location, parse_error.message().clone()));
+    }
+
+    pub fn resolve_error(&mut self, error: &ResolveError) {
+        let location = format!(" at '{}'", error.token().lexeme());
+        let mut diagnostic = Diagnostic::new(DiagnosticKind::Resolve, error.token().line(), *error.span(), location, error.message().clone());
+        diagnostic.notes = error.notes().to_vec();
+        self.push(diagnostic);
+    }
+
+    pub fn compile_error(&mut self, error: &CompileError) {
+        self.push(Diagnostic::new(DiagnosticKind::Compile, error.line(), *error.span(), String::new(), error.message().clone()));
+    }
+
+    pub fn runtime_error(&mut self, error: &RuntimeError) {
+        let mut diagnostic = Diagnostic::new(DiagnosticKind::Runtime, error.token().line(), *error.span(), String::new(), error.message().clone());
+        diagnostic.notes = error.notes().to_vec();
+        diagnostic.traceback = error.traceback().to_vec();
+        diagnostic.file = error.file().map(str::to_string);
+        self.push(diagnostic);
+    }
+
+    fn push(&mut self, mut diagnostic: Diagnostic) {
+        // A note is only useful with the source it points at.
+        diagnostic.notes.retain(|note| note.span.line() > 0);
+        if let Some(reporter) = &self.reporter {
+            if diagnostic.file.as_deref() == Some(reporter.file.as_str()) {
+                diagnostic.file = None;
+            }
+            let text = diagnostic.render(reporter.format, &reporter.file, &reporter.source);
+            reporter.output.borrow_mut().error(&text);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Returns true if an error was found before the script started running.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.kind != DiagnosticKind::Runtime)
+    }
+
+    pub fn has_runtime_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.kind == DiagnosticKind::Runtime)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    /// Returns the diagnostics recorded after the first `start` ones.
+    pub fn since(&self, start: usize) -> &[Diagnostic] {
+        &self.diagnostics[start..]
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiagnosticKind, ErrorFormat};
+    use crate::testing::{diagnostics, run};
+    use crate::{Backend, BufferOutput, Lox};
+
+    #[test]
+    fn each_run_returns_its_own_errors() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let syntax = diagnostics(backend, "@\nprint 1 +;");
+            assert_eq!(syntax.len(), 2, "{:?}", backend);
+            assert!(syntax.iter().all(|diagnostic| diagnostic.kind() == DiagnosticKind::Syntax), "{:?}", backend);
+            assert!(syntax.has_errors() && !syntax.has_runtime_errors(), "{:?}", backend);
+
+            let runtime = diagnostics(backend, "print 1;\nprint nil + 1;");
+            assert_eq!(runtime.len(), 1, "{:?}", backend);
+            assert_eq!(runtime.iter().next().unwrap().line(), 2, "{:?}", backend);
+            assert!(!runtime.has_errors() && runtime.has_runtime_errors(), "{:?}", backend);
+
+            assert!(diagnostics(backend, "print 1;").is_empty(), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn errors_do_not_leak_into_later_runs() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let mut lox = Lox::new(backend);
+            lox.set_output(BufferOutput::new());
+            assert!(lox.run("print nil + 1;").has_runtime_errors(), "{:?}", backend);
+            assert!(lox.run("print;").has_errors(), "{:?}", backend);
+            assert!(lox.run("print 1;").is_empty(), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn scripts_can_run_on_many_threads_at_once() {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let backend = if i % 2 == 0 { Backend::Tree } else { Backend::Vm };
+                    let source = if i < 4 { "print 1;" } else { "print nil + 1;" };
+                    (0..20).all(|_| diagnostics(backend, source).has_runtime_errors() == (i >= 4))
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn errors_carry_the_span_of_the_offending_code() {
+        let syntax = diagnostics(Backend::Tree, "var x = 1;\nprint x +;");
+        let span = syntax.iter().next().unwrap().span().copied().unwrap();
+        assert_eq!((span.line(), span.column(), span.len()), (2, 10, 1));
+
+        let source = "var x = nil;\nprint 1 + x;";
+        let runtime = diagnostics(Backend::Tree, source);
+        let span = runtime.iter().next().unwrap().span().copied().unwrap();
+        assert_eq!((span.line(), span.column(), &source[span.start()..span.end()]), (2, 9, "+"));
+    }
+
+    #[test]
+    fn human_reports_quote_and_underline_the_source() {
+        let output = run(Backend::Tree, "var x = nil;\nprint 1 + x;");
+        assert_eq!(output, "\
+runtime error: Operands must be two numbers or two strings.
+ --> <stdin>:2:9
+  |
+2 | print 1 + x;
+  |         ^
+");
+    }
+
+    #[test]
+    fn notes_point_at_related_source() {
+        let output = run(Backend::Tree, "{\n  var b = 1;\n  var b = 2;\n}");
+        assert_eq!(output, "\
+error: Variable with this name already declared in this scope.
+ --> <stdin>:3:7
+  |
+3 |   var b = 2;
+  |       ^
+note: previously declared here
+ --> <stdin>:2:7
+  |
+2 |   var b = 1;
+  |       ^
+");
+    }
+
+    #[test]
+    fn json_reports_have_one_object_per_line() {
+        let output = BufferOutput::new();
+        let mut lox = Lox::new(Backend::Tree);
+        lox.set_output(output.clone());
+        lox.set_error_format(ErrorFormat::Json);
+        lox.run("{\n  var b = 1;\n  var b = 2;\n}");
+        assert_eq!(output.take(), concat!(
+            "{\"kind\":\"resolve\",\"message\":\"Variable with this name already declared in this scope.\",",
+            "\"file\":\"<stdin>\",\"line\":3,\"column\":7,\"start\":21,\"end\":22,",
+            "\"notes\":[{\"message\":\"previously declared here\",\"line\":2,\"column\":7,\"start\":8,\"end\":9}],\"traceback\":[]}\n",
+        ));
+    }
+}
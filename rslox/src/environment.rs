@@ -1,11 +1,15 @@
-﻿use crate::runtime_error::{LoxRuntime, RuntimeError};
-use crate::token::{Token, TokenType};
+﻿use crate::gc::GcRef;
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::token::Token;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 #[derive(Clone)]
 pub struct Environment {
+    /// Globals, looked up by name.
     values: std::collections::HashMap<String, crate::value::Value>,
+    /// Locals, indexed by the slot the resolver assigned them.
+    slots: Vec<crate::value::Value>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -13,6 +17,7 @@ impl Environment {
     pub fn new() -> Self {
         Environment {
             values: std::collections::HashMap::new(),
+            slots: Vec::new(),
             enclosing: None,
         }
     }
@@ -20,6 +25,7 @@ impl Environment {
     pub fn from_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
         Environment {
             values: std::collections::HashMap::new(),
+            slots: Vec::new(),
             enclosing: Some(enclosing),
         }
     }
@@ -28,34 +34,43 @@ impl Environment {
         self.values.insert(name, value);
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Result<crate::value::Value, LoxRuntime> {
-        let environment = self.ancestor(distance);
-        let env_borrow = environment.borrow();
-        if let Some(value) = env_borrow.values.get(name) {
-            Ok(value.clone())
+    /// Defines a local in the next free slot. Locals are defined in the order the resolver declared them.
+    pub fn define_slot(&mut self, value: crate::value::Value) {
+        self.slots.push(value);
+    }
+
+    /// Returns the variables defined by name in this environment.
+    pub fn values(&self) -> Vec<(String, crate::value::Value)> {
+        self.values.iter().map(|(name, value)| (name.clone(), value.clone())).collect()
+    }
+
+    /// Returns a variable defined directly in this environment, without looking at enclosing ones.
+    pub fn get_here(&self, name: &str) -> Option<crate::value::Value> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn get_at(&self, distance: usize, slot: usize) -> crate::value::Value {
+        if distance == 0 {
+            self.slots[slot].clone()
         } else {
-            Err(LoxRuntime::Error(RuntimeError::new(Token::new(TokenType::Identifier, name.to_string(), None, 0), format!("Undefined variable '{}'.", name))))
+            self.enclosing.as_ref().unwrap().borrow().get_at(distance - 1, slot)
         }
     }
-    
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: crate::value::Value) -> Result<(), LoxRuntime> {
-        let environment = self.ancestor(distance);
-        let mut env_borrow = environment.borrow_mut();
-        if env_borrow.values.contains_key(name.lexeme()) {
-            env_borrow.values.insert(name.lexeme().to_string(), value);
-            Ok(())
+
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: crate::value::Value) {
+        if distance == 0 {
+            self.slots[slot] = value;
         } else {
-            Err(LoxRuntime::Error(RuntimeError::new(name.clone(), format!("Undefined variable '{}'.", name.lexeme()))))
+            self.enclosing.as_ref().unwrap().borrow_mut().assign_at(distance - 1, slot, value);
         }
     }
 
-    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
-        let mut environment = Rc::new(RefCell::new(self.clone()));
-        for _ in 0..distance {
-            let enc = environment.borrow().enclosing.as_ref().unwrap().clone();
-            environment = enc;
+    /// Adds the variables of this environment and the environment enclosing it.
+    pub fn trace(&self, refs: &mut Vec<GcRef>) {
+        self.values.values().chain(&self.slots).for_each(|value| value.trace(refs));
+        if let Some(enclosing) = &self.enclosing {
+            refs.push(GcRef::Environment(Rc::downgrade(enclosing)));
         }
-        environment
     }
 
     pub fn get(&self, name: &Token) -> Result<crate::value::Value, LoxRuntime> {
@@ -79,3 +94,89 @@ impl Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::globals_both;
+
+    #[test]
+    fn closures_keep_their_own_variables() {
+        let values = globals_both("
+            fun counter() {
+                var n = 0;
+                fun inc() { n = n + 1; return n; }
+                return inc;
+            }
+            var a = counter();
+            var b = counter();
+            var first = a();
+            var second = a();
+            var other = b();
+        ", &["first", "second", "other"]);
+        assert_eq!(values, ["1", "2", "1"]);
+    }
+
+    #[test]
+    fn inner_scopes_shadow_outer_ones() {
+        let values = globals_both("
+            var x = \"global\";
+            var seen = \"\";
+            {
+                var x = \"outer\";
+                { var x = \"inner\"; seen = seen + x + \" \"; }
+                seen = seen + x + \" \";
+            }
+            seen = seen + x;
+            var loop = \"\";
+            {
+                var i = \"block\";
+                for (var i = 0; i < 2; i = i + 1) {
+                    var j = i;
+                    loop = loop + \"${j} \";
+                }
+                loop = loop + i;
+            }
+        ", &["seen", "loop"]);
+        assert_eq!(values, ["inner outer global", "0 1 block"]);
+    }
+
+    #[test]
+    fn variables_resolve_to_the_scope_they_were_declared_in() {
+        let values = globals_both("
+            var x = \"global\";
+            var before;
+            var after;
+            {
+                fun show() { return x; }
+                before = show();
+                var x = \"local\";
+                after = show();
+            }
+        ", &["before", "after"]);
+        assert_eq!(values, ["global", "global"]);
+    }
+
+    #[test]
+    fn closures_reach_variables_several_scopes_out() {
+        let values = globals_both("
+            fun deep() {
+                var a = 1;
+                { var b = 2; { var c = 3; fun f() { return a + b + c; } return f; } }
+            }
+            var sum = deep()();
+        ", &["sum"]);
+        assert_eq!(values, ["6"]);
+    }
+
+    #[test]
+    fn globals_are_looked_up_when_used() {
+        let values = globals_both("
+            fun read() { return late; }
+            var late = 1;
+            var first = read();
+            late = 2;
+            var second = read();
+        ", &["first", "second"]);
+        assert_eq!(values, ["1", "2"]);
+    }
+}
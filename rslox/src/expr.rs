@@ -1,24 +1,40 @@
 //[ Appendix II expr
+#![allow(dead_code, clippy::vec_box, clippy::borrowed_box)]
+use std::rc::Rc;
 use crate::literal::LiteralValue;
 use crate::runtime_error::LoxRuntime;
+use crate::span::Span;
+use crate::stmt::Function;
 use crate::token::Token;
 use anyhow::Result;
 
 // Assign
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Assign {
+    id: usize,
+    span: Span,
     name: Token,
     value: Box<Expr>,
 }
 
 impl Assign {
-    pub fn new(name: Token, value: Box<Expr>) -> Self {
+    pub fn new(id: usize, span: Span, name: Token, value: Box<Expr>) -> Self {
         Assign {
+            id,
+            span,
             name,
             value,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn name(&self) -> &Token {
         &self.name
     }
@@ -31,20 +47,32 @@ impl Assign {
 // Binary
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Binary {
+    id: usize,
+    span: Span,
     left: Box<Expr>,
     operator: Token,
     right: Box<Expr>,
 }
 
 impl Binary {
-    pub fn new(left: Box<Expr>, operator: Token, right: Box<Expr>) -> Self {
+    pub fn new(id: usize, span: Span, left: Box<Expr>, operator: Token, right: Box<Expr>) -> Self {
         Binary {
+            id,
+            span,
             left,
             operator,
             right,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn left(&self) -> &Box<Expr> {
         &self.left
     }
@@ -61,20 +89,32 @@ impl Binary {
 // Call
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Call {
+    id: usize,
+    span: Span,
     callee: Box<Expr>,
     paren: Token,
     arguments: Vec<Box<Expr>>,
 }
 
 impl Call {
-    pub fn new(callee: Box<Expr>, paren: Token, arguments: Vec<Box<Expr>>) -> Self {
+    pub fn new(id: usize, span: Span, callee: Box<Expr>, paren: Token, arguments: Vec<Box<Expr>>) -> Self {
         Call {
+            id,
+            span,
             callee,
             paren,
             arguments,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn callee(&self) -> &Box<Expr> {
         &self.callee
     }
@@ -91,18 +131,30 @@ impl Call {
 // Get
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Get {
+    id: usize,
+    span: Span,
     object: Box<Expr>,
     name: Token,
 }
 
 impl Get {
-    pub fn new(object: Box<Expr>, name: Token) -> Self {
+    pub fn new(id: usize, span: Span, object: Box<Expr>, name: Token) -> Self {
         Get {
+            id,
+            span,
             object,
             name,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn object(&self) -> &Box<Expr> {
         &self.object
     }
@@ -115,34 +167,238 @@ impl Get {
 // Grouping
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Grouping {
+    id: usize,
+    span: Span,
     expression: Box<Expr>,
 }
 
 impl Grouping {
-    pub fn new(expression: Box<Expr>) -> Self {
+    pub fn new(id: usize, span: Span, expression: Box<Expr>) -> Self {
         Grouping {
+            id,
+            span,
             expression,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn expression(&self) -> &Box<Expr> {
         &self.expression
     }
 }
 
+// Index
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Index {
+    id: usize,
+    span: Span,
+    object: Box<Expr>,
+    bracket: Token,
+    index: Box<Expr>,
+}
+
+impl Index {
+    pub fn new(id: usize, span: Span, object: Box<Expr>, bracket: Token, index: Box<Expr>) -> Self {
+        Index {
+            id,
+            span,
+            object,
+            bracket,
+            index,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn object(&self) -> &Box<Expr> {
+        &self.object
+    }
+
+    pub fn bracket(&self) -> &Token {
+        &self.bracket
+    }
+
+    pub fn index(&self) -> &Box<Expr> {
+        &self.index
+    }
+}
+
+// IndexSet
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct IndexSet {
+    id: usize,
+    span: Span,
+    object: Box<Expr>,
+    bracket: Token,
+    index: Box<Expr>,
+    value: Box<Expr>,
+}
+
+impl IndexSet {
+    pub fn new(id: usize, span: Span, object: Box<Expr>, bracket: Token, index: Box<Expr>, value: Box<Expr>) -> Self {
+        IndexSet {
+            id,
+            span,
+            object,
+            bracket,
+            index,
+            value,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn object(&self) -> &Box<Expr> {
+        &self.object
+    }
+
+    pub fn bracket(&self) -> &Token {
+        &self.bracket
+    }
+
+    pub fn index(&self) -> &Box<Expr> {
+        &self.index
+    }
+
+    pub fn value(&self) -> &Box<Expr> {
+        &self.value
+    }
+}
+
+// Interpolation
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Interpolation {
+    id: usize,
+    span: Span,
+    parts: Vec<Box<Expr>>,
+}
+
+impl Interpolation {
+    pub fn new(id: usize, span: Span, parts: Vec<Box<Expr>>) -> Self {
+        Interpolation {
+            id,
+            span,
+            parts,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn parts(&self) -> &Vec<Box<Expr>> {
+        &self.parts
+    }
+}
+
+// Lambda
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Lambda {
+    id: usize,
+    span: Span,
+    function: Rc<Function>,
+}
+
+impl Lambda {
+    pub fn new(id: usize, span: Span, function: Rc<Function>) -> Self {
+        Lambda {
+            id,
+            span,
+            function,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn function(&self) -> &Rc<Function> {
+        &self.function
+    }
+}
+
+// List
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct List {
+    id: usize,
+    span: Span,
+    elements: Vec<Box<Expr>>,
+}
+
+impl List {
+    pub fn new(id: usize, span: Span, elements: Vec<Box<Expr>>) -> Self {
+        List {
+            id,
+            span,
+            elements,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn elements(&self) -> &Vec<Box<Expr>> {
+        &self.elements
+    }
+}
+
 // Literal
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Literal {
+    id: usize,
+    span: Span,
     value: LiteralValue,
 }
 
 impl Literal {
-    pub fn new(value: LiteralValue) -> Self {
+    pub fn new(id: usize, span: Span, value: LiteralValue) -> Self {
         Literal {
+            id,
+            span,
             value,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn value(&self) -> &LiteralValue {
         &self.value
     }
@@ -151,20 +407,32 @@ impl Literal {
 // Logical
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Logical {
+    id: usize,
+    span: Span,
     left: Box<Expr>,
     operator: Token,
     right: Box<Expr>,
 }
 
 impl Logical {
-    pub fn new(left: Box<Expr>, operator: Token, right: Box<Expr>) -> Self {
+    pub fn new(id: usize, span: Span, left: Box<Expr>, operator: Token, right: Box<Expr>) -> Self {
         Logical {
+            id,
+            span,
             left,
             operator,
             right,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn left(&self) -> &Box<Expr> {
         &self.left
     }
@@ -178,23 +446,77 @@ impl Logical {
     }
 }
 
+// Map
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Map {
+    id: usize,
+    span: Span,
+    brace: Token,
+    keys: Vec<Box<Expr>>,
+    values: Vec<Box<Expr>>,
+}
+
+impl Map {
+    pub fn new(id: usize, span: Span, brace: Token, keys: Vec<Box<Expr>>, values: Vec<Box<Expr>>) -> Self {
+        Map {
+            id,
+            span,
+            brace,
+            keys,
+            values,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn brace(&self) -> &Token {
+        &self.brace
+    }
+
+    pub fn keys(&self) -> &Vec<Box<Expr>> {
+        &self.keys
+    }
+
+    pub fn values(&self) -> &Vec<Box<Expr>> {
+        &self.values
+    }
+}
+
 // Set
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Set {
+    id: usize,
+    span: Span,
     object: Box<Expr>,
     name: Token,
     value: Box<Expr>,
 }
 
 impl Set {
-    pub fn new(object: Box<Expr>, name: Token, value: Box<Expr>) -> Self {
+    pub fn new(id: usize, span: Span, object: Box<Expr>, name: Token, value: Box<Expr>) -> Self {
         Set {
+            id,
+            span,
             object,
             name,
             value,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn object(&self) -> &Box<Expr> {
         &self.object
     }
@@ -208,21 +530,147 @@ impl Set {
     }
 }
 
+// Slice
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Slice {
+    id: usize,
+    span: Span,
+    object: Box<Expr>,
+    bracket: Token,
+    start: Option<Box<Expr>>,
+    end: Option<Box<Expr>>,
+}
+
+impl Slice {
+    pub fn new(id: usize, span: Span, object: Box<Expr>, bracket: Token, start: Option<Box<Expr>>, end: Option<Box<Expr>>) -> Self {
+        Slice {
+            id,
+            span,
+            object,
+            bracket,
+            start,
+            end,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn object(&self) -> &Box<Expr> {
+        &self.object
+    }
+
+    pub fn bracket(&self) -> &Token {
+        &self.bracket
+    }
+
+    pub fn start(&self) -> &Option<Box<Expr>> {
+        &self.start
+    }
+
+    pub fn end(&self) -> &Option<Box<Expr>> {
+        &self.end
+    }
+}
+
+// Super
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Super {
+    id: usize,
+    span: Span,
+    keyword: Token,
+    method: Token,
+}
+
+impl Super {
+    pub fn new(id: usize, span: Span, keyword: Token, method: Token) -> Self {
+        Super {
+            id,
+            span,
+            keyword,
+            method,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn keyword(&self) -> &Token {
+        &self.keyword
+    }
+
+    pub fn method(&self) -> &Token {
+        &self.method
+    }
+}
+
+// This
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct This {
+    id: usize,
+    span: Span,
+    keyword: Token,
+}
+
+impl This {
+    pub fn new(id: usize, span: Span, keyword: Token) -> Self {
+        This {
+            id,
+            span,
+            keyword,
+        }
+    }
+
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn keyword(&self) -> &Token {
+        &self.keyword
+    }
+}
+
 // Unary
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Unary {
+    id: usize,
+    span: Span,
     operator: Token,
     right: Box<Expr>,
 }
 
 impl Unary {
-    pub fn new(operator: Token, right: Box<Expr>) -> Self {
+    pub fn new(id: usize, span: Span, operator: Token, right: Box<Expr>) -> Self {
         Unary {
+            id,
+            span,
             operator,
             right,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn operator(&self) -> &Token {
         &self.operator
     }
@@ -235,16 +683,28 @@ impl Unary {
 // Variable
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Variable {
+    id: usize,
+    span: Span,
     name: Token,
 }
 
 impl Variable {
-    pub fn new(name: Token) -> Self {
+    pub fn new(id: usize, span: Span, name: Token) -> Self {
         Variable {
+            id,
+            span,
             name,
         }
     }
 
+    pub fn id(&self) -> &usize {
+        &self.id
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn name(&self) -> &Token {
         &self.name
     }
@@ -258,9 +718,18 @@ pub enum Expr {
     Call(Call),
     Get(Get),
     Grouping(Grouping),
+    Index(Index),
+    IndexSet(IndexSet),
+    Interpolation(Interpolation),
+    Lambda(Lambda),
+    List(List),
     Literal(Literal),
     Logical(Logical),
+    Map(Map),
     Set(Set),
+    Slice(Slice),
+    Super(Super),
+    This(This),
     Unary(Unary),
     Variable(Variable),
 }
@@ -272,9 +741,18 @@ pub trait Visitor<T> {
     fn visit_call_expr(&mut self, expr: &Call) -> Result<T, LoxRuntime>;
     fn visit_get_expr(&mut self, expr: &Get) -> Result<T, LoxRuntime>;
     fn visit_grouping_expr(&mut self, expr: &Grouping) -> Result<T, LoxRuntime>;
+    fn visit_index_expr(&mut self, expr: &Index) -> Result<T, LoxRuntime>;
+    fn visit_index_set_expr(&mut self, expr: &IndexSet) -> Result<T, LoxRuntime>;
+    fn visit_interpolation_expr(&mut self, expr: &Interpolation) -> Result<T, LoxRuntime>;
+    fn visit_lambda_expr(&mut self, expr: &Lambda) -> Result<T, LoxRuntime>;
+    fn visit_list_expr(&mut self, expr: &List) -> Result<T, LoxRuntime>;
     fn visit_literal_expr(&mut self, expr: &Literal) -> Result<T, LoxRuntime>;
     fn visit_logical_expr(&mut self, expr: &Logical) -> Result<T, LoxRuntime>;
+    fn visit_map_expr(&mut self, expr: &Map) -> Result<T, LoxRuntime>;
     fn visit_set_expr(&mut self, expr: &Set) -> Result<T, LoxRuntime>;
+    fn visit_slice_expr(&mut self, expr: &Slice) -> Result<T, LoxRuntime>;
+    fn visit_super_expr(&mut self, expr: &Super) -> Result<T, LoxRuntime>;
+    fn visit_this_expr(&mut self, expr: &This) -> Result<T, LoxRuntime>;
     fn visit_unary_expr(&mut self, expr: &Unary) -> Result<T, LoxRuntime>;
     fn visit_variable_expr(&mut self, expr: &Variable) -> Result<T, LoxRuntime>;
 }
@@ -288,13 +766,46 @@ impl Expr {
             Expr::Call(expr) => visitor.visit_call_expr(expr),
             Expr::Get(expr) => visitor.visit_get_expr(expr),
             Expr::Grouping(expr) => visitor.visit_grouping_expr(expr),
+            Expr::Index(expr) => visitor.visit_index_expr(expr),
+            Expr::IndexSet(expr) => visitor.visit_index_set_expr(expr),
+            Expr::Interpolation(expr) => visitor.visit_interpolation_expr(expr),
+            Expr::Lambda(expr) => visitor.visit_lambda_expr(expr),
+            Expr::List(expr) => visitor.visit_list_expr(expr),
             Expr::Literal(expr) => visitor.visit_literal_expr(expr),
             Expr::Logical(expr) => visitor.visit_logical_expr(expr),
+            Expr::Map(expr) => visitor.visit_map_expr(expr),
             Expr::Set(expr) => visitor.visit_set_expr(expr),
+            Expr::Slice(expr) => visitor.visit_slice_expr(expr),
+            Expr::Super(expr) => visitor.visit_super_expr(expr),
+            Expr::This(expr) => visitor.visit_this_expr(expr),
             Expr::Unary(expr) => visitor.visit_unary_expr(expr),
             Expr::Variable(expr) => visitor.visit_variable_expr(expr),
         }
     }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Expr::Assign(expr) => expr.span(),
+            Expr::Binary(expr) => expr.span(),
+            Expr::Call(expr) => expr.span(),
+            Expr::Get(expr) => expr.span(),
+            Expr::Grouping(expr) => expr.span(),
+            Expr::Index(expr) => expr.span(),
+            Expr::IndexSet(expr) => expr.span(),
+            Expr::Interpolation(expr) => expr.span(),
+            Expr::Lambda(expr) => expr.span(),
+            Expr::List(expr) => expr.span(),
+            Expr::Literal(expr) => expr.span(),
+            Expr::Logical(expr) => expr.span(),
+            Expr::Map(expr) => expr.span(),
+            Expr::Set(expr) => expr.span(),
+            Expr::Slice(expr) => expr.span(),
+            Expr::Super(expr) => expr.span(),
+            Expr::This(expr) => expr.span(),
+            Expr::Unary(expr) => expr.span(),
+            Expr::Variable(expr) => expr.span(),
+        }
+    }
 }
 
 //] Appendix II expr
@@ -0,0 +1,374 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+use crate::environment::Environment;
+use crate::lox_callable::LoxCallable;
+use crate::lox_class::LoxClass;
+use crate::lox_function::LoxFunction;
+use crate::lox_instance::LoxInstance;
+use crate::lox_map::LoxMap;
+use crate::lox_module::LoxModule;
+use crate::output::Output;
+use crate::value::Value;
+
+/// Number of tracked objects allocated before the first collection.
+const INITIAL_THRESHOLD: usize = 1024;
+/// After a collection, the next one runs once the heap has grown by this factor.
+const GROWTH_FACTOR: usize = 2;
+
+/// A non-owning reference to a heap object of the tree-walking interpreter.
+///
+/// The collector only ever holds weak references, so that the strong count of an object is
+/// exactly the number of references the program holds to it.
+#[derive(Clone)]
+pub enum GcRef {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    Class(Weak<LoxClass>),
+    Function(Weak<LoxFunction>),
+    Callable(Weak<dyn LoxCallable>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<LoxMap>>),
+    Module(Weak<LoxModule>),
+}
+
+impl GcRef {
+    fn address(&self) -> usize {
+        match self {
+            GcRef::Environment(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::Instance(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::Class(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::Function(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::Callable(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::List(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::Map(weak) => weak.as_ptr() as *const () as usize,
+            GcRef::Module(weak) => weak.as_ptr() as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            GcRef::Environment(weak) => weak.strong_count(),
+            GcRef::Instance(weak) => weak.strong_count(),
+            GcRef::Class(weak) => weak.strong_count(),
+            GcRef::Function(weak) => weak.strong_count(),
+            GcRef::Callable(weak) => weak.strong_count(),
+            GcRef::List(weak) => weak.strong_count(),
+            GcRef::Map(weak) => weak.strong_count(),
+            GcRef::Module(weak) => weak.strong_count(),
+        }
+    }
+
+    /// Adds the objects this one holds strong references to. Returns false if the object is
+    /// mutably borrowed, in which case its references cannot be read.
+    fn trace(&self, refs: &mut Vec<GcRef>) -> bool {
+        match self {
+            GcRef::Environment(weak) => Self::trace_cell(weak, |environment| environment.trace(refs)),
+            GcRef::Instance(weak) => Self::trace_cell(weak, |instance| instance.trace(refs)),
+            GcRef::List(weak) => Self::trace_cell(weak, |list| list.iter().for_each(|element| element.trace(refs))),
+            GcRef::Map(weak) => Self::trace_cell(weak, |map| map.entries().iter().for_each(|(_, value)| value.trace(refs))),
+            GcRef::Class(weak) => {
+                if let Some(class_) = weak.upgrade() {
+                    class_.trace(refs);
+                }
+                true
+            },
+            GcRef::Function(weak) => {
+                if let Some(function) = weak.upgrade() {
+                    function.trace(refs);
+                }
+                true
+            },
+            GcRef::Callable(weak) => {
+                if let Some(callable) = weak.upgrade() {
+                    callable.trace(refs);
+                }
+                true
+            },
+            GcRef::Module(weak) => {
+                if let Some(module) = weak.upgrade() {
+                    module.trace(refs);
+                }
+                true
+            },
+        }
+    }
+
+    fn trace_cell<T>(weak: &Weak<RefCell<T>>, trace: impl FnOnce(&T)) -> bool {
+        let Some(object) = weak.upgrade() else {
+            return true;
+        };
+        match object.try_borrow() {
+            Ok(object) => {
+                trace(&object);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+/// Counters reported by `--gc-stats`.
+#[derive(Clone, Default)]
+pub struct GcStats {
+    collections: usize,
+    environments_allocated: usize,
+    instances_allocated: usize,
+    lists_allocated: usize,
+    maps_allocated: usize,
+    environments_freed: usize,
+    instances_freed: usize,
+    lists_freed: usize,
+    maps_freed: usize,
+    time: Duration,
+}
+
+/// Tracks the environments, instances, lists and maps of the tree-walking interpreter and frees
+/// the ones that are only kept alive by reference cycles.
+///
+/// Values are reference counted, which frees everything except cycles, such as an instance
+/// storing itself in a field, a list containing itself or a function stored in the environment
+/// it closes over. A collection finds the tracked objects that cannot be reached from outside
+/// the heap and clears their variables, fields and elements, which breaks the cycles and lets
+/// reference counting free them. Lists and maps made by native functions are not tracked.
+///
+/// Objects referenced from outside the heap are found without scanning the Rust stack: an
+/// object whose strong count is higher than the number of references the heap holds to it is
+/// also referenced by the interpreter itself, so it is a root.
+#[derive(Clone)]
+pub struct Heap {
+    objects: Vec<GcRef>,
+    next_collection: usize,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            next_collection: INITIAL_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn track_environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        self.objects.push(GcRef::Environment(Rc::downgrade(environment)));
+        self.stats.environments_allocated += 1;
+    }
+
+    pub fn track_instance(&mut self, instance: &Rc<RefCell<LoxInstance>>) {
+        self.objects.push(GcRef::Instance(Rc::downgrade(instance)));
+        self.stats.instances_allocated += 1;
+    }
+
+    pub fn track_list(&mut self, list: &Rc<RefCell<Vec<Value>>>) {
+        self.objects.push(GcRef::List(Rc::downgrade(list)));
+        self.stats.lists_allocated += 1;
+    }
+
+    pub fn track_map(&mut self, map: &Rc<RefCell<LoxMap>>) {
+        self.objects.push(GcRef::Map(Rc::downgrade(map)));
+        self.stats.maps_allocated += 1;
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.objects.len() >= self.next_collection
+    }
+
+    /// Frees the tracked objects that are neither reachable from the given roots nor from
+    /// anything outside the heap.
+    pub fn collect(&mut self, roots: &[GcRef]) {
+        let start = Instant::now();
+        self.objects.retain(|object| object.strong_count() > 0);
+
+        if let Some(garbage) = self.find_garbage(roots) {
+            self.sweep(garbage);
+        }
+
+        self.objects.retain(|object| object.strong_count() > 0);
+        self.next_collection = (self.objects.len() * GROWTH_FACTOR).max(INITIAL_THRESHOLD);
+        self.stats.collections += 1;
+        self.stats.time += start.elapsed();
+    }
+
+    /// Returns the indices of the unreachable tracked objects, or None if an object could not be
+    /// traced because it is being modified.
+    fn find_garbage(&self, roots: &[GcRef]) -> Option<Vec<usize>> {
+        // Build the graph of everything reachable from the tracked objects, counting the
+        // references that come from within the graph.
+        let mut nodes: Vec<GcRef> = Vec::new();
+        let mut indices: HashMap<usize, usize> = HashMap::new();
+        let mut edges: Vec<Vec<usize>> = Vec::new();
+        let mut internal_refs: Vec<usize> = Vec::new();
+
+        for object in self.objects.iter().chain(roots) {
+            if let std::collections::hash_map::Entry::Vacant(entry) = indices.entry(object.address()) {
+                entry.insert(nodes.len());
+                nodes.push(object.clone());
+                edges.push(Vec::new());
+                internal_refs.push(0);
+            }
+        }
+
+        let mut refs = Vec::new();
+        let mut next = 0;
+        while next < nodes.len() {
+            if !nodes[next].trace(&mut refs) {
+                return None;
+            }
+            for child in refs.drain(..) {
+                let index = *indices.entry(child.address()).or_insert_with(|| {
+                    nodes.push(child);
+                    edges.push(Vec::new());
+                    internal_refs.push(0);
+                    nodes.len() - 1
+                });
+                internal_refs[index] += 1;
+                edges[next].push(index);
+            }
+            next += 1;
+        }
+
+        // Mark from the roots and from every object that something outside the graph refers to.
+        let mut marked = vec![false; nodes.len()];
+        let mut pending: Vec<usize> = roots.iter().map(|root| indices[&root.address()]).collect();
+        pending.extend((0..nodes.len()).filter(|&index| nodes[index].strong_count() > internal_refs[index]));
+
+        while let Some(index) = pending.pop() {
+            if !marked[index] {
+                marked[index] = true;
+                pending.extend(edges[index].iter().filter(|&&child| !marked[child]));
+            }
+        }
+
+        Some((0..self.objects.len()).filter(|&index| !marked[indices[&self.objects[index].address()]]).collect())
+    }
+
+    /// Clears the given tracked objects. Their contents are dropped only once all of them have
+    /// been cleared, so that dropping never recurses through a long chain of garbage.
+    fn sweep(&mut self, garbage: Vec<usize>) {
+        let mut environments = Vec::new();
+        let mut fields = Vec::new();
+        let mut elements = Vec::new();
+        let mut entries = Vec::new();
+
+        for index in garbage {
+            match &self.objects[index] {
+                GcRef::Environment(weak) => if let Some(environment) = weak.upgrade() {
+                    environments.push(std::mem::replace(&mut *environment.borrow_mut(), Environment::new()));
+                    self.stats.environments_freed += 1;
+                },
+                GcRef::Instance(weak) => if let Some(instance) = weak.upgrade() {
+                    fields.push(instance.borrow_mut().take_fields());
+                    self.stats.instances_freed += 1;
+                },
+                GcRef::List(weak) => if let Some(list) = weak.upgrade() {
+                    elements.push(std::mem::take(&mut *list.borrow_mut()));
+                    self.stats.lists_freed += 1;
+                },
+                GcRef::Map(weak) => if let Some(map) = weak.upgrade() {
+                    entries.push(std::mem::take(&mut *map.borrow_mut()));
+                    self.stats.maps_freed += 1;
+                },
+                _ => unreachable!("Only environments, instances, lists and maps are tracked."),
+            }
+        }
+
+        drop(environments);
+        drop(fields);
+        drop(elements);
+        drop(entries);
+    }
+
+    /// Prints the collector's counters.
+    pub fn print_stats(&self, output: &mut dyn Output) {
+        let stats = &self.stats;
+        let live = |kind: fn(&GcRef) -> bool| self.objects.iter().filter(|object| kind(object) && object.strong_count() > 0).count();
+        output.print(&format!("GC collections:      {} ({:.3} ms)", stats.collections, stats.time.as_secs_f64() * 1000.0));
+        output.print(&format!(
+            "Environments:        {} allocated, {} freed by the collector, {} live",
+            stats.environments_allocated,
+            stats.environments_freed,
+            live(|object| matches!(object, GcRef::Environment(_))),
+        ));
+        output.print(&format!(
+            "Instances:           {} allocated, {} freed by the collector, {} live",
+            stats.instances_allocated,
+            stats.instances_freed,
+            live(|object| matches!(object, GcRef::Instance(_))),
+        ));
+        output.print(&format!(
+            "Lists:               {} allocated, {} freed by the collector, {} live",
+            stats.lists_allocated,
+            stats.lists_freed,
+            live(|object| matches!(object, GcRef::List(_))),
+        ));
+        output.print(&format!(
+            "Maps:                {} allocated, {} freed by the collector, {} live",
+            stats.maps_allocated,
+            stats.maps_freed,
+            live(|object| matches!(object, GcRef::Map(_))),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::{Rc, Weak};
+    use crate::{Backend, BufferOutput, Lox, Value};
+
+    /// Runs source that passes the objects to check to `watch`, and returns weak references to
+    /// them. Enough is allocated after the source to make the collector run.
+    fn watch_objects(source: &str) -> Vec<Weak<dyn std::any::Any>> {
+        let watched: Rc<RefCell<Vec<Weak<dyn std::any::Any>>>> = Rc::new(RefCell::new(Vec::new()));
+        let mut lox = Lox::new(Backend::Tree);
+        let output = BufferOutput::new();
+        lox.set_output(output.clone());
+        let sink = watched.clone();
+        lox.define_native("watch", 1, move |args| {
+            let weak: Weak<dyn std::any::Any> = match args.get(0) {
+                Value::LoxInstance(instance) => Rc::downgrade(instance) as Weak<dyn std::any::Any>,
+                Value::List(list) => Rc::downgrade(list) as Weak<dyn std::any::Any>,
+                Value::Map(map) => Rc::downgrade(map) as Weak<dyn std::any::Any>,
+                value => panic!("can only watch instances, lists and maps, got {}", value),
+            };
+            sink.borrow_mut().push(weak);
+            Ok(Value::Nil)
+        });
+        lox.run(&format!("{}\nfor (var i = 0; i < 3000; i = i + 1) {{ var x = 0; }}", source));
+        assert_eq!(output.take(), "");
+        watched.take()
+    }
+
+    #[test]
+    fn instances_only_referencing_themselves_are_freed() {
+        let watched = watch_objects("class Node {} { var node = Node(); node.next = node; watch(node); }");
+        assert!(watched[0].upgrade().is_none());
+    }
+
+    #[test]
+    fn instances_referencing_each_other_are_freed() {
+        let watched = watch_objects("class Node {} { var a = Node(); var b = Node(); a.next = b; b.next = a; watch(a); watch(b); }");
+        assert!(watched.iter().all(|weak| weak.upgrade().is_none()));
+    }
+
+    #[test]
+    fn lists_only_referencing_themselves_are_freed() {
+        let watched = watch_objects("{ var xs = []; xs.push(xs); watch(xs); }");
+        assert!(watched[0].upgrade().is_none());
+    }
+
+    #[test]
+    fn maps_and_lists_referencing_each_other_are_freed() {
+        let watched = watch_objects("{ var m = {}; var xs = [m]; m[\"xs\"] = xs; watch(m); watch(xs); }");
+        assert!(watched.iter().all(|weak| weak.upgrade().is_none()));
+    }
+
+    #[test]
+    fn reachable_cycles_are_kept() {
+        let watched = watch_objects("class Node {} var node = Node(); node.next = node; watch(node);");
+        assert!(watched[0].upgrade().is_some());
+    }
+}
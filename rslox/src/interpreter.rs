@@ -1,62 +1,332 @@
-use crate::expr::{Expr, Binary, Grouping, Literal, Unary, Variable, Assign, Logical, Call, Get, Set};
+use crate::expr::{Expr, Binary, Grouping, Literal, Unary, Variable, Assign, Logical, Call, Get, Set, Super, This, Index, IndexSet, Interpolation, Lambda, List, Map, Slice};
 use crate::{expr, stmt};
-use crate::runtime_error::{LoxRuntime, RuntimeError, RuntimeReturn};
-use crate::stmt::{Block, Class, Expression, Function, If, Print, Return, Stmt, Var, While};
+use crate::runtime_error::{LoxRuntime, RuntimeError, RuntimeReturn, RuntimeThrow};
+use crate::stmt::{Block, Break, Class, Continue, Expression, Function, If, Import, Print, Return, Stmt, Throw, Try, Var, While};
 use crate::value::Value;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::diagnostics::{Diagnostics, ErrorFormat};
 use crate::environment::Environment;
+use crate::gc::{GcRef, Heap};
 use crate::literal::LiteralValue;
-use crate::lox::Lox;
+use crate::lox_callable::{Arity, LoxCallable};
+use crate::lox_class::LoxClass;
+use crate::lox_function::LoxFunction;
+use crate::lox_instance::LoxInstance;
+use crate::lox_list::{list_index, slice_bound, ListMethod};
+use crate::lox_map::{LoxMap, MapKey, MapMethod};
+use crate::lox_module::{self, LoxModule};
+use crate::lox_native::{LoxNative, NativeArgs, NativeError};
+use crate::lox_native_class::NativeInstance;
+use crate::output::{Output, StdoutOutput};
+use crate::stack_frame::StackFrame;
 use crate::token::{Token, TokenType};
 
+/// How deep calls of the tree backend may nest unless configured otherwise. Every call of a Lox
+/// function recurses on the Rust stack, and this many fit on the 2 MiB stack of a thread spawned
+/// with the default size even in debug builds.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100;
+
 #[derive(Clone)]
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,
+    /// Native functions, shared by the globals of every module.
+    builtins: Rc<RefCell<Environment>>,
     global: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    /// Depth and slot of every resolved local variable, keyed by the ID of the expression using it.
+    locals: HashMap<usize, (usize, usize)>,
+    modules: HashMap<PathBuf, Rc<LoxModule>>,
+    /// Files currently running, from the main script to the innermost import.
+    import_stack: Vec<PathBuf>,
+    /// Class of the objects that native runtime errors are turned into when they are caught.
+    runtime_error_class: Rc<LoxClass>,
+    heap: Heap,
+    /// Line of the innermost call, see `call_line`.
+    call_line: u32,
+    /// Calls of Lox functions that have not returned yet, innermost last.
+    call_stack: Vec<StackFrame>,
+    /// Length of the call stack at which further calls fail with a stack overflow.
+    max_call_depth: usize,
+    /// File of the code running now, which calls are recorded as made from.
+    file: Rc<str>,
+    /// Where `print` statements and runtime errors are written.
+    output: Rc<RefCell<dyn Output>>,
+    error_format: ErrorFormat,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let global = Rc::new(RefCell::new(Environment::new()));
+        let builtins = Rc::new(RefCell::new(Environment::new()));
 
-        global.borrow_mut().define(
-            "clock".to_string(),
-            Value::LoxCallable(Rc::new(crate::lox_clock::LoxClock::new())),
-        );
+        let global = Rc::new(RefCell::new(Environment::from_enclosing(builtins.clone())));
 
-        Interpreter {
+        let mut heap = Heap::new();
+        heap.track_environment(&builtins);
+        heap.track_environment(&global);
+
+        let mut interpreter = Interpreter {
             environment: global.clone(),
+            builtins,
             global,
             locals: HashMap::new(),
+            modules: HashMap::new(),
+            import_stack: Vec::new(),
+            runtime_error_class: Rc::new(LoxClass::new("RuntimeError".to_string(), None, HashMap::new())),
+            heap,
+            call_line: 0,
+            call_stack: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            file: Rc::from("<stdin>"),
+            output: Rc::new(RefCell::new(StdoutOutput::new())),
+            error_format: ErrorFormat::Human,
+        };
+
+        interpreter.define_native("clock", 0, |_| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("Time went backwards");
+            Ok(Value::Number(now.as_secs_f64()))
+        });
+        interpreter
+    }
+
+    /// Defines a function implemented in Rust, visible to every module. The function receives
+    /// the arguments of the call, which have been checked against its arity.
+    pub fn define_native<F>(&mut self, name: &str, arity: impl Into<Arity>, function: F)
+    where
+        F: Fn(&NativeArgs) -> Result<Value, NativeError> + 'static,
+    {
+        let native = LoxNative::new(name, arity.into(), Box::new(function));
+        self.define_builtin(name, Value::LoxCallable(Rc::new(native)));
+    }
+
+    pub fn define_builtin(&mut self, name: &str, value: Value) {
+        self.builtins.borrow_mut().define(name.to_string(), value);
+    }
+
+    /// Returns the native functions and other builtins, by name.
+    pub fn builtins(&self) -> Vec<(String, Value)> {
+        self.builtins.borrow().values()
+    }
+
+    pub fn output(&self) -> Rc<RefCell<dyn Output>> {
+        self.output.clone()
+    }
+
+    pub fn set_output(&mut self, output: Rc<RefCell<dyn Output>>) {
+        self.output = output;
+    }
+
+    pub fn error_format(&self) -> ErrorFormat {
+        self.error_format
+    }
+
+    /// Sets how errors in imported modules are written.
+    pub fn set_error_format(&mut self, error_format: ErrorFormat) {
+        self.error_format = error_format;
+    }
+
+    /// Line of the innermost call, which errors raised by native functions are reported at.
+    pub fn call_line(&self) -> u32 {
+        self.call_line
+    }
+
+    pub fn set_call_line(&mut self, line: u32) {
+        self.call_line = line;
+    }
+
+    /// File of the code running now, which functions it declares belong to.
+    pub fn file(&self) -> Rc<str> {
+        self.file.clone()
+    }
+
+    /// Records a call of `function`, declared in `file`, made from the current file at the
+    /// line of the innermost call.
+    pub fn push_frame(&mut self, function: Rc<str>, file: Rc<str>) {
+        let caller_file = std::mem::replace(&mut self.file, file);
+        self.call_stack.push(StackFrame::new(function, caller_file, self.call_line));
+    }
+
+    /// Sets how deep calls may nest before they fail with a stack overflow error.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Returns from the innermost call to the code that made it.
+    pub fn pop_frame(&mut self) -> StackFrame {
+        let frame = self.call_stack.pop().expect("Every call pushes a frame.");
+        self.file = frame.file_rc();
+        frame
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Allocates an environment tracked by the garbage collector, collecting first if enough
+    /// has been allocated since the last collection.
+    pub fn new_environment(&mut self, enclosing: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        let environment = Rc::new(RefCell::new(Environment::from_enclosing(enclosing)));
+        self.heap.track_environment(&environment);
+        environment
+    }
+
+    /// Allocates an instance tracked by the garbage collector.
+    pub fn new_instance(&mut self, class_: Rc<LoxClass>) -> Rc<RefCell<LoxInstance>> {
+        if self.heap.should_collect() {
+            self.collect_garbage();
         }
+        let instance = Rc::new(RefCell::new(LoxInstance::new(class_)));
+        self.heap.track_instance(&instance);
+        instance
+    }
+
+    /// Allocates a list tracked by the garbage collector.
+    pub fn new_list(&mut self, elements: Vec<Value>) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        let list = Rc::new(RefCell::new(elements));
+        self.heap.track_list(&list);
+        Value::List(list)
+    }
+
+    /// Allocates a map tracked by the garbage collector.
+    pub fn new_map(&mut self, map: LoxMap) -> Value {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        let map = Rc::new(RefCell::new(map));
+        self.heap.track_map(&map);
+        Value::Map(map)
+    }
+
+    /// Frees the objects that are only kept alive by reference cycles.
+    pub fn collect_garbage(&mut self) {
+        let mut roots = vec![
+            GcRef::Environment(Rc::downgrade(&self.builtins)),
+            GcRef::Environment(Rc::downgrade(&self.global)),
+            GcRef::Environment(Rc::downgrade(&self.environment)),
+        ];
+        roots.extend(self.modules.values().map(|module| GcRef::Module(Rc::downgrade(module))));
+        self.heap.collect(&roots);
+    }
+
+    /// Sets the file the main script was loaded from. Imports are resolved relative to it.
+    pub fn set_script_path(&mut self, path: &str) {
+        let path = Path::new(path);
+        self.import_stack = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+        self.file = Rc::from(path.display().to_string());
+    }
+
+    /// Returns the file the main script was loaded from, if it was loaded from a file.
+    pub fn script_path(&self) -> Option<&Path> {
+        self.import_stack.first().map(PathBuf::as_path)
     }
 
     pub fn globals(&self) -> Rc<RefCell<Environment>> {
         Rc::clone(&self.global)
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Box<Stmt>>) {
+    /// Switches to the globals of another module, returning the previous ones.
+    pub fn replace_globals(&mut self, globals: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        std::mem::replace(&mut self.global, globals)
+    }
+
+    /// Runs a script until a statement fails, recording its runtime error in `diagnostics`.
+    pub fn interpret(&mut self, statements: &[Box<Stmt>], diagnostics: &mut Diagnostics) {
         for statement in statements {
-            match self.execute(&statement) {
+            match self.execute(statement) {
                 Ok(_) => {},
                 Err(e) => {
                     match e {
                         LoxRuntime::Error(runtime_error) => {
-                            Lox::runtime_error(&runtime_error);
+                            diagnostics.runtime_error(&runtime_error);
+                        },
+                        LoxRuntime::Throw(throw) => {
+                            diagnostics.runtime_error(&throw.to_error());
                         },
                         LoxRuntime::Return(_) => {
                             // This should never happen at the top level.
                             panic!("Unexpected return statement at top level.");
                         },
+                        LoxRuntime::Break(_) | LoxRuntime::Continue(_) => {
+                            // The resolver rejects loop control outside of a loop.
+                            panic!("Unexpected loop control statement at top level.");
+                        },
                     }
+                    return;
                 }
             }
         }
     }
 
+    /// Runs the statements, stopping at the first error, and returns the value of the last one
+    /// if it is an expression statement.
+    pub fn evaluate_statements(&mut self, statements: &[Box<Stmt>]) -> Result<Value, LoxRuntime> {
+        let mut value = Value::Nil;
+        for statement in statements {
+            value = match statement.as_ref() {
+                Stmt::Expression(expression) => self.evaluate(expression.expression())?,
+                _ => {
+                    self.execute(statement)?;
+                    Value::Nil
+                },
+            };
+        }
+        Ok(value)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.global.borrow().get_here(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.global.borrow_mut().define(name.to_string(), value);
+    }
+
+    /// Calls a global function or class from outside of any script.
+    pub fn call_global(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        let token = Token::new(TokenType::Identifier, name.to_string(), None, 0);
+        let function: Rc<dyn LoxCallable> = match self.global.borrow().get(&token)? {
+            Value::LoxCallable(function) => function,
+            Value::LoxClass(class_) => class_,
+            _ => return Err(LoxRuntime::Error(RuntimeError::new(
+                token,
+                "Can only call functions and classes.".to_string(),
+            ))),
+        };
+
+        if !function.arity().accepts(arguments.len()) {
+            return Err(LoxRuntime::Error(Self::arity_error(token, &function, arguments.len())));
+        }
+        self.check_call_depth(&token)?;
+        self.call_line = 0;
+        function.call(self, arguments)
+    }
+
+    /// Fails with a stack overflow error, which scripts can catch, if another call would nest
+    /// deeper than allowed.
+    fn check_call_depth(&self, token: &Token) -> Result<(), LoxRuntime> {
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(LoxRuntime::Error(RuntimeError::new(token.clone(), "Stack overflow.".to_string())));
+        }
+        Ok(())
+    }
+
+    fn arity_error(token: Token, function: &Rc<dyn LoxCallable>, count: usize) -> RuntimeError {
+        let error = RuntimeError::new(token, format!("Expected {} arguments but got {}.", function.arity(), count));
+        match function.definition() {
+            Some(span) => error.with_note(span, "function defined here"),
+            None => error,
+        }
+    }
+
     fn evaluate(&mut self, expr: &Expr) -> Result<Value, LoxRuntime> {
         expr.accept(self)
     }
@@ -65,17 +335,26 @@ impl Interpreter {
         stmt.accept(self)
     }
 
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.clone(), depth);
+    pub fn resolve(&mut self, id: usize, depth: usize, slot: usize) {
+        self.locals.insert(id, (depth, slot));
+    }
+
+    /// Defines a variable in the current scope. Globals are stored by name, locals in the next slot.
+    fn define(&mut self, name: &Token, value: Value) {
+        if Rc::ptr_eq(&self.environment, &self.global) {
+            self.global.borrow_mut().define(name.lexeme().to_string(), value);
+        } else {
+            self.environment.borrow_mut().define_slot(value);
+        }
     }
 
-    pub fn execute_block(&mut self, statements: &Vec<Box<Stmt>>, environment: Rc<RefCell<Environment>>) -> Result<(), LoxRuntime> {
+    pub fn execute_block(&mut self, statements: &[Box<Stmt>], environment: Rc<RefCell<Environment>>) -> Result<(), LoxRuntime> {
         let previous = Rc::clone(&self.environment);
         self.environment = environment;
         
         let result = (|| {
             for statement in statements {
-                self.execute(&statement)?;
+                self.execute(statement)?;
             }
             Ok(())
         })();
@@ -84,30 +363,83 @@ impl Interpreter {
         result
     }
 
-    fn lookup_variable(&self, name: &Token, expr: &Expr) -> Result<Value, LoxRuntime> {
-        if let Some(distance) = self.locals.get(expr) {
-            self.environment.borrow().get_at(*distance, name.lexeme())
+    fn lookup_variable(&self, name: &Token, id: usize) -> Result<Value, LoxRuntime> {
+        if let Some(&(distance, slot)) = self.locals.get(&id) {
+            Ok(self.environment.borrow().get_at(distance, slot))
         } else {
             self.global.borrow().get(name)
         }
     }
 
-    fn is_truthy(&self, value: &Value) -> bool {
-        match value {
-            Value::Nil => false,
-            Value::Boolean(b) => *b,
-            _ => true,
+    /// Loads the module an import statement refers to, running it the first time it is imported.
+    fn import_module(&mut self, stmt: &Import) -> Result<Rc<LoxModule>, LoxRuntime> {
+        let path = lox_module::module_path(&self.import_stack, stmt.path())?;
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
         }
+        lox_module::check_circular_import(&self.import_stack, &path, stmt.path())?;
+
+        self.import_stack.push(path.clone());
+        let result = self.run_module(&path);
+        self.import_stack.pop();
+
+        let module = result.map_err(|e| lox_module::module_error(stmt.path(), &path, e))?;
+        self.modules.insert(path, module.clone());
+        Ok(module)
     }
 
-    fn is_equal(&self, a: &Value, b: &Value) -> bool {
-        match (a, b) {
-            (Value::Nil, Value::Nil) => true,
-            (Value::Nil, _) => false,
-            (Value::Number(x), Value::Number(y)) => x == y,
-            (Value::Boolean(x), Value::Boolean(y)) => x == y,
-            (Value::String(x), Value::String(y)) => x == y,
-            _ => false,
+    /// Runs a module file with its own globals.
+    fn run_module(&mut self, path: &Path) -> Result<Rc<LoxModule>, RuntimeError> {
+        let statements = lox_module::parse_module(path, self)?;
+
+        let globals = self.new_environment(self.builtins.clone());
+        let previous_environment = std::mem::replace(&mut self.environment, globals.clone());
+        let previous_globals = self.replace_globals(globals.clone());
+        let previous_file = std::mem::replace(&mut self.file, Rc::from(lox_module::display_path(path)));
+
+        let mut result = Ok(());
+        for statement in &statements {
+            result = self.execute(statement);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.environment = previous_environment;
+        self.replace_globals(previous_globals);
+        self.file = previous_file;
+
+        result.map_err(Self::unwind_error)?;
+
+        Ok(Rc::new(LoxModule::new(lox_module::module_name(path), globals)))
+    }
+
+    /// Converts anything that unwinds out of a module's top level into an error.
+    fn unwind_error(unwind: LoxRuntime) -> RuntimeError {
+        match unwind {
+            LoxRuntime::Error(error) => error,
+            LoxRuntime::Throw(throw) => throw.to_error(),
+            LoxRuntime::Return(_) | LoxRuntime::Break(_) | LoxRuntime::Continue(_) => {
+                unreachable!("The resolver rejects return and loop control at the top level.")
+            },
+        }
+    }
+
+    /// Turns a native runtime error into an object that can be caught, with `message` and `line` fields.
+    fn error_value(&mut self, error: &RuntimeError) -> Value {
+        let instance = self.new_instance(self.runtime_error_class.clone());
+        let field = |name: &str| Token::new(TokenType::Identifier, name.to_string(), None, error.token().line());
+        instance.borrow_mut().set(&field("message"), Value::String(error.message().clone()));
+        instance.borrow_mut().set(&field("line"), Value::Number(error.token().line() as f64));
+        Value::LoxInstance(instance)
+    }
+
+    /// Returns true if a `break` or `continue` with the given label applies to the given loop.
+    fn targets_loop(&self, label: &Option<String>, loop_label: &Option<Token>) -> bool {
+        match (label, loop_label) {
+            (None, _) => true,
+            (Some(label), Some(loop_label)) => label == loop_label.lexeme(),
+            (Some(_), None) => false,
         }
     }
 
@@ -126,8 +458,8 @@ impl Interpreter {
 impl expr::Visitor<Value> for Interpreter {
     fn visit_assign_expr(&mut self, expr: &Assign) -> anyhow::Result<Value, LoxRuntime> {
         let value = self.evaluate(expr.value())?;
-        if let Some(distance) = self.locals.get(&expr::Expr::Assign(expr.clone())) {
-            self.environment.borrow_mut().assign_at(*distance, expr.name(), value.clone())?;
+        if let Some(&(distance, slot)) = self.locals.get(expr.id()) {
+            self.environment.borrow_mut().assign_at(distance, slot, value.clone());
         } else {
             self.global.borrow_mut().assign(expr.name(), value.clone())?;
         }
@@ -184,10 +516,10 @@ impl expr::Visitor<Value> for Interpreter {
                 Ok(Value::Boolean(l <= r))
             },
             TokenType::EqualEqual => {
-                Ok(Value::Boolean(self.is_equal(&left, &right)))
+                Ok(Value::Boolean(left.equals(&right)))
             },
             TokenType::BangEqual => {
-                Ok(Value::Boolean(!self.is_equal(&left, &right)))
+                Ok(Value::Boolean(!left.equals(&right)))
             },
             _ => Err(LoxRuntime::Error(RuntimeError::new(
                 binary.operator().clone(),
@@ -204,29 +536,37 @@ impl expr::Visitor<Value> for Interpreter {
             arguments.push(self.evaluate(argument)?);
         }
 
-        match callee {
-            Value::LoxCallable(function) => {
-                if arguments.len() != function.arity() {
-                    return Err(LoxRuntime::Error(RuntimeError::new(
-                        expr.paren().clone(),
-                        format!("Expected {} arguments but got {}.", function.arity(), arguments.len()),
-                    )));
-                }
-                function.call(self, arguments)
-            },
-            _ => Err(LoxRuntime::Error(RuntimeError::new(
+        let function: Rc<dyn LoxCallable> = match callee {
+            Value::LoxCallable(function) => function,
+            Value::LoxClass(class_) => class_,
+            _ => return Err(LoxRuntime::Error(RuntimeError::new(
                 expr.paren().clone(),
                 "Can only call functions and classes.".to_string(),
             ))),
+        };
+
+        if !function.arity().accepts(arguments.len()) {
+            return Err(LoxRuntime::Error(Self::arity_error(expr.paren().clone(), &function, arguments.len())));
         }
+        self.check_call_depth(expr.paren())?;
+        self.call_line = expr.paren().line();
+        function.call(self, arguments)
     }
 
     fn visit_get_expr(&mut self, expr: &Get) -> anyhow::Result<Value, LoxRuntime> {
         let object = self.evaluate(expr.object())?;
         match object {
             Value::LoxInstance(instance) => {
-                instance.borrow().get(expr.name())
+                LoxInstance::get(self, &instance, expr.name())
+            },
+            Value::List(list) => {
+                Ok(Value::LoxCallable(Rc::new(ListMethod::new(list, expr.name())?)))
             },
+            Value::Map(map) => {
+                Ok(Value::LoxCallable(Rc::new(MapMethod::new(map, expr.name())?)))
+            },
+            Value::LoxModule(module) => module.get(expr.name()),
+            Value::NativeInstance(instance) => NativeInstance::get(&instance, expr.name()),
             _ => Err(LoxRuntime::Error(RuntimeError::new(
                 expr.name().clone(),
                 "Only instances have properties.".to_string(),
@@ -238,6 +578,77 @@ impl expr::Visitor<Value> for Interpreter {
         self.evaluate(grouping.expression())
     }
 
+    fn visit_index_expr(&mut self, expr: &Index) -> anyhow::Result<Value, LoxRuntime> {
+        let object = self.evaluate(expr.object())?;
+        let index = self.evaluate(expr.index())?;
+        match object {
+            Value::List(list) => {
+                let list = list.borrow();
+                let position = list_index(expr.bracket(), &index, list.len())?;
+                Ok(list[position].clone())
+            },
+            Value::Map(map) => {
+                let key = MapKey::from_value(expr.bracket(), &index)?;
+                match map.borrow().get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(LoxRuntime::Error(RuntimeError::new(
+                        expr.bracket().clone(),
+                        format!("Undefined key '{}'.", index),
+                    ))),
+                }
+            },
+            _ => Err(LoxRuntime::Error(RuntimeError::new(
+                expr.bracket().clone(),
+                "Only lists and maps can be indexed.".to_string(),
+            ))),
+        }
+    }
+
+    fn visit_index_set_expr(&mut self, expr: &IndexSet) -> anyhow::Result<Value, LoxRuntime> {
+        let object = self.evaluate(expr.object())?;
+        let index = self.evaluate(expr.index())?;
+        let value = self.evaluate(expr.value())?;
+        match object {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                let position = list_index(expr.bracket(), &index, list.len())?;
+                list[position] = value.clone();
+                Ok(value)
+            },
+            Value::Map(map) => {
+                let key = MapKey::from_value(expr.bracket(), &index)?;
+                map.borrow_mut().insert(key, value.clone());
+                Ok(value)
+            },
+            _ => Err(LoxRuntime::Error(RuntimeError::new(
+                expr.bracket().clone(),
+                "Only lists and maps can be indexed.".to_string(),
+            ))),
+        }
+    }
+
+    fn visit_interpolation_expr(&mut self, expr: &Interpolation) -> anyhow::Result<Value, LoxRuntime> {
+        let mut result = String::new();
+        for part in expr.parts() {
+            let value = self.evaluate(part)?;
+            result.push_str(&value.to_string());
+        }
+        Ok(Value::String(result))
+    }
+
+    fn visit_lambda_expr(&mut self, expr: &Lambda) -> anyhow::Result<Value, LoxRuntime> {
+        let function = LoxFunction::new(expr.function().clone(), self.environment.clone(), self.globals(), self.file(), false);
+        Ok(Value::LoxCallable(Rc::new(function)))
+    }
+
+    fn visit_list_expr(&mut self, expr: &List) -> anyhow::Result<Value, LoxRuntime> {
+        let mut elements = Vec::new();
+        for element in expr.elements() {
+            elements.push(self.evaluate(element)?);
+        }
+        Ok(self.new_list(elements))
+    }
+
     fn visit_literal_expr(&mut self, literal: &Literal) -> anyhow::Result<Value, LoxRuntime> {
         match &literal.value() {
             LiteralValue::Number(n) => Ok(Value::Number(*n)),
@@ -251,14 +662,14 @@ impl expr::Visitor<Value> for Interpreter {
         let left = self.evaluate(expr.left())?;
         match expr.operator().token_type() {
             TokenType::Or => {
-                if self.is_truthy(&left) {
+                if left.is_truthy() {
                     Ok(left)
                 } else {
                     self.evaluate(expr.right())
                 }
             },
             TokenType::And => {
-                if !self.is_truthy(&left) {
+                if !left.is_truthy() {
                     Ok(left)
                 } else {
                     self.evaluate(expr.right())
@@ -271,6 +682,17 @@ impl expr::Visitor<Value> for Interpreter {
         }
     }
 
+    fn visit_map_expr(&mut self, expr: &Map) -> anyhow::Result<Value, LoxRuntime> {
+        let mut map = LoxMap::new();
+        for (key, value) in expr.keys().iter().zip(expr.values()) {
+            let key_value = self.evaluate(key)?;
+            let key = MapKey::from_value(expr.brace(), &key_value)?;
+            let value = self.evaluate(value)?;
+            map.insert(key, value);
+        }
+        Ok(self.new_map(map))
+    }
+
     fn visit_set_expr(&mut self, expr: &Set) -> anyhow::Result<Value, LoxRuntime> {
         let object = self.evaluate(expr.object())?;
         match object {
@@ -279,6 +701,11 @@ impl expr::Visitor<Value> for Interpreter {
                 instance.borrow_mut().set(expr.name(), value.clone());
                 Ok(value)
             },
+            Value::NativeInstance(instance) => {
+                let value = self.evaluate(expr.value())?;
+                NativeInstance::set(&instance, expr.name(), value.clone())?;
+                Ok(value)
+            },
             _ => Err(LoxRuntime::Error(RuntimeError::new(
                 expr.name().clone(),
                 "Only instances have fields.".to_string(),
@@ -286,6 +713,59 @@ impl expr::Visitor<Value> for Interpreter {
         }
     }
 
+    fn visit_slice_expr(&mut self, expr: &Slice) -> anyhow::Result<Value, LoxRuntime> {
+        let object = self.evaluate(expr.object())?;
+        let start = match expr.start() {
+            Some(start) => Some(self.evaluate(start)?),
+            None => None,
+        };
+        let end = match expr.end() {
+            Some(end) => Some(self.evaluate(end)?),
+            None => None,
+        };
+
+        match object {
+            Value::List(list) => {
+                let elements = {
+                    let list = list.borrow();
+                    let start = slice_bound(expr.bracket(), start, list.len(), 0)?;
+                    let end = slice_bound(expr.bracket(), end, list.len(), list.len())?;
+                    if start < end { list[start..end].to_vec() } else { Vec::new() }
+                };
+                Ok(self.new_list(elements))
+            },
+            _ => Err(LoxRuntime::Error(RuntimeError::new(
+                expr.bracket().clone(),
+                "Only lists can be sliced.".to_string(),
+            ))),
+        }
+    }
+
+    fn visit_super_expr(&mut self, expr: &Super) -> anyhow::Result<Value, LoxRuntime> {
+        // 'super' and 'this' are the only variables in their scopes, so both live in slot 0.
+        let (distance, _) = *self.locals.get(expr.id()).unwrap();
+        let superclass = match self.environment.borrow().get_at(distance, 0) {
+            Value::LoxClass(class_) => class_,
+            _ => unreachable!("'super' is always bound to a class."),
+        };
+        let object = match self.environment.borrow().get_at(distance - 1, 0) {
+            Value::LoxInstance(instance) => instance,
+            _ => unreachable!("'this' is always bound to an instance."),
+        };
+
+        match superclass.find_method(expr.method().lexeme()) {
+            Some(method) => Ok(Value::LoxCallable(Rc::new(method.bind(self, object)))),
+            None => Err(LoxRuntime::Error(RuntimeError::new(
+                expr.method().clone(),
+                format!("Undefined property '{}'.", expr.method().lexeme()),
+            ))),
+        }
+    }
+
+    fn visit_this_expr(&mut self, expr: &This) -> anyhow::Result<Value, LoxRuntime> {
+        self.lookup_variable(expr.keyword(), *expr.id())
+    }
+
     fn visit_unary_expr(&mut self, unary: &Unary) -> anyhow::Result<Value, LoxRuntime> {
         let right = self.evaluate(unary.right())?;
         match unary.operator().token_type() {
@@ -294,7 +774,7 @@ impl expr::Visitor<Value> for Interpreter {
                 Ok(Value::Number(-n))
             },
             TokenType::Bang => {
-                Ok(Value::Boolean(!self.is_truthy(&right)))
+                Ok(Value::Boolean(!right.is_truthy()))
             },
             _ => Err(LoxRuntime::Error(RuntimeError::new(
                 unary.operator().clone(),
@@ -304,44 +784,88 @@ impl expr::Visitor<Value> for Interpreter {
     }
 
     fn visit_variable_expr(&mut self, expr: &Variable) -> anyhow::Result<Value, LoxRuntime> {
-        self.lookup_variable(expr.name(), &expr::Expr::Variable(expr.clone()))
+        self.lookup_variable(expr.name(), *expr.id())
     }
 }
 
 impl stmt::Visitor<()> for Interpreter {
     fn visit_block_stmt(&mut self, stmt: &Block) -> anyhow::Result<(), LoxRuntime> {
-        let new_environment = Rc::new(RefCell::new(Environment::from_enclosing(self.environment.clone())));
+        let new_environment = self.new_environment(self.environment.clone());
         self.execute_block(stmt.statements(), new_environment)?;
         Ok(())
     }
 
+    fn visit_break_stmt(&mut self, stmt: &Break) -> anyhow::Result<(), LoxRuntime> {
+        Err(LoxRuntime::Break(stmt.label().as_ref().map(|label| label.lexeme().clone())))
+    }
+
     fn visit_class_stmt(&mut self, stmt: &Class) -> anyhow::Result<(), LoxRuntime> {
-        self.environment.borrow_mut().define(stmt.name().lexeme().to_string(), Value::Nil);
-        let class_ = crate::lox_class::LoxClass::new(stmt.name().lexeme().to_string());
-        self.environment.borrow_mut().assign(stmt.name(), Value::LoxClass(Rc::new(class_)))?;
+        let mut superclass = None;
+        if let Some(superclass_expr) = stmt.superclass() {
+            match self.evaluate(superclass_expr)? {
+                Value::LoxClass(class_) => superclass = Some(class_),
+                _ => {
+                    let name = match superclass_expr.as_ref() {
+                        Expr::Variable(variable) => variable.name().clone(),
+                        _ => stmt.name().clone(),
+                    };
+                    return Err(LoxRuntime::Error(RuntimeError::new(
+                        name,
+                        "Superclass must be a class.".to_string(),
+                    )));
+                }
+            }
+        }
+
+        let enclosing = self.environment.clone();
+        if let Some(superclass) = &superclass {
+            self.environment = self.new_environment(enclosing.clone());
+            self.environment.borrow_mut().define_slot(Value::LoxClass(superclass.clone()));
+        }
+
+        let mut methods = HashMap::new();
+        for method in stmt.methods() {
+            let function = LoxFunction::new(
+                Rc::new(method.as_ref().clone()),
+                self.environment.clone(),
+                self.globals(),
+                self.file(),
+                method.name().lexeme() == "init",
+            );
+            methods.insert(method.name().lexeme().to_string(), Rc::new(function));
+        }
+
+        let class_ = LoxClass::new(stmt.name().lexeme().to_string(), superclass, methods);
+        self.environment = enclosing;
+        // Methods look the class up through their closure when called, so it can be defined last.
+        self.define(stmt.name(), Value::LoxClass(Rc::new(class_)));
         Ok(())
     }
 
+    fn visit_continue_stmt(&mut self, stmt: &Continue) -> anyhow::Result<(), LoxRuntime> {
+        Err(LoxRuntime::Continue(stmt.label().as_ref().map(|label| label.lexeme().clone())))
+    }
+
     fn visit_expression_stmt(&mut self, stmt: &Expression) -> anyhow::Result<(), LoxRuntime> {
         self.evaluate(stmt.expression())?;
         Ok(())
     }
 
     fn visit_function_stmt(&mut self, stmt: &Function) -> anyhow::Result<(), LoxRuntime> {
-        let function = crate::lox_function::LoxFunction::new(
-            Box::new(stmt.clone()),
+        let function = LoxFunction::new(
+            Rc::new(stmt.clone()),
             self.environment.clone(),
+            self.globals(),
+            self.file(),
+            false,
         );
-        self.environment.borrow_mut().define(
-            stmt.name().lexeme().to_string(),
-            Value::LoxCallable(Rc::new(function)),
-        );
+        self.define(stmt.name(), Value::LoxCallable(Rc::new(function)));
         Ok(())
     }
 
     fn visit_if_stmt(&mut self, stmt: &If) -> anyhow::Result<(), LoxRuntime> {
         let condition = self.evaluate(stmt.condition())?;
-        if self.is_truthy(&condition) {
+        if condition.is_truthy() {
             self.execute(stmt.then_branch())?;
         } else if let Some(else_branch) = stmt.else_branch() {
             self.execute(else_branch)?;
@@ -349,9 +873,22 @@ impl stmt::Visitor<()> for Interpreter {
         Ok(())
     }
 
+    fn visit_import_stmt(&mut self, stmt: &Import) -> anyhow::Result<(), LoxRuntime> {
+        let module = self.import_module(stmt)?;
+
+        if let Some(alias) = stmt.alias() {
+            self.define(alias, Value::LoxModule(module.clone()));
+        }
+        for name in stmt.names() {
+            let value = module.get(name)?;
+            self.define(name, value);
+        }
+        Ok(())
+    }
+
     fn visit_print_stmt(&mut self, stmt: &Print) -> anyhow::Result<(), LoxRuntime> {
         let value = self.evaluate(stmt.expression())?;
-        println!("{}", value);
+        self.output.borrow_mut().print(&value.to_string());
         Ok(())
     }
 
@@ -364,24 +901,206 @@ impl stmt::Visitor<()> for Interpreter {
         Err(LoxRuntime::Return(RuntimeReturn::new(value)))
     }
 
+    fn visit_throw_stmt(&mut self, stmt: &Throw) -> anyhow::Result<(), LoxRuntime> {
+        let value = self.evaluate(stmt.value())?;
+        Err(LoxRuntime::Throw(RuntimeThrow::new(stmt.keyword().clone(), value)))
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &Try) -> anyhow::Result<(), LoxRuntime> {
+        let environment = self.new_environment(self.environment.clone());
+        let mut result = self.execute_block(stmt.body(), environment);
+
+        if let Some(catch_body) = stmt.catch_body() {
+            let thrown = match &result {
+                Err(LoxRuntime::Throw(throw)) => Some(throw.value().clone()),
+                Err(LoxRuntime::Error(error)) => Some(self.error_value(error)),
+                _ => None,
+            };
+
+            if let Some(value) = thrown {
+                let environment = self.new_environment(self.environment.clone());
+                if stmt.catch_name().is_some() {
+                    environment.borrow_mut().define_slot(value);
+                }
+                result = self.execute_block(catch_body, environment);
+            }
+        }
+
+        // The finally block runs however the try and catch blocks were left, and its own
+        // unwinding (an error, return or break) takes precedence.
+        if let Some(finally_body) = stmt.finally_body() {
+            let environment = self.new_environment(self.environment.clone());
+            self.execute_block(finally_body, environment)?;
+        }
+
+        result
+    }
+
     fn visit_var_stmt(&mut self, stmt: &Var) -> anyhow::Result<(), LoxRuntime> {
         let value = if let Some(initializer) = stmt.initializer() {
             self.evaluate(initializer)?
         } else {
             Value::Nil
         };
-        self.environment.borrow_mut().define(stmt.name().lexeme().to_string(), value);
+        self.define(stmt.name(), value);
         Ok(())
     }
 
     fn visit_while_stmt(&mut self, stmt: &While) -> anyhow::Result<(), LoxRuntime> {
         loop {
             let condition = self.evaluate(stmt.condition())?;
-            if !self.is_truthy(&condition) {
+            if !condition.is_truthy() {
                 break;
             }
-            self.execute(stmt.body())?;
+
+            match self.execute(stmt.body()) {
+                Ok(_) => {},
+                Err(LoxRuntime::Break(label)) if self.targets_loop(&label, stmt.label()) => break,
+                Err(LoxRuntime::Continue(label)) if self.targets_loop(&label, stmt.label()) => {},
+                Err(err) => return Err(err),
+            }
+
+            if let Some(increment) = stmt.increment() {
+                self.evaluate(increment)?;
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{globals_both, run_both};
+
+    #[test]
+    fn break_and_continue_apply_to_the_innermost_loop() {
+        let values = globals_both("
+            var steps = 0;
+            var sum = 0;
+            for (var i = 0; i < 3; i = i + 1) {
+                for (var j = 0; j < 3; j = j + 1) {
+                    steps = steps + 1;
+                    if (j == 1) continue;
+                    if (j == 2) break;
+                    sum = sum + i + j;
+                }
+            }
+        ", &["steps", "sum"]);
+        assert_eq!(values, ["9", "3"]);
+    }
+
+    #[test]
+    fn continue_in_a_for_loop_runs_the_increment() {
+        let values = globals_both("
+            var sum = 0;
+            for (var i = 0; i < 4; i = i + 1) {
+                if (i == 0 or i == 2) continue;
+                sum = sum + i;
+            }
+        ", &["sum"]);
+        assert_eq!(values, ["4"]);
+    }
+
+    #[test]
+    fn labels_apply_break_and_continue_to_outer_loops() {
+        let values = globals_both("
+            var steps = 0;
+            var last = nil;
+            outer: for (var i = 0; i < 3; i = i + 1) {
+                var j = 0;
+                while (true) {
+                    steps = steps + 1;
+                    j = j + 1;
+                    if (i == 1) continue outer;
+                    if (i == 2) break outer;
+                    if (j == 2) break;
+                    last = i * 10 + j;
+                }
+            }
+        ", &["steps", "last"]);
+        assert_eq!(values, ["4", "1"]);
+    }
+
+    #[test]
+    fn thrown_values_are_caught() {
+        let values = globals_both("
+            var log = [];
+            fun risky(x) {
+                if (x > 1) throw \"too big: ${x}\";
+                return x;
+            }
+            try {
+                log.push(risky(1));
+                log.push(risky(2));
+                log.push(\"unreached\");
+            } catch (e) {
+                log.push(\"caught \" + e);
+            }
+        ", &["log"]);
+        assert_eq!(values, ["[1, caught too big: 2]"]);
+    }
+
+    #[test]
+    fn runtime_errors_are_caught_as_objects() {
+        let values = globals_both("var message;\nvar line;\ntry {\n  nil + 1;\n} catch (e) {\n  message = e.message;\n  line = e.line;\n}", &["message", "line"]);
+        assert_eq!(values, ["Operands must be two numbers or two strings.", "4"]);
+    }
+
+    #[test]
+    fn finally_runs_however_the_block_is_left() {
+        let values = globals_both("
+            var log = [];
+            fun f() {
+                try { return \"returned\"; } finally { log.push(\"after return\"); }
+            }
+            log.push(f());
+            for (var i = 0; i < 3; i = i + 1) {
+                try { if (i == 1) break; } finally { log.push(\"after ${i}\"); }
+            }
+            try {
+                try { throw 1; } finally { log.push(\"inner\"); }
+            } catch (e) {
+                log.push(\"outer ${e}\");
+            }
+            try { throw 1; } catch (e) { log.push(\"catch\"); } finally { log.push(\"finally\"); }
+        ", &["log"]);
+        assert_eq!(values, ["[after return, returned, after 0, after 1, inner, outer 1, catch, finally]"]);
+    }
+
+    #[test]
+    fn finally_blocks_rethrow_the_original_error() {
+        let output = run_both("
+            fun inner() { return nil + 1; }
+            fun outer() {
+                try { inner(); } finally { print \"cleanup\"; }
+            }
+            outer();
+        ");
+        assert!(output.starts_with("cleanup\nruntime error: Operands must be two numbers or two strings.\n"), "{}", output);
+        assert!(output.contains("\n2 |             fun inner() { return nil + 1; }\n"), "{}", output);
+        assert!(output.ends_with("  in inner, called from <stdin>:4\n  in outer, called from <stdin>:6\n"), "{}", output);
+
+        let output = run_both("
+            try {
+                try { nil + 1; } finally { print \"inner\"; }
+            } catch (e) {
+                print e.message;
+            }
+        ");
+        assert_eq!(output, "inner\nOperands must be two numbers or two strings.\n");
+    }
+
+    #[test]
+    fn runtime_errors_stop_the_script() {
+        let output = run_both("print \"before\";\nprint nil + 1;\nprint \"after\";");
+        assert!(output.starts_with("before\nruntime error: Operands must be two numbers or two strings.\n"), "{}", output);
+        assert!(!output.contains("after"), "{}", output);
+    }
+
+    #[test]
+    fn uncaught_exceptions_are_reported() {
+        let output = run_both("throw \"oops\";\nprint \"next\";");
+        assert!(output.starts_with("runtime error: Uncaught exception: oops"), "{}", output);
+        assert!(!output.contains("next"), "{}", output);
+    }
+}
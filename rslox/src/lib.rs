@@ -0,0 +1,55 @@
+//! Rust implementation of Lox language from Crafting Interpreters book.
+//!
+//! Besides the `rslox` command line tool, the interpreter can be embedded in a Rust program.
+//! [`Lox`] evaluates source code and returns the resulting [`Value`] or a [`LoxError`], gives
+//! access to global variables and calls Lox functions by name. [`Value`] converts from Rust
+//! numbers, booleans, strings and vectors, and back with `TryFrom`.
+
+mod console_macros;
+mod scanner;
+mod token;
+mod lox;
+mod token_type;
+mod literal;
+mod parser;
+mod expr;
+mod stmt;
+mod interpreter;
+mod value;
+mod parse_error;
+mod resolve_error;
+mod compile_error;
+mod runtime_error;
+mod environment;
+mod lox_callable;
+mod lox_function;
+mod resolver;
+mod lox_class;
+mod lox_instance;
+mod lox_list;
+mod lox_map;
+mod lox_module;
+mod lox_native;
+mod lox_native_class;
+mod chunk;
+mod compiler;
+mod vm;
+mod vm_object;
+mod gc;
+mod output;
+mod diagnostics;
+mod span;
+mod stack_frame;
+#[cfg(test)]
+mod testing;
+
+pub use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, ErrorFormat, Note};
+pub use crate::interpreter::DEFAULT_MAX_CALL_DEPTH;
+pub use crate::lox::{Backend, Lox, LoxError};
+pub use crate::lox_callable::Arity;
+pub use crate::lox_native::{NativeArgs, NativeError};
+pub use crate::lox_native_class::{NativeClass, NativeClassBuilder, NativeInstance};
+pub use crate::output::{BufferOutput, FileOutput, Output, StdoutOutput};
+pub use crate::span::Span;
+pub use crate::stack_frame::StackFrame;
+pub use crate::value::Value;
@@ -1,41 +1,193 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use crate::{cprint, cprintln, scanner};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use crate::{cprint, scanner};
 use anyhow::Result;
 use scanner::Scanner;
+use crate::compiler::Compiler;
+use crate::diagnostics::{Diagnostic, Diagnostics, ErrorFormat};
 use crate::interpreter::Interpreter;
-use crate::parse_error::ParseError;
+use crate::lox_callable::Arity;
+use crate::lox_native::{LoxNative, NativeArgs, NativeError};
+use crate::lox_native_class::NativeClassBuilder;
+use crate::output::Output;
 use crate::parser::Parser;
 use crate::resolver::Resolver;
-use crate::runtime_error::RuntimeError;
+use crate::runtime_error::LoxRuntime;
+use crate::stack_frame::StackFrame;
+use crate::stmt::Stmt;
+use crate::value::Value;
+use crate::vm::Vm;
+
+/// Which implementation runs the scripts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// Tree-walking interpreter
+    Tree,
+    /// Bytecode compiler and virtual machine
+    Vm,
+}
+
+/// An error returned to a host program.
+#[derive(Debug, Clone)]
+pub enum LoxError {
+    /// Errors found before the source ran, while scanning, parsing or resolving it.
+    Compile(Vec<Diagnostic>),
+    /// A runtime error, such as an undefined variable or a wrong operand type, with the calls
+    /// it unwound out of, innermost first.
+    Runtime { line: u32, message: String, traceback: Vec<StackFrame> },
+    /// A value thrown by the script and never caught, with the calls it unwound out of.
+    Uncaught { line: u32, value: Value, traceback: Vec<StackFrame> },
+    /// A value could not be converted to the requested Rust type.
+    Type { expected: &'static str, value: Value },
+}
+
+impl LoxError {
+    fn from_runtime(runtime: LoxRuntime) -> Self {
+        match runtime {
+            LoxRuntime::Error(error) => LoxError::Runtime {
+                line: error.token().line(),
+                message: error.message().clone(),
+                traceback: error.traceback().to_vec(),
+            },
+            LoxRuntime::Throw(throw) => LoxError::Uncaught {
+                line: throw.token().line(),
+                value: throw.value().clone(),
+                traceback: throw.traceback().to_vec(),
+            },
+            LoxRuntime::Return(_) | LoxRuntime::Break(_) | LoxRuntime::Continue(_) => {
+                unreachable!("The resolver rejects return and loop control at the top level.")
+            },
+        }
+    }
+
+    /// Returns the calls a runtime error or uncaught exception unwound out of, innermost first.
+    pub fn traceback(&self) -> &[StackFrame] {
+        match self {
+            LoxError::Runtime { traceback, .. } | LoxError::Uncaught { traceback, .. } => traceback,
+            LoxError::Compile(_) | LoxError::Type { .. } => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxError::Compile(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            },
+            LoxError::Runtime { line, message, .. } => write!(f, "[line {}] {}", line, message),
+            LoxError::Uncaught { line, value, .. } => write!(f, "[line {}] Uncaught exception: {}", line, value),
+            LoxError::Type { expected, value } => write!(f, "Expected {} but got '{}'.", expected, value),
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
 
 pub struct Lox {
     interpreter: Interpreter,
+    backend: Backend,
+    /// Created on first use, so that globals persist across REPL lines.
+    vm: Option<Vm>,
+    disassemble: bool,
+    gc_stats: bool,
+    /// Call depth set by the host. Otherwise, each backend has a default of its own.
+    max_call_depth: Option<usize>,
+    /// Name of the script being run, shown in error reports.
+    source_name: String,
 }
 
-static HAD_ERROR: AtomicBool = AtomicBool::new(false);
-static HAD_RUNTIME_ERROR: AtomicBool = AtomicBool::new(false);
-
 impl Lox {
-    pub fn new() -> Lox {
-        HAD_ERROR.store(false, Ordering::SeqCst);
-        HAD_RUNTIME_ERROR.store(false, Ordering::SeqCst);
+    pub fn new(backend: Backend) -> Lox {
         Lox {
             interpreter: Interpreter::new(),
+            backend,
+            vm: None,
+            disassemble: false,
+            gc_stats: false,
+            max_call_depth: None,
+            source_name: "<stdin>".to_string(),
         }
     }
 
-    pub fn run_file(&mut self, file: &str) -> Result<()> {
-        let contents = std::fs::read_to_string(file)?;
-        self.run(contents.as_str())?;
+    /// Makes the vm backend print the bytecode of each script instead of running it.
+    pub fn set_disassemble(&mut self, disassemble: bool) {
+        self.disassemble = disassemble;
+    }
 
-        if Lox::had_error() {
-            std::process::exit(65);
+    /// Sends the output of `print` statements and all error reports to the given sink instead
+    /// of standard output and standard error.
+    pub fn set_output(&mut self, output: impl Output + 'static) {
+        let output: Rc<RefCell<dyn Output>> = Rc::new(RefCell::new(output));
+        self.interpreter.set_output(output.clone());
+        if let Some(vm) = &mut self.vm {
+            vm.set_output(output);
         }
-        if Lox::had_runtime_error() {
-            std::process::exit(70);
+    }
+
+    /// Sets how errors are written to the output.
+    pub fn set_error_format(&mut self, error_format: ErrorFormat) {
+        self.interpreter.set_error_format(error_format);
+        if let Some(vm) = &mut self.vm {
+            vm.set_error_format(error_format);
         }
-        
-        Ok(())
+    }
+
+    /// Sets how deep calls may nest before they fail with a "Stack overflow." runtime error,
+    /// which scripts can catch. The tree backend defaults to
+    /// [`DEFAULT_MAX_CALL_DEPTH`](crate::DEFAULT_MAX_CALL_DEPTH), and the vm backend, whose
+    /// frames live on the heap, to 100 000.
+    ///
+    /// Calls of the tree backend recurse on the Rust stack of the thread running the script,
+    /// taking up to about 16 KiB per call in debug builds and 3 KiB in release builds. A higher
+    /// limit needs a thread with a stack that large, e.g. 16 MiB for 1000 calls in a debug build,
+    /// or the process aborts when the stack runs out.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = Some(max_call_depth);
+        self.interpreter.set_max_call_depth(max_call_depth);
+        if let Some(vm) = &mut self.vm {
+            vm.set_max_call_depth(max_call_depth);
+        }
+    }
+
+    /// Makes the tree backend print garbage collector statistics when the program ends.
+    pub fn set_gc_stats(&mut self, gc_stats: bool) {
+        self.gc_stats = gc_stats;
+    }
+
+    /// Runs a final collection, so that the live counts only include reachable objects, and
+    /// prints the statistics.
+    fn print_gc_stats(&mut self) {
+        if self.gc_stats && self.backend == Backend::Tree {
+            self.interpreter.collect_garbage();
+            self.interpreter.heap().print_stats(&mut *self.interpreter.output().borrow_mut());
+        }
+    }
+
+    /// Sets the file that the source passed to `run` was read from. Errors name it, and imports
+    /// are resolved relative to it.
+    pub(crate) fn set_script_path(&mut self, file: &str) {
+        self.interpreter.set_script_path(file);
+        if let Some(vm) = &mut self.vm {
+            vm.set_script_path(Path::new(file));
+        }
+        self.source_name = file.to_string();
+    }
+
+    /// Runs a script file and returns the errors found in it, or fails if it cannot be read.
+    pub fn run_file(&mut self, file: &str) -> Result<Diagnostics> {
+        let contents = std::fs::read_to_string(file)?;
+        self.set_script_path(file);
+        let diagnostics = self.run(contents.as_str());
+        self.print_gc_stats();
+        Ok(diagnostics)
     }
 
     pub fn run_prompt(&mut self) -> anyhow::Result<()> {
@@ -55,65 +207,318 @@ impl Lox {
                 break; // EOF reached
             }
 
-            self.run(line.as_str())?;
+            self.run(line.as_str());
         }
 
-        HAD_ERROR.store(false, Ordering::SeqCst);
+        self.print_gc_stats();
 
         Ok(())
     }
 
-    fn run(&mut self, source: &str) -> anyhow::Result<()> {
-        let mut scanner = Scanner::new(source.to_string());
-        let tokens = scanner.scan_tokens();
+    /// Runs source code with the configured backend and returns the value of its last statement
+    /// if that is an expression statement, or nil otherwise. Globals defined by the source stay
+    /// defined for later calls.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let mut diagnostics = Diagnostics::new();
+        let Some(statements) = self.prepare(source, &mut diagnostics) else {
+            return Err(LoxError::Compile(diagnostics.iter().cloned().collect()));
+        };
 
-        let mut parser = Parser::new(tokens);
-        let statements = parser.parse();
+        match self.backend {
+            Backend::Tree => self.interpreter.evaluate_statements(&statements).map_err(LoxError::from_runtime),
+            Backend::Vm => {
+                let function = match Compiler::new().compile_eval(&statements) {
+                    Ok(function) => function,
+                    Err(error) => {
+                        diagnostics.compile_error(&error);
+                        return Err(LoxError::Compile(diagnostics.iter().cloned().collect()));
+                    },
+                };
+                self.vm().evaluate(function).map_err(LoxError::from_runtime)
+            },
+        }
+    }
 
-        if Lox::had_error() {
-            return Ok(());
+    /// Defines a function implemented in Rust, for scripts run by either backend. Calls with
+    /// the wrong number of arguments fail before the function runs; a fixed arity can be given
+    /// as a plain number.
+    pub fn define_native<F>(&mut self, name: &str, arity: impl Into<Arity>, function: F)
+    where
+        F: Fn(&NativeArgs) -> Result<Value, NativeError> + 'static,
+    {
+        let native = LoxNative::new(name, arity.into(), Box::new(function));
+        self.define_builtin(name, Value::LoxCallable(Rc::new(native)));
+    }
+
+    /// Defines a class implemented in Rust, for scripts run by either backend.
+    pub fn define_class<T: 'static>(&mut self, class_: NativeClassBuilder<T>) {
+        let class_ = class_.build();
+        let name = class_.name().clone();
+        self.define_builtin(&name, Value::LoxCallable(Rc::new(class_)));
+    }
+
+    fn define_builtin(&mut self, name: &str, value: Value) {
+        self.interpreter.define_builtin(name, value.clone());
+        if let Some(vm) = &mut self.vm {
+            vm.define_builtin(name, value);
         }
+    }
 
-        let mut resolver = Resolver::new(&mut self.interpreter);
-        resolver.resolve(&statements.clone().unwrap()).unwrap();
+    /// Returns the value of a global variable of the configured backend.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match self.backend {
+            Backend::Tree => self.interpreter.get_global(name),
+            Backend::Vm => self.vm.as_ref().and_then(|vm| vm.get_global(name)),
+        }
+    }
 
-        if Lox::had_error() {
-            return Ok(());
+    /// Defines a global variable of the configured backend, or assigns it if it is already
+    /// defined.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        match self.backend {
+            Backend::Tree => self.interpreter.set_global(name, value),
+            Backend::Vm => self.vm().set_global(name, value),
         }
+    }
+
+    /// Calls the global function or class with the given name on the configured backend.
+    pub fn call(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, LoxError> {
+        let result = match self.backend {
+            Backend::Tree => self.interpreter.call_global(name, arguments),
+            Backend::Vm => self.vm().call_global(name, arguments),
+        };
+        result.map_err(LoxError::from_runtime)
+    }
 
-        self.interpreter.interpret(&statements.clone().unwrap());
+    /// Runs source code with the configured backend. Errors are written to the output as they
+    /// are found, and returned.
+    pub fn run(&mut self, source: &str) -> Diagnostics {
+        let output = self.interpreter.output();
+        let mut diagnostics = Diagnostics::reporting(output, self.interpreter.error_format(), &self.source_name, source);
 
-        Ok(())
+        if let Some(statements) = self.prepare(source, &mut diagnostics) {
+            match self.backend {
+                Backend::Tree => self.interpreter.interpret(&statements, &mut diagnostics),
+                Backend::Vm => self.run_vm(&statements, &mut diagnostics),
+            }
+        }
+        diagnostics
     }
 
-    pub fn error_line(line: u32, message: &str) {
-        Lox::report(line, "", message);
+    /// Scans, parses and resolves source code. Returns None if any of them found errors.
+    #[allow(clippy::vec_box)]
+    fn prepare(&mut self, source: &str, diagnostics: &mut Diagnostics) -> Option<Vec<Box<Stmt>>> {
+        let tokens = Scanner::new(source.to_string(), diagnostics).scan_tokens();
+        let (statements, _) = Parser::new(tokens, diagnostics).parse();
+        if diagnostics.has_errors() {
+            return None;
+        }
+
+        match Resolver::new(&mut self.interpreter).resolve(&statements) {
+            Ok(()) => Some(statements),
+            Err(errors) => {
+                errors.iter().for_each(|error| diagnostics.resolve_error(error));
+                None
+            },
+        }
     }
 
-    pub fn parse_error(parse_error: &ParseError) {
-        if parse_error.token().token_type() == &crate::token_type::TokenType::Eof {
-            Lox::report(parse_error.token().line(), " at end", parse_error.message());
+    fn run_vm(&mut self, statements: &[Box<Stmt>], diagnostics: &mut Diagnostics) {
+        let function = match Compiler::new().compile(statements) {
+            Ok(function) => function,
+            Err(error) => {
+                diagnostics.compile_error(&error);
+                return;
+            },
+        };
+
+        if self.disassemble {
+            function.chunk().disassemble("script", &mut *self.interpreter.output().borrow_mut());
         } else {
-            Lox::report(parse_error.token().line(), &format!(" at '{}'", parse_error.token().lexeme()), parse_error.message());
+            self.vm().interpret(function, diagnostics);
+        }
+    }
+
+    /// Returns the VM, creating it with the current settings on first use.
+    fn vm(&mut self) -> &mut Vm {
+        self.vm.get_or_insert_with(|| {
+            let mut vm = Vm::new(self.interpreter.builtins(), self.interpreter.output());
+            if let Some(max_call_depth) = self.max_call_depth {
+                vm.set_max_call_depth(max_call_depth);
+            }
+            vm.set_error_format(self.interpreter.error_format());
+            if self.interpreter.script_path().is_some() {
+                vm.set_script_path(Path::new(&self.source_name));
+            }
+            vm
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, Lox, LoxError};
+    use crate::{BufferOutput, Value};
+
+    fn lox(backend: Backend) -> (Lox, BufferOutput) {
+        let output = BufferOutput::new();
+        let mut lox = Lox::new(backend);
+        lox.set_output(output.clone());
+        (lox, output)
+    }
+
+    #[test]
+    fn scripts_see_globals_set_by_the_host() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (mut lox, output) = lox(backend);
+            lox.set_global("greeting", Value::from("hello"));
+            lox.run("print greeting; greeting = greeting + \" world\";");
+            assert_eq!(output.take(), "hello\n", "{:?}", backend);
+            assert_eq!(lox.get_global("greeting").unwrap().to_string(), "hello world", "{:?}", backend);
+            assert!(lox.get_global("missing").is_none(), "{:?}", backend);
         }
+    }
+
+    #[test]
+    fn hosts_can_call_script_functions_classes_and_natives() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (mut lox, _) = lox(backend);
+            lox.define_native("double", 1, |args| Ok(Value::from(args.number(0)? * 2.0)));
+            lox.run("
+                fun add(a, b) { return a + b; }
+                class Point { init(x) { this.x = x; } }
+            ");
+            let sum = lox.call("add", vec![Value::from(1.0), Value::from(2.0)]).unwrap();
+            assert_eq!(f64::try_from(sum).unwrap(), 3.0, "{:?}", backend);
+            let point = lox.call("Point", vec![Value::from(4.0)]).unwrap();
+            assert_eq!(point.to_string(), "<instance of Point>", "{:?}", backend);
+            let doubled = lox.call("double", vec![Value::from(5.0)]).unwrap();
+            assert_eq!(f64::try_from(doubled).unwrap(), 10.0, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn errors_in_calls_from_the_host_are_returned() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (mut lox, _) = lox(backend);
+            lox.run("
+                fun fail() { return nil + 1; }
+                fun throws() { throw \"oops\"; }
+            ");
+            let error = lox.call("missing", Vec::new()).unwrap_err();
+            assert_eq!(error.to_string(), "[line 0] Undefined variable 'missing'.", "{:?}", backend);
+            let error = lox.call("fail", Vec::new()).unwrap_err();
+            assert!(matches!(error, LoxError::Runtime { line: 2, .. }), "{:?}: {}", backend, error);
+            let error = lox.call("throws", Vec::new()).unwrap_err();
+            assert!(matches!(error, LoxError::Uncaught { line: 3, .. }), "{:?}: {}", backend, error);
+            let error = lox.call("fail", vec![Value::from(1.0)]).unwrap_err();
+            assert_eq!(error.to_string(), "[line 0] Expected 0 arguments but got 1.", "{:?}", backend);
+            // The failed calls leave nothing behind.
+            let sum = lox.eval("1 + 2;").unwrap();
+            assert_eq!(f64::try_from(sum).unwrap(), 3.0, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn eval_returns_the_last_expression_and_keeps_globals() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (mut lox, output) = lox(backend);
+            assert!(matches!(lox.eval("var x = 20;"), Ok(Value::Nil)), "{:?}", backend);
+            let value = lox.eval("print x; x * 2 + 2;").unwrap();
+            assert_eq!(f64::try_from(value).unwrap(), 42.0, "{:?}", backend);
+            assert_eq!(output.take(), "20\n", "{:?}", backend);
+            assert_eq!(lox.get_global("x").unwrap().to_string(), "20", "{:?}", backend);
+            assert!(matches!(lox.eval("x +;"), Err(LoxError::Compile(_))), "{:?}", backend);
+            assert!(matches!(lox.eval("x();"), Err(LoxError::Runtime { .. })), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn run_file_returns_the_errors_of_the_script() {
+        let path = std::env::temp_dir().join(format!("rslox-run-file-test-{}.lox", std::process::id()));
+        std::fs::write(&path, "print 1;\nprint nil + 1;").unwrap();
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (mut lox, output) = lox(backend);
+            let diagnostics = lox.run_file(&path.display().to_string()).unwrap();
+            assert!(diagnostics.has_runtime_errors() && !diagnostics.has_errors(), "{:?}", backend);
+            assert!(output.take().starts_with("1\nruntime error:"), "{:?}", backend);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(Lox::new(Backend::Tree).run_file(&path.display().to_string()).is_err());
+    }
 
+    #[test]
+    fn too_deep_calls_are_a_catchable_runtime_error() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (mut lox, output) = lox(backend);
+            lox.set_max_call_depth(50);
+            lox.run("
+                var depth = 0;
+                fun f() { depth = depth + 1; f(); }
+                try { f(); } catch (e) { print e.message; }
+                print depth;
+            ");
+            assert_eq!(output.take(), "Stack overflow.\n50\n", "{:?}", backend);
+        }
     }
 
-    pub fn runtime_error(runtime_error: &RuntimeError) {
-        cprintln!(colored::Color::Red, "{}\n[line {}]", runtime_error.message(), runtime_error.token().line());
-        HAD_RUNTIME_ERROR.store(true, Ordering::SeqCst);
+    /// Recurses through methods, `super` and `try` until the call depth runs out.
+    const DEEP_RECURSION: &str = "
+        var depth = 0;
+        class A {
+            f() {
+                depth = depth + 1;
+                try { return [this.f()][0]; } finally { }
+            }
+        }
+        class B < A { f() { return super.f(); } }
+        try {
+            B().f();
+        } catch (e) {
+            print e.message;
+        }
+        print depth;
+    ";
+
+    /// Runs `DEEP_RECURSION` on a new thread and returns the depth it reached, checking that it
+    /// ended with a stack overflow error.
+    fn recursion_depth(backend: Backend, stack_size: Option<usize>, max_call_depth: Option<usize>) -> usize {
+        let mut builder = std::thread::Builder::new();
+        if let Some(stack_size) = stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let runner = builder.spawn(move || {
+            let (mut lox, output) = lox(backend);
+            if let Some(max_call_depth) = max_call_depth {
+                lox.set_max_call_depth(max_call_depth);
+            }
+            lox.run(DEEP_RECURSION);
+            output.take()
+        });
+        let output = runner.unwrap().join().unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines[0], "Stack overflow.", "{:?}: {}", backend, output);
+        // Every level of recursion is two calls.
+        lines[1].parse::<usize>().unwrap() * 2
     }
 
-    fn report(line: u32, what: &str, message: &str) {
-        cprintln!(colored::Color::Red, "[line {}] Error{}: {}", line, what, message);
-        HAD_ERROR.store(true, Ordering::SeqCst);
+    #[test]
+    fn the_default_call_depth_fits_a_default_thread() {
+        let depth = recursion_depth(Backend::Tree, None, None);
+        assert!(depth >= crate::DEFAULT_MAX_CALL_DEPTH - 2, "{}", depth);
     }
 
-    pub fn had_error() -> bool {
-        HAD_ERROR.load(Ordering::SeqCst)
+    #[test]
+    fn the_vm_allows_much_deeper_calls_by_default() {
+        let depth = recursion_depth(Backend::Vm, None, None);
+        assert!(depth >= crate::vm::DEFAULT_MAX_CALL_DEPTH - 2, "{}", depth);
     }
-    
-    pub fn had_runtime_error() -> bool {
-        HAD_RUNTIME_ERROR.load(Ordering::SeqCst)
+
+    #[test]
+    fn a_higher_call_depth_fits_the_documented_stack() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let depth = recursion_depth(backend, Some(16 * 1024 * 1024), Some(1000));
+            assert!(depth >= 998, "{:?}: {}", backend, depth);
+        }
     }
 }
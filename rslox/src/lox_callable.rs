@@ -1,7 +1,49 @@
-﻿use crate::runtime_error::LoxRuntime;
+﻿use crate::gc::GcRef;
+use crate::runtime_error::LoxRuntime;
+use crate::span::Span;
 use crate::value::Value;
 
+/// The number of arguments a callable accepts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Fixed(usize),
+    /// Variadic, with the given minimum.
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Fixed(arity) => count == *arity,
+            Arity::AtLeast(minimum) => count >= *minimum,
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(arity: usize) -> Self {
+        Arity::Fixed(arity)
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Fixed(arity) => write!(f, "{}", arity),
+            Arity::AtLeast(minimum) => write!(f, "at least {}", minimum),
+        }
+    }
+}
+
 pub trait LoxCallable : std::fmt::Display + std::fmt::Debug {
-    fn arity(&self) -> usize;
+    fn arity(&self) -> Arity;
     fn call(&self, interpreter: &mut crate::interpreter::Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime>;
+
+    /// Adds the heap objects this callable holds on to, for the garbage collector.
+    fn trace(&self, _refs: &mut Vec<GcRef>) {}
+
+    /// Where the function is declared, for callables defined by a script.
+    fn definition(&self) -> Option<Span> {
+        None
+    }
 }
\ No newline at end of file
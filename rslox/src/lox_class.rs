@@ -1,36 +1,72 @@
-use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
+use crate::gc::GcRef;
 use crate::interpreter::Interpreter;
-use crate::lox_callable::LoxCallable;
-use crate::lox_instance::LoxInstance;
+use crate::lox_callable::{Arity, LoxCallable};
+use crate::lox_function::LoxFunction;
 use crate::runtime_error::LoxRuntime;
+use crate::span::Span;
 use crate::value::Value;
 
 #[derive(Debug, Clone)]
 pub struct LoxClass {
     name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
 }
 
 impl LoxClass {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, superclass: Option<Rc<LoxClass>>, methods: HashMap<String, Rc<LoxFunction>>) -> Self {
         LoxClass {
             name,
+            superclass,
+            methods,
         }
     }
 
     pub fn name(&self) -> &String {
         &self.name
     }
+
+    /// Looks up a method on this class, walking up the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+
+        match &self.superclass {
+            Some(superclass) => superclass.find_method(name),
+            None => None,
+        }
+    }
 }
 
 impl LoxCallable for LoxClass {
-    fn arity(&self) -> usize {
-        0
+    fn arity(&self) -> Arity {
+        match self.find_method("init") {
+            Some(initializer) => initializer.arity(),
+            None => Arity::Fixed(0),
+        }
+    }
+
+    /// Classes are called through their initializer, so that is where their arity comes from.
+    fn definition(&self) -> Option<Span> {
+        self.find_method("init").and_then(|initializer| initializer.definition())
     }
 
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
-        let instance = LoxInstance::new(Rc::new(self.clone()));
-        Ok(Value::LoxInstance(Rc::new(RefCell::new(instance))))
+        let instance = interpreter.new_instance(Rc::new(self.clone()));
+        if let Some(initializer) = self.find_method("init") {
+            initializer.bind(interpreter, instance.clone()).call(interpreter, arguments)?;
+        }
+        Ok(Value::LoxInstance(instance))
+    }
+
+    fn trace(&self, refs: &mut Vec<GcRef>) {
+        if let Some(superclass) = &self.superclass {
+            refs.push(GcRef::Class(Rc::downgrade(superclass)));
+        }
+        refs.extend(self.methods.values().map(|method| GcRef::Function(Rc::downgrade(method))));
     }
 }
 
@@ -1,39 +1,84 @@
 ﻿use std::cell::RefCell;
 use std::rc::Rc;
 use crate::environment::Environment;
+use crate::gc::GcRef;
+use crate::interpreter::Interpreter;
+use crate::lox_callable::Arity;
+use crate::lox_instance::LoxInstance;
 use crate::runtime_error::LoxRuntime;
+use crate::span::Span;
 use crate::stmt::Function;
 use crate::value::Value;
 
 pub struct LoxFunction {
-    declaration: Box<Function>,
+    declaration: Rc<Function>,
     closure: Rc<RefCell<Environment>>,
+    /// Globals of the module the function was declared in.
+    globals: Rc<RefCell<Environment>>,
+    /// Name and file of the function, shared with the stack frames of its calls.
+    name: Rc<str>,
+    file: Rc<str>,
+    is_initializer: bool,
 }
 
 impl LoxFunction {
-    pub fn new(declaration: Box<Function>, closure: Rc<RefCell<Environment>>) -> Self {
-        LoxFunction { declaration, closure }
+    pub fn new(declaration: Rc<Function>, closure: Rc<RefCell<Environment>>, globals: Rc<RefCell<Environment>>, file: Rc<str>, is_initializer: bool) -> Self {
+        let name = Rc::from(declaration.name().lexeme().as_str());
+        LoxFunction { declaration, closure, globals, name, file, is_initializer }
+    }
+
+    /// Creates a copy of this method whose closure has `this` bound to the given instance.
+    pub fn bind(&self, interpreter: &mut Interpreter, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let environment = interpreter.new_environment(self.closure.clone());
+        environment.borrow_mut().define_slot(Value::LoxInstance(instance));
+        LoxFunction {
+            declaration: self.declaration.clone(),
+            closure: environment,
+            globals: self.globals.clone(),
+            name: self.name.clone(),
+            file: self.file.clone(),
+            is_initializer: self.is_initializer,
+        }
     }
 }
 
 impl crate::lox_callable::LoxCallable for LoxFunction {
-    fn arity(&self) -> usize {
-        self.declaration.params().len()
+    fn arity(&self) -> Arity {
+        Arity::Fixed(self.declaration.params().len())
+    }
+
+    fn definition(&self) -> Option<Span> {
+        Some(*self.declaration.name().span()).filter(|span| span.line() > 0)
     }
 
     fn call(&self, interpreter: &mut crate::interpreter::Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
-        let environment = Rc::new(RefCell::new(Environment::from_enclosing(self.closure.clone())));
+        let environment = interpreter.new_environment(self.closure.clone());
 
-        for (i, param) in self.declaration.params().iter().enumerate() {
-            environment.borrow_mut().define(param.lexeme().clone(), arguments[i].clone());
+        for argument in arguments {
+            environment.borrow_mut().define_slot(argument);
         }
 
-        match interpreter.execute_block(&self.declaration.body(), environment) {
+        let caller_globals = interpreter.replace_globals(self.globals.clone());
+        interpreter.push_frame(self.name.clone(), self.file.clone());
+        let result = interpreter.execute_block(self.declaration.body(), environment);
+        let frame = interpreter.pop_frame();
+        interpreter.replace_globals(caller_globals);
+
+        match result {
+            Ok(_) | Err(LoxRuntime::Return(_)) if self.is_initializer => Ok(self.closure.borrow().get_at(0, 0)),
             Ok(_) => Ok(Value::Nil),
             Err(LoxRuntime::Return(return_value)) => Ok(return_value.value().clone()),
-            Err(err) => Err(err),
+            Err(mut err) => {
+                err.push_frame(frame, &self.file);
+                Err(err)
+            },
         }
     }
+
+    fn trace(&self, refs: &mut Vec<GcRef>) {
+        refs.push(GcRef::Environment(Rc::downgrade(&self.closure)));
+        refs.push(GcRef::Environment(Rc::downgrade(&self.globals)));
+    }
 }
 
 impl std::fmt::Debug for LoxFunction {
@@ -1,5 +1,8 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use crate::gc::GcRef;
+use crate::interpreter::Interpreter;
 use crate::lox_class::LoxClass;
 use crate::runtime_error::{LoxRuntime, RuntimeError};
 use crate::token::Token;
@@ -23,17 +26,34 @@ impl LoxInstance {
         &self.class_
     }
 
-    pub fn get(&self, name: &Token) -> Result<Value, LoxRuntime> {
-        if let Some(value) = self.fields.get(name.lexeme()) {
-            Ok(value.clone())
-        } else {
-            Err(LoxRuntime::Error(RuntimeError::new(name.clone(), format!("Undefined property '{}'.", name.lexeme()))))
+    /// Looks up a property on the instance. Fields shadow methods; methods are bound to the instance.
+    pub fn get(interpreter: &mut Interpreter, instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Value, LoxRuntime> {
+        let this = instance.borrow();
+        if let Some(value) = this.fields.get(name.lexeme()) {
+            return Ok(value.clone());
         }
+
+        if let Some(method) = this.class_().find_method(name.lexeme()) {
+            return Ok(Value::LoxCallable(Rc::new(method.bind(interpreter, instance.clone()))));
+        }
+
+        Err(LoxRuntime::Error(RuntimeError::new(name.clone(), format!("Undefined property '{}'.", name.lexeme()))))
     }
     
     pub fn set(&mut self, name: &Token, value: Value) {
         self.fields.insert(name.lexeme().to_string(), value);
     }
+
+    /// Removes all fields, returning them.
+    pub fn take_fields(&mut self) -> HashMap<String, Value> {
+        std::mem::take(&mut self.fields)
+    }
+
+    /// Adds the class and the field values of this instance.
+    pub fn trace(&self, refs: &mut Vec<GcRef>) {
+        refs.push(GcRef::Class(Rc::downgrade(&self.class_)));
+        self.fields.values().for_each(|value| value.trace(refs));
+    }
 }
 
 impl std::fmt::Display for LoxInstance {
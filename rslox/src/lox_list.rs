@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use crate::gc::GcRef;
+use crate::interpreter::Interpreter;
+use crate::lox_callable::{Arity, LoxCallable};
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::token::Token;
+use crate::value::Value;
+
+/// Converts a Lox index into a position in a list of the given length. Negative indices count from the end.
+pub fn list_index(bracket: &Token, index: &Value, len: usize) -> Result<usize, LoxRuntime> {
+    let offset = integer(bracket, index, "List index must be an integer.")?;
+    let position = if offset < 0 { offset + len as i64 } else { offset };
+
+    if position < 0 || position >= len as i64 {
+        return Err(LoxRuntime::Error(RuntimeError::new(
+            bracket.clone(),
+            format!("List index {} out of range for list of length {}.", index, len),
+        )));
+    }
+    Ok(position as usize)
+}
+
+/// Converts a Lox index into a position to insert at in a list of the given length, which may
+/// be the length itself to append. Like for indexing, negative indices count from the end.
+fn insert_position(token: &Token, index: &Value, len: usize) -> Result<usize, LoxRuntime> {
+    let offset = integer(token, index, "List index must be an integer.")?;
+    let position = if offset < 0 { offset + len as i64 } else { offset };
+
+    if position < 0 || position > len as i64 {
+        return Err(LoxRuntime::Error(RuntimeError::new(
+            token.clone(),
+            format!("List index {} out of range for list of length {}.", index, len),
+        )));
+    }
+    Ok(position as usize)
+}
+
+/// Converts an optional slice bound into a position. Like in Python, bounds are clamped to the list.
+pub fn slice_bound(bracket: &Token, bound: Option<Value>, len: usize, default: usize) -> Result<usize, LoxRuntime> {
+    let bound = match bound {
+        Some(bound) => integer(bracket, &bound, "Slice bounds must be integers.")?,
+        None => return Ok(default),
+    };
+    let position = if bound < 0 { bound + len as i64 } else { bound };
+    Ok(position.clamp(0, len as i64) as usize)
+}
+
+fn integer(token: &Token, value: &Value, message: &str) -> Result<i64, LoxRuntime> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+        _ => Err(LoxRuntime::Error(RuntimeError::new(token.clone(), message.to_string()))),
+    }
+}
+
+/// A built-in list method bound to the list it was read from, e.g. `xs.push`.
+pub struct ListMethod {
+    list: Rc<RefCell<Vec<Value>>>,
+    name: Token,
+}
+
+impl ListMethod {
+    pub fn new(list: Rc<RefCell<Vec<Value>>>, name: &Token) -> Result<Self, LoxRuntime> {
+        match name.lexeme().as_str() {
+            "push" | "pop" | "len" | "insert" => Ok(ListMethod { list, name: name.clone() }),
+            _ => Err(LoxRuntime::Error(RuntimeError::new(
+                name.clone(),
+                format!("Undefined property '{}'.", name.lexeme()),
+            ))),
+        }
+    }
+}
+
+impl LoxCallable for ListMethod {
+    fn arity(&self) -> Arity {
+        Arity::Fixed(match self.name.lexeme().as_str() {
+            "push" => 1,
+            "insert" => 2,
+            _ => 0,
+        })
+    }
+
+    fn call(&self, _interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        let mut list = self.list.borrow_mut();
+        match self.name.lexeme().as_str() {
+            "push" => {
+                list.push(arguments[0].clone());
+                Ok(Value::Nil)
+            },
+            "pop" => list.pop().ok_or_else(|| LoxRuntime::Error(RuntimeError::new(
+                self.name.clone(),
+                "Cannot pop from an empty list.".to_string(),
+            ))),
+            "len" => Ok(Value::Number(list.len() as f64)),
+            "insert" => {
+                let position = insert_position(&self.name, &arguments[0], list.len())?;
+                list.insert(position, arguments[1].clone());
+                Ok(Value::Nil)
+            },
+            _ => unreachable!("List methods are validated in ListMethod::new."),
+        }
+    }
+
+    fn trace(&self, refs: &mut Vec<GcRef>) {
+        refs.push(GcRef::List(Rc::downgrade(&self.list)));
+    }
+}
+
+impl Display for ListMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name.lexeme())
+    }
+}
+
+impl Debug for ListMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name.lexeme())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Backend;
+    use crate::testing::{globals_both, run};
+
+    #[test]
+    fn lists_index_and_slice_from_either_end() {
+        let values = globals_both("
+            var xs = [1, 2, 3, 4];
+            var first = xs[0];
+            var last = xs[-1];
+            var middle = xs[1:3];
+            var init = xs[:-1];
+            var tail = xs[-2:];
+        ", &["first", "last", "middle", "init", "tail"]);
+        assert_eq!(values, ["1", "4", "[2, 3]", "[1, 2, 3]", "[3, 4]"]);
+    }
+
+    #[test]
+    fn insert_counts_negative_indices_from_the_end() {
+        let values = globals_both("
+            var xs = [1, 2, 3];
+            xs.insert(-1, 9);
+            var ys = [1, 2, 3];
+            ys.insert(0, 0);
+            ys.insert(ys.len(), 4);
+            ys.insert(-4, 8);
+        ", &["xs", "ys"]);
+        assert_eq!(values, ["[1, 2, 9, 3]", "[0, 8, 1, 2, 3, 4]"]);
+    }
+
+    #[test]
+    fn insert_rejects_positions_outside_of_the_list() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let output = run(backend, "var xs = [1]; xs.insert(3, 0);");
+            assert!(output.contains("List index 3 out of range for list of length 1."), "{}", output);
+            let output = run(backend, "var xs = [1]; xs.insert(-2, 0);");
+            assert!(output.contains("List index -2 out of range for list of length 1."), "{}", output);
+        }
+    }
+
+    #[test]
+    fn huge_indices_are_reported_as_written() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let output = run(backend, "var xs = [1]; print xs[100000000000000000000];");
+            assert!(output.contains("List index 100000000000000000000 out of range for list of length 1."), "{}", output);
+            let output = run(backend, "var xs = [1]; xs.insert(-100000000000000000000, 0);");
+            assert!(output.contains("List index -100000000000000000000 out of range for list of length 1."), "{}", output);
+        }
+    }
+
+    #[test]
+    fn push_and_pop_work_at_the_end() {
+        let values = globals_both("
+            var xs = [];
+            xs.push(1);
+            xs.push(2);
+            var popped = xs.pop();
+        ", &["popped", "xs"]);
+        assert_eq!(values, ["2", "[1]"]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use crate::gc::GcRef;
+use crate::interpreter::Interpreter;
+use crate::lox_callable::{Arity, LoxCallable};
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::token::Token;
+use crate::value::{fmt_once, Value};
+
+/// The hashable subset of values that can be used as map keys.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum MapKey {
+    Number(u64),
+    String(String),
+    Boolean(bool),
+    Nil,
+}
+
+impl MapKey {
+    pub fn from_value(token: &Token, value: &Value) -> Result<MapKey, LoxRuntime> {
+        match value {
+            // Normalize -0 so that it hashes like 0, which it is equal to.
+            Value::Number(n) if *n == 0.0 => Ok(MapKey::Number(0f64.to_bits())),
+            Value::Number(n) => Ok(MapKey::Number(n.to_bits())),
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            Value::Boolean(b) => Ok(MapKey::Boolean(*b)),
+            Value::Nil => Ok(MapKey::Nil),
+            _ => Err(LoxRuntime::Error(RuntimeError::new(
+                token.clone(),
+                format!("Map keys must be numbers, strings, booleans or nil, got '{}'.", value),
+            ))),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => Value::String(s.clone()),
+            MapKey::Boolean(b) => Value::Boolean(*b),
+            MapKey::Nil => Value::Nil,
+        }
+    }
+}
+
+/// A hash map that remembers the order in which its keys were inserted.
+#[derive(Debug, Clone, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    indices: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        LoxMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.indices.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: Value) {
+        if let Some(&i) = self.indices.get(&key) {
+            self.entries[i].1 = value;
+        } else {
+            self.indices.insert(key.clone(), self.entries.len());
+            self.entries.push((key, value));
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        for i in self.indices.values_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn entries(&self) -> &Vec<(MapKey, Value)> {
+        &self.entries
+    }
+}
+
+impl Display for LoxMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt_once(f, self as *const LoxMap as usize, "{...}", |f| {
+            write!(f, "{{")?;
+            for (i, (key, value)) in self.entries.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", key.to_value(), value)?;
+            }
+            write!(f, "}}")
+        })
+    }
+}
+
+/// A built-in map method bound to the map it was read from, e.g. `m.keys`.
+pub struct MapMethod {
+    map: Rc<RefCell<LoxMap>>,
+    name: Token,
+}
+
+impl MapMethod {
+    pub fn new(map: Rc<RefCell<LoxMap>>, name: &Token) -> Result<Self, LoxRuntime> {
+        match name.lexeme().as_str() {
+            "keys" | "values" | "has" | "remove" | "len" => Ok(MapMethod { map, name: name.clone() }),
+            _ => Err(LoxRuntime::Error(RuntimeError::new(
+                name.clone(),
+                format!("Undefined property '{}'.", name.lexeme()),
+            ))),
+        }
+    }
+}
+
+impl LoxCallable for MapMethod {
+    fn arity(&self) -> Arity {
+        Arity::Fixed(match self.name.lexeme().as_str() {
+            "has" | "remove" => 1,
+            _ => 0,
+        })
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        // The lists are allocated once the map is no longer borrowed, so that a collection
+        // they trigger can trace the map.
+        match self.name.lexeme().as_str() {
+            "keys" => {
+                let keys = self.map.borrow().entries().iter().map(|(key, _)| key.to_value()).collect();
+                Ok(interpreter.new_list(keys))
+            },
+            "values" => {
+                let values = self.map.borrow().entries().iter().map(|(_, value)| value.clone()).collect();
+                Ok(interpreter.new_list(values))
+            },
+            "has" => {
+                let key = MapKey::from_value(&self.name, &arguments[0])?;
+                Ok(Value::Boolean(self.map.borrow().contains_key(&key)))
+            },
+            "remove" => {
+                let key = MapKey::from_value(&self.name, &arguments[0])?;
+                Ok(self.map.borrow_mut().remove(&key).unwrap_or(Value::Nil))
+            },
+            "len" => Ok(Value::Number(self.map.borrow().len() as f64)),
+            _ => unreachable!("Map methods are validated in MapMethod::new."),
+        }
+    }
+
+    fn trace(&self, refs: &mut Vec<GcRef>) {
+        refs.push(GcRef::Map(Rc::downgrade(&self.map)));
+    }
+}
+
+impl Display for MapMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name.lexeme())
+    }
+}
+
+impl Debug for MapMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name.lexeme())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Backend;
+    use crate::testing::{globals_both, run};
+
+    #[test]
+    fn maps_keep_insertion_order() {
+        let values = globals_both("
+            var m = {\"b\": 1, \"a\": 2};
+            m[\"c\"] = 3;
+            m[\"b\"] = 4;
+            var keys = m.keys();
+            var values = m.values();
+            var removed = m.remove(\"b\");
+            var has = m.has(\"b\");
+            var len = m.len();
+        ", &["keys", "values", "removed", "m", "has", "len"]);
+        assert_eq!(values, ["[b, a, c]", "[4, 2, 3]", "4", "{a: 2, c: 3}", "false", "2"]);
+    }
+
+    #[test]
+    fn maps_containing_themselves_are_printed_once() {
+        let values = globals_both("
+            var m = {};
+            m[\"m\"] = m;
+            var interpolated = \"${m}\";
+        ", &["m", "interpolated"]);
+        assert_eq!(values, ["{m: {...}}", "{m: {...}}"]);
+    }
+
+    #[test]
+    fn lists_and_maps_containing_each_other_are_printed_once() {
+        let values = globals_both("
+            var m = {};
+            var xs = [m];
+            m[\"xs\"] = xs;
+        ", &["m", "xs"]);
+        assert_eq!(values, ["{xs: [{...}]}", "[{xs: [...]}]"]);
+    }
+
+    #[test]
+    fn keys_must_be_numbers_strings_booleans_or_nil() {
+        for key in ["[1]", "{}", "Foo()", "Foo", "f"] {
+            for backend in [Backend::Tree, Backend::Vm] {
+                let output = run(backend, &format!("class Foo {{}} fun f() {{}} var m = {{}}; m[{}] = 1;", key));
+                assert!(output.contains("Map keys must be numbers, strings, booleans or nil, got '"), "{}", output);
+            }
+        }
+    }
+}
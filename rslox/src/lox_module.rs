@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::diagnostics::Diagnostics;
+use crate::environment::Environment;
+use crate::gc::GcRef;
+use crate::interpreter::Interpreter;
+use crate::literal::LiteralValue;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::token::{Token, TokenType};
+use crate::value::Value;
+
+/// A loaded source file. Its members are the globals the file defined when it ran.
+pub struct LoxModule {
+    name: String,
+    globals: Rc<RefCell<Environment>>,
+}
+
+impl LoxModule {
+    pub fn new(name: String, globals: Rc<RefCell<Environment>>) -> Self {
+        LoxModule {
+            name,
+            globals,
+        }
+    }
+
+    pub fn trace(&self, refs: &mut Vec<GcRef>) {
+        refs.push(GcRef::Environment(Rc::downgrade(&self.globals)));
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, LoxRuntime> {
+        match self.globals.borrow().get_here(name.lexeme()) {
+            Some(value) => Ok(value),
+            None => Err(LoxRuntime::Error(RuntimeError::new(
+                name.clone(),
+                format!("Module '{}' has no member '{}'.", self.name, name.lexeme()),
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for LoxModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+// Module loading shared by both backends. Each keeps its own cache of loaded modules and a
+// stack of the files being loaded, from the main script to the innermost import.
+
+/// Finds the file an import refers to, relative to the directory of the file doing the import.
+pub fn module_path(import_stack: &[PathBuf], path: &Token) -> Result<PathBuf, LoxRuntime> {
+    let relative_path = match path.literal() {
+        Some(LiteralValue::String(path)) => path.clone(),
+        _ => unreachable!("Import paths are string literals."),
+    };
+
+    let base = match import_stack.last().and_then(|path| path.parent()) {
+        Some(directory) => directory.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    base.join(&relative_path).canonicalize().map_err(|e| LoxRuntime::Error(RuntimeError::new(
+        path.clone(),
+        format!("Cannot open module '{}': {}.", relative_path, e),
+    )))
+}
+
+/// Fails if the module is still being loaded, which means that it imports itself.
+pub fn check_circular_import(import_stack: &[PathBuf], path: &Path, token: &Token) -> Result<(), LoxRuntime> {
+    if !import_stack.iter().any(|loading| loading == path) {
+        return Ok(());
+    }
+    let chain: Vec<String> = import_stack.iter().map(PathBuf::as_path).chain([path]).map(display_path).collect();
+    Err(LoxRuntime::Error(RuntimeError::new(
+        token.clone(),
+        format!("Circular import: {}.", chain.join(" -> ")),
+    )))
+}
+
+/// Reads, parses and resolves a module file. Its errors are written to the output of `host`.
+#[allow(clippy::vec_box)]
+pub fn parse_module(path: &Path, host: &mut Interpreter) -> Result<Vec<Box<Stmt>>, RuntimeError> {
+    let file_token = Token::new(TokenType::String, display_path(path), None, 0);
+    let source = std::fs::read_to_string(path).map_err(|e| RuntimeError::new(
+        file_token.clone(),
+        format!("Cannot read module: {}.", e),
+    ))?;
+
+    let mut diagnostics = Diagnostics::reporting(host.output(), host.error_format(), file_token.lexeme(), &source);
+    let tokens = Scanner::new(source, &mut diagnostics).scan_tokens();
+    let (statements, _) = Parser::new(tokens, &mut diagnostics).parse();
+    if diagnostics.has_errors() {
+        return Err(static_error(path, &diagnostics, "Module has syntax errors."));
+    }
+
+    if let Err(errors) = Resolver::new(host).resolve(&statements) {
+        errors.iter().for_each(|error| diagnostics.resolve_error(error));
+        return Err(static_error(path, &diagnostics, "Module has errors."));
+    }
+    Ok(statements)
+}
+
+/// Makes the error of a module that did not parse or resolve, at the line of its first error.
+fn static_error(path: &Path, diagnostics: &Diagnostics, message: &str) -> RuntimeError {
+    let line = diagnostics.iter().next().map_or(0, |diagnostic| diagnostic.line());
+    RuntimeError::new(Token::new(TokenType::String, display_path(path), None, line), message.to_string())
+}
+
+/// Reports an error that stopped a module from loading at the import of the module. Errors
+/// about the module as a whole, such as an unreadable file, have no line.
+pub fn module_error(import: &Token, path: &Path, error: RuntimeError) -> LoxRuntime {
+    let location = match error.token().line() {
+        0 => String::new(),
+        line => format!(" at line {}", line),
+    };
+    LoxRuntime::Error(RuntimeError::new(
+        import.clone(),
+        format!("Error in module '{}'{}: {}", display_path(path), location, error.message()),
+    ).with_traceback(error.traceback()))
+}
+
+/// Returns the name of the module loaded from a file, which is the file name without extension.
+pub fn module_name(path: &Path) -> String {
+    path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// Shows a path relative to the working directory if it is inside it.
+pub fn display_path(path: &Path) -> String {
+    let relative = std::env::current_dir().ok().and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf));
+    relative.unwrap_or_else(|| path.to_path_buf()).display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Backend;
+    use crate::testing::{file_globals, run_files};
+
+    #[test]
+    fn modules_are_imported_by_alias_or_by_name() {
+        let values = file_globals(&[
+            ("main.lox", "
+                import \"lib/math.lox\" as math;
+                from \"lib/math.lox\" import square, pi;
+                var nine = math.square(3);
+                var sixteen = square(4);
+            "),
+            ("lib/math.lox", "
+                var pi = 3;
+                fun square(x) { return x * x; }
+            "),
+        ], &["math", "nine", "sixteen", "pi"]);
+        assert_eq!(values, ["<module math>", "9", "16", "3"]);
+    }
+
+    #[test]
+    fn modules_can_be_imported_in_local_scopes() {
+        let values = file_globals(&[
+            ("main.lox", "
+                fun area(r) {
+                    from \"lib.lox\" import pi;
+                    import \"lib.lox\" as lib;
+                    return lib.square(r) * pi;
+                }
+                var small = area(2);
+                var large = area(3);
+            "),
+            ("lib.lox", "var pi = 3; fun square(x) { return x * x; }"),
+        ], &["small", "large"]);
+        assert_eq!(values, ["12", "27"]);
+    }
+
+    #[test]
+    fn imports_are_resolved_relative_to_the_importing_file() {
+        let values = file_globals(&[
+            ("main.lox", "from \"lib/a.lox\" import a;"),
+            ("lib/a.lox", "from \"b.lox\" import b; var a = \"a\" + b;"),
+            ("lib/b.lox", "var b = \"b\";"),
+        ], &["a"]);
+        assert_eq!(values, ["ab"]);
+    }
+
+    #[test]
+    fn modules_have_their_own_globals() {
+        let values = file_globals(&[
+            ("main.lox", "
+                var name = \"main\";
+                import \"other.lox\" as other;
+                var other_name = other.name();
+                var same_clock = other.clock == clock;
+            "),
+            ("other.lox", "
+                var name = \"other\";
+                fun name() { return \"other\"; }
+                var clock = clock;
+            "),
+        ], &["name", "other_name", "same_clock"]);
+        assert_eq!(values, ["main", "other", "true"]);
+    }
+
+    #[test]
+    fn missing_members_and_modules_are_errors() {
+        let values = file_globals(&[
+            ("main.lox", "
+                var member;
+                var module;
+                import \"lib.lox\" as lib;
+                try { lib.missing; } catch (e) { member = e.message; }
+                try { import \"missing.lox\" as missing; } catch (e) { module = e.message; }
+            "),
+            ("lib.lox", "var x = 1;"),
+        ], &["member", "module"]);
+        assert_eq!(values[0], "Module 'lib' has no member 'missing'.");
+        assert!(values[1].starts_with("Cannot open module 'missing.lox'"), "{}", values[1]);
+    }
+
+    #[test]
+    fn circular_imports_are_errors() {
+        let values = file_globals(&[
+            ("main.lox", "var error; try { import \"a.lox\" as a; } catch (e) { error = e.message; }"),
+            ("a.lox", "import \"b.lox\" as b;"),
+            ("b.lox", "import \"a.lox\" as a;"),
+        ], &["error"]);
+        assert!(values[0].contains("Circular import:"), "{}", values[0]);
+        assert!(values[0].contains("a.lox -> "), "{}", values[0]);
+    }
+
+    #[test]
+    fn errors_in_modules_name_the_module() {
+        let values = file_globals(&[
+            ("main.lox", "var error; try { import \"broken.lox\" as broken; } catch (e) { error = e.message; }"),
+            ("broken.lox", "var x = 1;\nprint x + nil;"),
+        ], &["error"]);
+        assert!(values[0].starts_with("Error in module"), "{}", values[0]);
+        assert!(values[0].ends_with("broken.lox' at line 2: Operands must be two numbers or two strings."), "{}", values[0]);
+    }
+
+    #[test]
+    fn errors_in_modules_can_be_caught() {
+        let values = file_globals(&[
+            ("main.lox", "
+                var caught = false;
+                try {
+                    import \"broken.lox\" as broken;
+                } catch (e) {
+                    caught = true;
+                }
+            "),
+            ("broken.lox", "throw \"oops\";"),
+        ], &["caught"]);
+        assert_eq!(values, ["true"]);
+    }
+
+    #[test]
+    fn modules_are_equal_only_to_themselves() {
+        let values = file_globals(&[
+            ("main.lox", "
+                import \"a.lox\" as a;
+                import \"b.lox\" as b;
+                var same = a == a;
+                var different = a == b;
+            "),
+            ("a.lox", "var x = 1;"),
+            ("b.lox", "var x = 1;"),
+        ], &["same", "different"]);
+        assert_eq!(values, ["true", "false"]);
+    }
+
+    #[test]
+    fn syntax_errors_in_modules_name_their_line() {
+        let values = file_globals(&[
+            ("main.lox", "var error; try { import \"broken.lox\" as broken; } catch (e) { error = e.message; }"),
+            ("broken.lox", "var x = 1;\nprint 1 +;"),
+        ], &["error"]);
+        assert!(values[0].starts_with("Error in module"), "{}", values[0]);
+        assert!(values[0].ends_with("broken.lox' at line 2: Module has syntax errors."), "{}", values[0]);
+    }
+
+    #[test]
+    fn module_errors_point_at_the_import_path() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let output = run_files(backend, &[("main.lox", "var x = 1;\nimport \"missing.lox\" as missing;")]);
+            assert!(output.contains("/main.lox:2:8\n"), "{:?}: {}", backend, output);
+            assert!(output.ends_with("2 | import \"missing.lox\" as missing;\n  |        ^^^^^^^^^^^^^\n"), "{:?}: {}", backend, output);
+        }
+    }
+}
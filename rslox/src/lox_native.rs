@@ -0,0 +1,227 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+use crate::interpreter::Interpreter;
+use crate::lox::LoxError;
+use crate::lox_callable::{Arity, LoxCallable};
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::token::{Token, TokenType};
+use crate::value::Value;
+
+/// The signature of a native function implemented by a Rust closure.
+pub type NativeFn = dyn Fn(&NativeArgs) -> Result<Value, NativeError>;
+
+/// A function implemented in Rust, registered with `define_native`.
+pub struct LoxNative {
+    name: String,
+    arity: Arity,
+    function: Box<NativeFn>,
+}
+
+impl LoxNative {
+    pub fn new(name: &str, arity: Arity, function: Box<NativeFn>) -> Self {
+        LoxNative {
+            name: name.to_string(),
+            arity,
+            function,
+        }
+    }
+}
+
+impl LoxCallable for LoxNative {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        let arguments = NativeArgs::new(&self.name, arguments);
+        (self.function)(&arguments).map_err(|error| error.into_runtime(&self.name, interpreter.call_line()))
+    }
+}
+
+impl Display for LoxNative {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Debug for LoxNative {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// The arguments a native function was called with. The typed accessors fail with an error
+/// naming the argument and the function when the argument has a different type.
+pub struct NativeArgs<'a> {
+    name: &'a str,
+    values: Vec<Value>,
+}
+
+impl<'a> NativeArgs<'a> {
+    pub(crate) fn new(name: &'a str, values: Vec<Value>) -> Self {
+        NativeArgs {
+            name,
+            values,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the argument at the given index. The arity of the function guarantees that
+    /// indices below its minimum argument count exist.
+    pub fn get(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn number(&self, index: usize) -> Result<f64, NativeError> {
+        match self.get(index) {
+            Value::Number(n) => Ok(*n),
+            _ => Err(self.type_error(index, "a number")),
+        }
+    }
+
+    pub fn string(&self, index: usize) -> Result<&str, NativeError> {
+        match self.get(index) {
+            Value::String(s) => Ok(s),
+            _ => Err(self.type_error(index, "a string")),
+        }
+    }
+
+    pub fn boolean(&self, index: usize) -> Result<bool, NativeError> {
+        match self.get(index) {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(self.type_error(index, "a boolean")),
+        }
+    }
+
+    pub fn list(&self, index: usize) -> Result<Rc<RefCell<Vec<Value>>>, NativeError> {
+        match self.get(index) {
+            Value::List(list) => Ok(list.clone()),
+            _ => Err(self.type_error(index, "a list")),
+        }
+    }
+
+    fn type_error(&self, index: usize, expected: &str) -> NativeError {
+        NativeError::new(format!("Argument {} of '{}' must be {}.", index + 1, self.name, expected))
+    }
+}
+
+/// An error raised by a native function. It becomes a runtime error at the line of the call.
+#[derive(Debug, Clone)]
+pub struct NativeError {
+    message: String,
+}
+
+impl NativeError {
+    pub fn new(message: String) -> Self {
+        NativeError {
+            message,
+        }
+    }
+
+    /// Turns the error into a runtime error raised by the named native at the given line.
+    pub(crate) fn into_runtime(self, name: &str, line: u32) -> LoxRuntime {
+        let token = Token::new(TokenType::Identifier, name.to_string(), None, line);
+        LoxRuntime::Error(RuntimeError::new(token, self.message))
+    }
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        NativeError::new(message)
+    }
+}
+
+impl From<&str> for NativeError {
+    fn from(message: &str) -> Self {
+        NativeError::new(message.to_string())
+    }
+}
+
+impl From<LoxError> for NativeError {
+    fn from(error: LoxError) -> Self {
+        NativeError::new(error.to_string())
+    }
+}
+
+impl Display for NativeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for NativeError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::lox_callable::Arity;
+    use crate::testing::globals_both_with;
+    use crate::value::Value;
+    use crate::Lox;
+
+    fn with_natives(lox: &mut Lox) {
+        lox.define_native("sqrt", 1, |args| Ok(Value::from(args.number(0)?.sqrt())));
+        lox.define_native("shout", 1, |args| Ok(Value::from(args.string(0)?.to_uppercase())));
+        lox.define_native("sum", Arity::AtLeast(1), |args| {
+            let numbers = (0..args.len()).map(|i| args.number(i)).collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::from(numbers.iter().sum::<f64>()))
+        });
+        lox.define_native("fail", 0, |_| Err("Something went wrong.".into()));
+    }
+
+    /// Runs the call on both backends and returns the message of the error it fails with.
+    fn error(call: &str) -> String {
+        let source = format!("var error; try {{ {} }} catch (e) {{ error = e.message; }}", call);
+        globals_both_with(with_natives, &source, &["error"]).remove(0)
+    }
+
+    #[test]
+    fn natives_are_called_with_typed_arguments() {
+        let values = globals_both_with(with_natives, "
+            var root = sqrt(16);
+            var loud = shout(\"hi\");
+            var native = sqrt;
+        ", &["root", "loud", "native"]);
+        assert_eq!(values, ["4", "HI", "<native fn sqrt>"]);
+    }
+
+    #[test]
+    fn variadic_natives_take_any_number_of_arguments_above_their_minimum() {
+        let values = globals_both_with(with_natives, "var one = sum(1); var ten = sum(1, 2, 3, 4);", &["one", "ten"]);
+        assert_eq!(values, ["1", "10"]);
+        assert_eq!(error("sum();"), "Expected at least 1 arguments but got 0.");
+    }
+
+    #[test]
+    fn wrong_arguments_are_runtime_errors() {
+        assert_eq!(error("sqrt(1, 2);"), "Expected 1 arguments but got 2.");
+        assert_eq!(error("sqrt(\"four\");"), "Argument 1 of 'sqrt' must be a number.");
+        assert_eq!(error("sum(1, \"2\");"), "Argument 2 of 'sum' must be a number.");
+    }
+
+    #[test]
+    fn native_errors_can_be_caught() {
+        let values = globals_both_with(with_natives, "
+            var message;
+            var line;
+            try {
+                fail();
+            } catch (error) {
+                message = error.message;
+                line = error.line;
+            }
+        ", &["message", "line"]);
+        assert_eq!(values, ["Something went wrong.", "5"]);
+    }
+}
@@ -0,0 +1,345 @@
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use crate::interpreter::Interpreter;
+use crate::lox_callable::{Arity, LoxCallable};
+use crate::lox_native::{NativeArgs, NativeError};
+use crate::runtime_error::{LoxRuntime, RuntimeError};
+use crate::token::Token;
+use crate::value::Value;
+
+type ConstructorFn = dyn Fn(&NativeArgs) -> Result<Box<dyn Any>, NativeError>;
+type MethodFn = dyn Fn(&mut dyn Any, &NativeArgs) -> Result<Value, NativeError>;
+type GetterFn = dyn Fn(&dyn Any) -> Value;
+type SetterFn = dyn Fn(&mut dyn Any, Value) -> Result<(), NativeError>;
+
+/// A class implemented in Rust. Its instances wrap a value of a Rust type, which its methods,
+/// getters and setters work on. Calling the class runs its constructor.
+pub struct NativeClass {
+    definition: Rc<ClassDefinition>,
+}
+
+/// The members of a native class, shared by the class and its instances.
+struct ClassDefinition {
+    name: String,
+    constructor: Option<(Arity, Box<ConstructorFn>)>,
+    methods: HashMap<String, (Arity, Rc<MethodFn>)>,
+    getters: HashMap<String, Box<GetterFn>>,
+    setters: HashMap<String, Box<SetterFn>>,
+}
+
+impl NativeClass {
+    pub fn name(&self) -> &String {
+        &self.definition.name
+    }
+}
+
+impl LoxCallable for NativeClass {
+    fn arity(&self) -> Arity {
+        match &self.definition.constructor {
+            Some((arity, _)) => *arity,
+            None => Arity::Fixed(0),
+        }
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        let name = self.name();
+        let Some((_, constructor)) = &self.definition.constructor else {
+            return Err(NativeError::from(format!("Native class '{}' has no constructor.", name))
+                .into_runtime(name, interpreter.call_line()));
+        };
+        let data = constructor(&NativeArgs::new(name, arguments))
+            .map_err(|error| error.into_runtime(name, interpreter.call_line()))?;
+        Ok(Value::NativeInstance(Rc::new(NativeInstance {
+            class_: self.definition.clone(),
+            data: RefCell::new(data),
+        })))
+    }
+}
+
+impl Display for NativeClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native class {}>", self.name())
+    }
+}
+
+impl Debug for NativeClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native class {}>", self.name())
+    }
+}
+
+/// Builds a native class whose instances wrap a `T`.
+pub struct NativeClassBuilder<T> {
+    definition: ClassDefinition,
+    data: PhantomData<T>,
+}
+
+impl<T: 'static> NativeClassBuilder<T> {
+    pub fn new(name: &str) -> Self {
+        NativeClassBuilder {
+            definition: ClassDefinition {
+                name: name.to_string(),
+                constructor: None,
+                methods: HashMap::new(),
+                getters: HashMap::new(),
+                setters: HashMap::new(),
+            },
+            data: PhantomData,
+        }
+    }
+
+    /// Sets the function that creates the wrapped value when the class is called.
+    pub fn constructor<F>(mut self, arity: impl Into<Arity>, constructor: F) -> Self
+    where
+        F: Fn(&NativeArgs) -> Result<T, NativeError> + 'static,
+    {
+        let constructor = move |arguments: &NativeArgs| -> Result<Box<dyn Any>, NativeError> {
+            Ok(Box::new(constructor(arguments)?))
+        };
+        self.definition.constructor = Some((arity.into(), Box::new(constructor)));
+        self
+    }
+
+    pub fn method<F>(mut self, name: &str, arity: impl Into<Arity>, method: F) -> Self
+    where
+        F: Fn(&mut T, &NativeArgs) -> Result<Value, NativeError> + 'static,
+    {
+        let method = move |data: &mut dyn Any, arguments: &NativeArgs| method(Self::downcast_mut(data), arguments);
+        self.definition.methods.insert(name.to_string(), (arity.into(), Rc::new(method)));
+        self
+    }
+
+    /// Adds a property that is computed from the wrapped value when it is read.
+    pub fn getter<F>(mut self, name: &str, getter: F) -> Self
+    where
+        F: Fn(&T) -> Value + 'static,
+    {
+        let getter = move |data: &dyn Any| getter(data.downcast_ref::<T>().expect("Native instances wrap the type of their class."));
+        self.definition.getters.insert(name.to_string(), Box::new(getter));
+        self
+    }
+
+    /// Adds a property that updates the wrapped value when it is assigned.
+    pub fn setter<F>(mut self, name: &str, setter: F) -> Self
+    where
+        F: Fn(&mut T, Value) -> Result<(), NativeError> + 'static,
+    {
+        let setter = move |data: &mut dyn Any, value: Value| setter(Self::downcast_mut(data), value);
+        self.definition.setters.insert(name.to_string(), Box::new(setter));
+        self
+    }
+
+    pub fn build(self) -> NativeClass {
+        NativeClass {
+            definition: Rc::new(self.definition),
+        }
+    }
+
+    fn downcast_mut(data: &mut dyn Any) -> &mut T {
+        data.downcast_mut::<T>().expect("Native instances wrap the type of their class.")
+    }
+}
+
+/// An instance of a native class.
+pub struct NativeInstance {
+    class_: Rc<ClassDefinition>,
+    data: RefCell<Box<dyn Any>>,
+}
+
+impl NativeInstance {
+    pub fn class_name(&self) -> &String {
+        &self.class_.name
+    }
+
+    /// Returns the wrapped value, or None if it is not a `T` or one of the instance's own methods
+    /// or setters is running, e.g. when an instance is passed to its own method.
+    pub fn data<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.data.try_borrow().ok()?, |data| data.downcast_ref::<T>()).ok()
+    }
+
+    /// Borrows the wrapped value for a getter, or fails if it is being updated.
+    fn borrow_data(&self, name: &str, line: u32) -> Result<Ref<'_, Box<dyn Any>>, LoxRuntime> {
+        self.data.try_borrow().map_err(|_| self.in_use_error(name, line))
+    }
+
+    /// Borrows the wrapped value for a method or setter, or fails if it is already borrowed, e.g.
+    /// when the host holds on to `data` while calling into the script.
+    fn borrow_data_mut(&self, name: &str, line: u32) -> Result<RefMut<'_, Box<dyn Any>>, LoxRuntime> {
+        self.data.try_borrow_mut().map_err(|_| self.in_use_error(name, line))
+    }
+
+    fn in_use_error(&self, name: &str, line: u32) -> LoxRuntime {
+        NativeError::from(format!("The instance of {} is already in use.", self.class_.name)).into_runtime(name, line)
+    }
+
+    /// Looks up a property on the instance. Getters shadow methods; methods are bound to the instance.
+    pub fn get(instance: &Rc<NativeInstance>, name: &Token) -> Result<Value, LoxRuntime> {
+        let class_ = &instance.class_;
+        if let Some(getter) = class_.getters.get(name.lexeme()) {
+            return Ok(getter(&**instance.borrow_data(name.lexeme(), name.line())?));
+        }
+
+        if let Some((arity, method)) = class_.methods.get(name.lexeme()) {
+            return Ok(Value::LoxCallable(Rc::new(NativeMethod {
+                name: format!("{}.{}", class_.name, name.lexeme()),
+                arity: *arity,
+                receiver: instance.clone(),
+                method: method.clone(),
+            })));
+        }
+
+        Err(LoxRuntime::Error(RuntimeError::new(name.clone(), format!("Undefined property '{}'.", name.lexeme()))))
+    }
+
+    /// Assigns a property through its setter. Native instances have no fields of their own.
+    pub fn set(instance: &Rc<NativeInstance>, name: &Token, value: Value) -> Result<(), LoxRuntime> {
+        match instance.class_.setters.get(name.lexeme()) {
+            Some(setter) => setter(&mut **instance.borrow_data_mut(name.lexeme(), name.line())?, value)
+                .map_err(|error| error.into_runtime(name.lexeme(), name.line())),
+            None => Err(LoxRuntime::Error(RuntimeError::new(
+                name.clone(),
+                format!("Cannot set property '{}' on an instance of native class {}.", name.lexeme(), instance.class_.name),
+            ))),
+        }
+    }
+}
+
+impl Display for NativeInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<instance of {}>", self.class_.name)
+    }
+}
+
+/// A method of a native class read from an instance, e.g. `f.read`.
+struct NativeMethod {
+    name: String,
+    arity: Arity,
+    receiver: Rc<NativeInstance>,
+    method: Rc<MethodFn>,
+}
+
+impl LoxCallable for NativeMethod {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        let line = interpreter.call_line();
+        let arguments = NativeArgs::new(&self.name, arguments);
+        (self.method)(&mut **self.receiver.borrow_data_mut(&self.name, line)?, &arguments)
+            .map_err(|error| error.into_runtime(&self.name, line))
+    }
+}
+
+impl Display for NativeMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Debug for NativeMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NativeClassBuilder;
+    use crate::testing::globals_both_with;
+    use crate::value::Value;
+    use crate::{Backend, Lox, LoxError};
+
+    /// A counter whose `add` method reads another counter through `data`.
+    fn with_counter(lox: &mut Lox) {
+        let counter = NativeClassBuilder::<f64>::new("Counter")
+            .constructor(1, |args| args.number(0))
+            .method("increment", 0, |count, _| {
+                *count += 1.0;
+                Ok(Value::from(*count))
+            })
+            .method("add", 1, |count, args| {
+                let Value::NativeInstance(other) = args.get(0) else {
+                    return Err("Expected a counter.".into());
+                };
+                *count += *other.data::<f64>().ok_or("The counter is busy.")?;
+                Ok(Value::Nil)
+            })
+            .getter("count", |count| Value::from(*count))
+            .setter("count", |count, value| {
+                *count = f64::try_from(value)?;
+                Ok(())
+            });
+        lox.define_class(counter);
+    }
+
+    #[test]
+    fn native_classes_have_constructors_methods_getters_and_setters() {
+        let values = globals_both_with(with_counter, "
+            var a = Counter(1);
+            var b = Counter(10);
+            var incremented = a.increment();
+            a.add(b);
+            var added = a.count;
+            b.count = 5;
+            var set = b.count;
+            var class_ = Counter;
+        ", &["incremented", "added", "set", "a", "class_"]);
+        assert_eq!(values, ["2", "12", "5", "<instance of Counter>", "<native class Counter>"]);
+    }
+
+    #[test]
+    fn instances_are_equal_only_to_themselves() {
+        let values = globals_both_with(with_counter, "
+            var a = Counter(1);
+            var same = a == a;
+            var different = a == Counter(1);
+        ", &["same", "different"]);
+        assert_eq!(values, ["true", "false"]);
+    }
+
+    #[test]
+    fn instances_passed_to_their_own_methods_fail_instead_of_panicking() {
+        let values = globals_both_with(with_counter, "
+            var a = Counter(1);
+            var error;
+            try {
+                a.add(a);
+            } catch (e) {
+                error = e.message;
+            }
+            var count = a.count;
+        ", &["error", "count"]);
+        assert_eq!(values, ["The counter is busy.", "1"]);
+    }
+
+    #[test]
+    fn instances_borrowed_by_the_host_fail_instead_of_panicking() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let mut lox = Lox::new(backend);
+            with_counter(&mut lox);
+            lox.run("
+                var a = Counter(1);
+                fun bump() { return a.increment(); }
+                fun reset() { a.count = 0; }
+            ");
+            let Some(Value::NativeInstance(a)) = lox.get_global("a") else {
+                panic!("a is a native instance");
+            };
+            let data = a.data::<f64>().unwrap();
+            for function in ["bump", "reset"] {
+                let error = lox.call(function, Vec::new()).unwrap_err();
+                assert!(
+                    matches!(error, LoxError::Runtime { ref message, .. } if message == "The instance of Counter is already in use."),
+                    "{:?}: {}", backend, error,
+                );
+            }
+            drop(data);
+            assert_eq!(f64::try_from(lox.call("bump", Vec::new()).unwrap()).unwrap(), 2.0, "{:?}", backend);
+        }
+    }
+}
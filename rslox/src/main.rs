@@ -1,48 +1,69 @@
 mod args;
-mod console_macros;
-mod scanner;
-mod token;
-mod lox;
-mod token_type;
-mod literal;
-mod parser;
-mod expr;
-mod stmt;
-mod interpreter;
-mod value;
-mod parse_error;
-mod runtime_error;
-mod environment;
-mod lox_callable;
-mod lox_clock;
-mod lox_function;
-mod resolver;
-mod lox_class;
-mod lox_instance;
 
+use std::process::ExitCode;
+use clap::error::ErrorKind;
 use clap::{CommandFactory, Parser};
 use anyhow::Result;
-use crate::args::Args;
-use crate::lox::Lox;
+use rslox::{cprintln, Lox};
+use crate::args::{Args, Backend, ErrorFormat};
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
-    let mut lox = Lox::new();
+    if args.gc_stats && (args.backend == Backend::Vm || args.disassemble) {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--gc-stats needs the tree backend, the only one with a garbage collector")
+            .exit();
+    }
+    // Calls of the tree backend recurse on the Rust stack, so scripts run on a thread whose
+    // stack fits the allowed call depth rather than on the main thread.
+    let stack_size = args.stack_size * 1024 * 1024;
+    let runner = std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || run(args))?;
+    runner.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    // Disassembling only makes sense for bytecode.
+    let backend = if args.disassemble { Backend::Vm } else { args.backend };
+    let mut lox = Lox::new(backend.into());
+    lox.set_disassemble(args.disassemble);
+    lox.set_gc_stats(args.gc_stats);
+    lox.set_error_format(args.error_format.into());
+    lox.set_max_call_depth(args.max_call_depth);
+    // Tools reading JSON diagnostics get nothing but the script's own output besides them.
+    let banners = args.error_format != ErrorFormat::Json;
+    if !banners {
+        colored::control::set_override(false);
+    }
 
     if args.interactive {
-        cprintln!(colored::Color::Cyan, "Running in interactive REPL mode...");
+        if banners {
+            cprintln!(colored::Color::Cyan, "Running in interactive REPL mode...");
+        }
         lox.run_prompt()?;
     } else if let Some(filename) = args.file {
-        cprintln!(colored::Color::Cyan, "Running script from file: {}", filename);
-        lox.run_file(&filename)?;
+        if banners {
+            cprintln!(colored::Color::Cyan, "Running script from file: {}", filename);
+        }
+        let diagnostics = lox.run_file(&filename)?;
+        // The exit codes of sysexits.h for bad input data and for internal errors.
+        if diagnostics.has_errors() {
+            return Ok(ExitCode::from(65));
+        }
+        if diagnostics.has_runtime_errors() {
+            return Ok(ExitCode::from(70));
+        }
     }
     else {
         cprintln!(colored::Color::Red, "No input provided. Use --help for usage information.");
         Args::command().print_help()?;
     }
 
-    println!();
-    cprintln!(colored::Color::Green, "Finished.");
+    if banners {
+        println!();
+        cprintln!(colored::Color::Green, "Finished.");
+    }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
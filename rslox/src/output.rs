@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::rc::Rc;
+use colored::Colorize;
+
+/// Where the text printed by scripts and the errors found in them are written. Each call
+/// writes one line, without its line break.
+pub trait Output {
+    /// Writes the text of a `print` statement.
+    fn print(&mut self, text: &str);
+
+    /// Writes an error report.
+    fn error(&mut self, text: &str);
+}
+
+/// Writes printed text to standard output and errors to standard error, so that tools reading
+/// either one do not get the other mixed in. Errors are shown in red when standard error is a
+/// terminal.
+#[derive(Default)]
+pub struct StdoutOutput;
+
+impl StdoutOutput {
+    pub fn new() -> Self {
+        StdoutOutput
+    }
+}
+
+impl Output for StdoutOutput {
+    fn print(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn error(&mut self, text: &str) {
+        if std::io::stderr().is_terminal() {
+            eprintln!("{}", text.red());
+        } else {
+            eprintln!("{}", text);
+        }
+    }
+}
+
+/// Collects everything in memory. Clones share the same buffer, so a host can keep one to read
+/// what a script printed after handing another to the interpreter.
+#[derive(Clone, Default)]
+pub struct BufferOutput {
+    buffer: Rc<RefCell<String>>,
+}
+
+impl BufferOutput {
+    pub fn new() -> Self {
+        BufferOutput::default()
+    }
+
+    pub fn contents(&self) -> String {
+        self.buffer.borrow().clone()
+    }
+
+    /// Returns everything written so far and empties the buffer.
+    pub fn take(&self) -> String {
+        std::mem::take(&mut *self.buffer.borrow_mut())
+    }
+}
+
+impl Output for BufferOutput {
+    fn print(&mut self, text: &str) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.push_str(text);
+        buffer.push('\n');
+    }
+
+    fn error(&mut self, text: &str) {
+        self.print(text);
+    }
+}
+
+/// Writes to a file, without colors.
+pub struct FileOutput {
+    file: File,
+}
+
+impl FileOutput {
+    /// Creates the file, truncating it if it exists.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(FileOutput {
+            file: File::create(path)?,
+        })
+    }
+}
+
+impl Output for FileOutput {
+    fn print(&mut self, text: &str) {
+        // Like `println!`, there is nowhere to report a failed write to.
+        let _ = writeln!(self.file, "{}", text);
+    }
+
+    fn error(&mut self, text: &str) {
+        self.print(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferOutput, FileOutput, Output};
+    use crate::testing::run_both;
+    use crate::{Backend, Lox};
+
+    #[test]
+    fn prints_go_to_the_output() {
+        assert_eq!(run_both("print \"one\"; print 1 + 2;"), "one\n3\n");
+    }
+
+    #[test]
+    fn runtime_errors_go_to_the_output() {
+        let output = run_both("print \"one\"; print nil + 1;");
+        assert!(output.starts_with("one\nruntime error: Operands must be two numbers or two strings.\n"), "{}", output);
+    }
+
+    #[test]
+    fn buffer_output_is_emptied_by_take() {
+        let mut output = BufferOutput::new();
+        output.print("one");
+        output.error("two");
+        assert_eq!(output.contents(), "one\ntwo\n");
+        assert_eq!(output.take(), "one\ntwo\n");
+        assert_eq!(output.contents(), "");
+    }
+
+    #[test]
+    fn file_output_writes_to_the_file() {
+        let path = std::env::temp_dir().join(format!("rslox-output-test-{}.txt", std::process::id()));
+        let mut lox = Lox::new(Backend::Tree);
+        lox.set_output(FileOutput::create(&path).unwrap());
+        lox.eval("print 1 + 2; print \"done\";").unwrap();
+        drop(lox);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "3\ndone\n");
+    }
+
+    #[test]
+    fn the_output_can_be_replaced_between_runs() {
+        let first = BufferOutput::new();
+        let second = BufferOutput::new();
+        let mut lox = Lox::new(Backend::Tree);
+        lox.set_output(first.clone());
+        lox.eval("var x = 1; print x;").unwrap();
+        lox.set_output(second.clone());
+        lox.eval("print x + 1;").unwrap();
+        assert_eq!((first.take(), second.take()), ("1\n".to_string(), "2\n".to_string()));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::span::Span;
 use crate::token::Token;
 
 #[derive(Debug, Clone)]
@@ -21,6 +22,11 @@ impl ParseError {
     pub fn message(&self) -> &String {
         &self.message
     }
+
+    /// Where in the source the error is, taken from its token.
+    pub fn span(&self) -> &Span {
+        self.token.span()
+    }
 }
 
 impl std::fmt::Display for ParseError {
@@ -1,90 +1,216 @@
+use std::rc::Rc;
 use crate::stmt::Stmt;
 use crate::token::Token;
 use crate::token_type::TokenType;
 use crate::literal::LiteralValue;
 use crate::expr::*;
-use crate::lox;
+use crate::diagnostics::{DiagnosticKind, Diagnostics};
 use crate::stmt::*;
 use crate::parse_error::ParseError;
+use crate::span::Span;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-pub struct Parser {
+/// Source of expression IDs. The interpreter resolves the code of every REPL line and module
+/// into one table, so IDs must stay unique across parsers.
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of syntax errors after which the parser gives up on the rest of the file. Past this
+/// point, errors are mostly caused by earlier ones.
+const MAX_ERRORS: usize = 20;
+
+pub struct Parser<'a> {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<ParseError>,
+    diagnostics: &'a mut Diagnostics,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+// Statements and expressions are built in the boxed form the syntax tree stores them in.
+#[allow(clippy::vec_box)]
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<Token>, diagnostics: &'a mut Diagnostics) -> Self {
+        Parser { tokens, current: 0, errors: Vec::new(), diagnostics }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Box<Stmt>>, ParseError> {
+    /// Parses the whole file, skipping past syntax errors. Returns the statements that parsed
+    /// without errors, and the errors.
+    pub fn parse(&mut self) -> (Vec<Box<Stmt>>, Vec<ParseError>) {
         let mut statements = vec![];
 
         while !self.is_at_end() {
-            let decl = self.declaration()?;
-            statements.push(Box::new(decl));
+            if let Some(decl) = self.declaration() {
+                statements.push(Box::new(decl));
+            }
         }
-        Ok(statements)
+        (statements, std::mem::take(&mut self.errors))
+    }
+
+    fn next_id(&self) -> usize {
+        NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
     }
 
     fn expression(&mut self) -> Result<Expr, ParseError> {
         self.assignment()
     }
 
-    fn declaration(&mut self) -> Result<Stmt, ParseError> {
-        let result = if self.match_token(&[TokenType::Class]) {
-            return self.class_declaration();
-        } else if self.match_token(&[TokenType::Fun]) {
+    /// Parses a declaration. On a syntax error, skips to where the next one is likely to start
+    /// and returns None.
+    fn declaration(&mut self) -> Option<Stmt> {
+        let start = self.current;
+        match self.try_declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(_) => {
+                self.synchronize();
+                // A stray token such as a '}' at the top level would otherwise be parsed forever.
+                if self.current == start {
+                    self.advance();
+                }
+                None
+            },
+        }
+    }
+
+    fn try_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(&[TokenType::Class]) {
+            self.class_declaration()
+        } else if self.match_token(&[TokenType::Import]) {
+            self.import_declaration()
+        } else if self.check_contextual("from") && self.check_next(&TokenType::String) {
+            self.advance();
+            self.selective_import_declaration()
+        } else if self.check(&TokenType::Fun) && self.check_next(&TokenType::Identifier) {
+            self.advance();
             self.function("function")
         } else if self.match_token(&[TokenType::Var]) {
             self.var_declaration()
         } else {
             self.statement()
-        };
-
-        if let Err(_) = result {
-            self.synchronize();
         }
-
-        result
     }
 
     fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
         let name = self.consume(TokenType::Identifier, "Expect class name.")?.clone();
+
+        let mut superclass = None;
+        if self.match_token(&[TokenType::Less]) {
+            self.consume(TokenType::Identifier, "Expect superclass name.")?;
+            superclass = Some(Box::new(Expr::Variable(Variable::new(self.next_id(), *self.previous().span(), self.previous().clone()))));
+        }
+
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
 
         let mut methods = vec![];
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            let stmt = self.function("method")?;
-            match stmt {
-                Stmt::Function(func) => methods.push(Box::new(func)),
-                _ => return Err(self.error(self.previous(), "Expect method declaration.")),
+            match self.function("method") {
+                Ok(Stmt::Function(func)) => methods.push(Box::new(func)),
+                Ok(_) => return Err(self.error(self.previous().clone(), "Expect method declaration.")),
+                Err(_) => self.synchronize_method(),
             }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
-        Ok(Stmt::Class(Class::new(name, methods)))
+        Ok(Stmt::Class(Class::new(self.span_from(&start), name, superclass, methods)))
+    }
+
+    /// Parses `import "path.lox" as name;`.
+    fn import_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
+        let path = self.consume(TokenType::String, "Expect module path after 'import'.")?.clone();
+        if !self.check_contextual("as") {
+            return Err(self.error(self.peek().clone(), "Expect 'as' after module path."));
+        }
+        self.advance();
+        let alias = self.consume(TokenType::Identifier, "Expect module name after 'as'.")?.clone();
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+
+        Ok(Stmt::Import(Import::new(self.span_from(&start), path, Some(alias), vec![])))
+    }
+
+    /// Parses `from "path.lox" import a, b;`.
+    fn selective_import_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
+        let path = self.consume(TokenType::String, "Expect module path after 'from'.")?.clone();
+        self.consume(TokenType::Import, "Expect 'import' after module path.")?;
+
+        let mut names = vec![];
+        loop {
+            names.push(self.consume(TokenType::Identifier, "Expect name to import.")?.clone());
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after import.")?;
+
+        Ok(Stmt::Import(Import::new(self.span_from(&start), path, None, names)))
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
-        if self.match_token(&[TokenType::For]) {
-            self.for_statement()
+        if self.match_token(&[TokenType::Break]) {
+            self.break_statement()
+        } else if self.match_token(&[TokenType::Continue]) {
+            self.continue_statement()
+        } else if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+            self.labeled_statement()
+        } else if self.match_token(&[TokenType::For]) {
+            self.for_statement(None)
         } else if self.match_token(&[TokenType::If]) {
             self.if_statement()
         } else if self.match_token(&[TokenType::Print]) {
             self.print_statement()
         } else if self.match_token(&[TokenType::Return]) {
             self.return_statement()
+        } else if self.match_token(&[TokenType::Throw]) {
+            self.throw_statement()
+        } else if self.match_token(&[TokenType::Try]) {
+            self.try_statement()
         } else if self.match_token(&[TokenType::While]) {
-            self.while_statement()
+            self.while_statement(None)
         } else if self.match_token(&[TokenType::LeftBrace]) {
-            Ok(Stmt::Block(Block::new(self.block()?)))
+            let start = *self.previous().span();
+            let statements = self.block()?;
+            Ok(Stmt::Block(Block::new(self.span_from(&start), statements)))
         } else {
             self.expression_statement()
         }
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn break_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let label = self.loop_label()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.")?;
+        Ok(Stmt::Break(Break::new(self.span_from(keyword.span()), keyword, label)))
+    }
+
+    fn continue_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let label = self.loop_label()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.")?;
+        Ok(Stmt::Continue(Continue::new(self.span_from(keyword.span()), keyword, label)))
+    }
+
+    fn loop_label(&mut self) -> Result<Option<Token>, ParseError> {
+        if self.check(&TokenType::Identifier) {
+            return Ok(Some(self.advance().clone()));
+        }
+        Ok(None)
+    }
+
+    fn labeled_statement(&mut self) -> Result<Stmt, ParseError> {
+        let label = self.advance().clone();
+        self.consume(TokenType::Colon, "Expect ':' after label.")?;
+
+        if self.match_token(&[TokenType::For]) {
+            self.for_statement(Some(label))
+        } else if self.match_token(&[TokenType::While]) {
+            self.while_statement(Some(label))
+        } else {
+            Err(self.error(self.peek().clone(), "Expect loop after label."))
+        }
+    }
+
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let start = *label.as_ref().unwrap_or(self.previous()).span();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
         let initializer = if self.match_token(&[TokenType::Semicolon]) {
@@ -98,7 +224,7 @@ impl Parser {
         let condition = if !self.check(&TokenType::Semicolon) {
             self.expression()?
         } else {
-            Expr::Literal(Literal::new(LiteralValue::Boolean(true)))
+            Expr::Literal(Literal::new(self.next_id(), *self.peek().span(), LiteralValue::Boolean(true)))
         };
         self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
 
@@ -109,19 +235,14 @@ impl Parser {
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(inc) = increment {
-            body = Stmt::Block(Block::new(vec![
-                Box::new(body),
-                Box::new(Stmt::Expression(Expression::new(Box::new(inc)))),
-            ]));
-        }
+        let body = self.statement()?;
 
-        body = Stmt::While(While::new(Box::new(condition), Box::new(body)));
+        // The increment stays on the loop itself so that `continue` still runs it.
+        let span = self.span_from(&start);
+        let mut body = Stmt::While(While::new(span, Box::new(condition), Box::new(body), increment.map(Box::new), label));
 
         if let Some(init) = initializer {
-            body = Stmt::Block(Block::new(vec![
+            body = Stmt::Block(Block::new(span, vec![
                 Box::new(init),
                 Box::new(body),
             ]));
@@ -131,6 +252,7 @@ impl Parser {
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
@@ -141,13 +263,14 @@ impl Parser {
             else_branch = Some(Box::new(self.statement()?));
         }
 
-        Ok(Stmt::If(If::new(Box::new(condition), then_branch, else_branch)))
+        Ok(Stmt::If(If::new(self.span_from(&start), Box::new(condition), then_branch, else_branch)))
     }
 
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(Print::new(Box::new(value))))
+        Ok(Stmt::Print(Print::new(self.span_from(&start), Box::new(value))))
     }
 
     fn return_statement(&mut self) -> Result<Stmt, ParseError> {
@@ -158,10 +281,47 @@ impl Parser {
         }
 
         self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
-        Ok(Stmt::Return(Return::new(keyword, value)))
+        Ok(Stmt::Return(Return::new(self.span_from(keyword.span()), keyword, value)))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw(Throw::new(self.span_from(keyword.span()), keyword, Box::new(value))))
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+
+        let mut catch_name = None;
+        let mut catch_body = None;
+        if self.match_token(&[TokenType::Catch]) {
+            if self.match_token(&[TokenType::LeftParen]) {
+                catch_name = Some(self.consume(TokenType::Identifier, "Expect exception variable name.")?.clone());
+                self.consume(TokenType::RightParen, "Expect ')' after exception variable.")?;
+            }
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+            catch_body = Some(self.block()?);
+        }
+
+        let mut finally_body = None;
+        if self.match_token(&[TokenType::Finally]) {
+            self.consume(TokenType::LeftBrace, "Expect '{' before finally body.")?;
+            finally_body = Some(self.block()?);
+        }
+
+        if catch_body.is_none() && finally_body.is_none() {
+            return Err(self.error(self.peek().clone(), "Expect 'catch' or 'finally' after try block."));
+        }
+
+        Ok(Stmt::Try(Try::new(self.span_from(&start), body, catch_name, catch_body, finally_body)))
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = *self.previous().span();
         let name = self.consume(TokenType::Identifier, "Expect variable name.")?.clone();
 
         let mut initializer = None;
@@ -170,33 +330,44 @@ impl Parser {
         }
 
         self.consume(TokenType::Semicolon, "Expect ';' after variable declaration.")?;
-        Ok(Stmt::Var(Var::new(name, initializer.map(Box::new))))
+        Ok(Stmt::Var(Var::new(self.span_from(&start), name, initializer.map(Box::new))))
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let start = *label.as_ref().unwrap_or(self.previous()).span();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
         let body = Box::new(self.statement()?);
 
-        Ok(Stmt::While(While::new(Box::new(condition), body)))
+        Ok(Stmt::While(While::new(self.span_from(&start), Box::new(condition), body, None, label)))
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
-        Ok(Stmt::Expression(Expression::new(Box::new(expr))))
+        Ok(Stmt::Expression(Expression::new(self.span_from(expr.span()), Box::new(expr))))
     }
 
     fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+        // Methods have no 'fun' keyword, so they start at their name.
+        let start = if self.previous().token_type() == &TokenType::Fun { *self.previous().span() } else { *self.peek().span() };
         let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?.clone();
         self.consume(TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let parameters = self.parameters()?;
+        self.consume(TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
+        let body = self.block()?;
+
+        Ok(Stmt::Function(Function::new(self.span_from(&start), name, parameters, body)))
+    }
 
+    /// Parses a parameter list up to and including the closing ')'.
+    fn parameters(&mut self) -> Result<Vec<Token>, ParseError> {
         let mut parameters = vec![];
         if !self.check(&TokenType::RightParen) {
             loop {
                 if parameters.len() >= 255 {
-                    return Err(self.error(self.peek(), "Can't have more than 255 parameters."));
+                    return Err(self.error(self.peek().clone(), "Can't have more than 255 parameters."));
                 }
 
                 let param = self.consume(TokenType::Identifier, "Expect parameter name.")?.clone();
@@ -209,18 +380,74 @@ impl Parser {
         }
 
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
-        self.consume(TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
+        Ok(parameters)
+    }
+
+    /// Parses the rest of `fun (a, b) { ... }` after the 'fun' keyword.
+    fn lambda(&mut self) -> Result<Expr, ParseError> {
+        let start = *self.previous().span();
+        let name = self.anonymous_name(self.previous());
+        self.consume(TokenType::LeftParen, "Expect '(' after 'fun'.")?;
+        let parameters = self.parameters()?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
         let body = self.block()?;
 
-        Ok(Stmt::Function(Function::new(name, parameters, body)))
+        let span = self.span_from(&start);
+        Ok(Expr::Lambda(Lambda::new(self.next_id(), span, Rc::new(Function::new(span, name, parameters, body)))))
+    }
+
+    /// Parses an arrow function such as `(a, b) => a + b` or `(a) => { ... }`.
+    fn arrow_function(&mut self) -> Result<Expr, ParseError> {
+        let start = *self.peek().span();
+        self.consume(TokenType::LeftParen, "Expect '(' before parameters.")?;
+        let parameters = self.parameters()?;
+        let arrow = self.consume(TokenType::Arrow, "Expect '=>' after parameters.")?.clone();
+        let name = self.anonymous_name(&arrow);
+
+        let body = if self.match_token(&[TokenType::LeftBrace]) {
+            self.block()?
+        } else {
+            let value = self.assignment()?;
+            let span = *value.span();
+            vec![Box::new(Stmt::Return(Return::new(span, arrow, Some(Box::new(value)))))]
+        };
+
+        let span = self.span_from(&start);
+        Ok(Expr::Lambda(Lambda::new(self.next_id(), span, Rc::new(Function::new(span, name, parameters, body)))))
+    }
+
+    fn anonymous_name(&self, token: &Token) -> Token {
+        Token::new(TokenType::Identifier, "anonymous".to_string(), None, token.line()).with_span(*token.span())
+    }
+
+    /// Returns true if the tokens ahead are `(`, a possibly empty list of names, `)` and `=>`.
+    fn is_arrow_function(&self) -> bool {
+        let mut i = self.current + 1;
+        let token_type = |i: usize| self.tokens.get(i).map(|token| token.token_type());
+
+        if token_type(i) != Some(&TokenType::RightParen) {
+            loop {
+                if token_type(i) != Some(&TokenType::Identifier) {
+                    return false;
+                }
+                i += 1;
+                if token_type(i) != Some(&TokenType::Comma) {
+                    break;
+                }
+                i += 1;
+            }
+        }
+
+        token_type(i) == Some(&TokenType::RightParen) && token_type(i + 1) == Some(&TokenType::Arrow)
     }
 
     fn block(&mut self) -> Result<Vec<Box<Stmt>>, ParseError> {
         let mut statements = vec![];
 
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            let stmt = self.declaration()?;
-            statements.push(Box::new(stmt));
+            if let Some(stmt) = self.declaration() {
+                statements.push(Box::new(stmt));
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
@@ -233,16 +460,20 @@ impl Parser {
         if self.match_token(&[TokenType::Equal]) {
             let equals = self.previous().clone();
             let value = self.assignment()?;
+            let span = expr.span().to(value.span());
 
             if let Expr::Variable(var) = expr {
                 let name = var.name().clone();
-                return Ok(Expr::Assign(Assign::new(name, Box::new(value))));
+                return Ok(Expr::Assign(Assign::new(self.next_id(), span, name, Box::new(value))));
             }
             else if let Expr::Get(get) = expr {
-                return Ok(Expr::Set(Set::new(get.object().clone(), get.name().clone(), Box::new(value))));
+                return Ok(Expr::Set(Set::new(self.next_id(), span, get.object().clone(), get.name().clone(), Box::new(value))));
+            }
+            else if let Expr::Index(index) = expr {
+                return Ok(Expr::IndexSet(IndexSet::new(self.next_id(), span, index.object().clone(), index.bracket().clone(), index.index().clone(), Box::new(value))));
             }
 
-            return Err(self.error(&equals, "Invalid assignment target."));
+            return Err(self.error(equals, "Invalid assignment target."));
         }
 
         Ok(expr)
@@ -254,7 +485,7 @@ impl Parser {
         while self.match_token(&[TokenType::Or]) {
             let operator = self.previous().clone();
             let right = self.and()?;
-            expr = Expr::Logical(Logical::new(Box::new(expr), operator, Box::new(right)));
+            expr = Expr::Logical(Logical::new(self.next_id(), expr.span().to(right.span()), Box::new(expr), operator, Box::new(right)));
         }
 
         Ok(expr)
@@ -266,7 +497,7 @@ impl Parser {
         while self.match_token(&[TokenType::And]) {
             let operator = self.previous().clone();
             let right = self.equality()?;
-            expr = Expr::Logical(Logical::new(Box::new(expr), operator, Box::new(right)));
+            expr = Expr::Logical(Logical::new(self.next_id(), expr.span().to(right.span()), Box::new(expr), operator, Box::new(right)));
         }
 
         Ok(expr)
@@ -277,7 +508,7 @@ impl Parser {
         while self.match_token(&[TokenType::BangEqual, TokenType::EqualEqual]) {
             let operator = self.previous().clone();
             let right = self.comparison()?;
-            expr = Expr::Binary(Binary::new(Box::new(expr), operator, Box::new(right)));
+            expr = Expr::Binary(Binary::new(self.next_id(), expr.span().to(right.span()), Box::new(expr), operator, Box::new(right)));
         }
 
         Ok(expr)
@@ -288,7 +519,7 @@ impl Parser {
         while self.match_token(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
             let operator = self.previous().clone();
             let right = self.term()?;
-            expr = Expr::Binary(Binary::new(Box::new(expr), operator, Box::new(right)));
+            expr = Expr::Binary(Binary::new(self.next_id(), expr.span().to(right.span()), Box::new(expr), operator, Box::new(right)));
         }
 
         Ok(expr)
@@ -299,7 +530,7 @@ impl Parser {
         while self.match_token(&[TokenType::Plus, TokenType::Minus]) {
             let operator = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::Binary(Binary::new(Box::new(expr), operator, Box::new(right)));
+            expr = Expr::Binary(Binary::new(self.next_id(), expr.span().to(right.span()), Box::new(expr), operator, Box::new(right)));
         }
 
         Ok(expr)
@@ -310,7 +541,7 @@ impl Parser {
         while self.match_token(&[TokenType::Star, TokenType::Slash]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::Binary(Binary::new(Box::new(expr), operator, Box::new(right)));
+            expr = Expr::Binary(Binary::new(self.next_id(), expr.span().to(right.span()), Box::new(expr), operator, Box::new(right)));
         }
 
         Ok(expr)
@@ -320,7 +551,7 @@ impl Parser {
         if self.match_token(&[TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            return Ok(Expr::Unary(Unary::new(operator, Box::new(right))));
+            return Ok(Expr::Unary(Unary::new(self.next_id(), operator.span().to(right.span()), operator, Box::new(right))));
         }
 
         self.call()
@@ -334,7 +565,10 @@ impl Parser {
                 expr = self.finish_call(expr)?;
             } else if self.match_token(&[TokenType::Dot]) {
                 let name = self.consume(TokenType::Identifier, "Expect property name after '.'.")?.clone();
-                expr = Expr::Get(Get::new(Box::new(expr), name));
+                expr = Expr::Get(Get::new(self.next_id(), expr.span().to(name.span()), Box::new(expr), name));
+            }
+            else if self.match_token(&[TokenType::LeftBracket]) {
+                expr = self.finish_index(expr)?;
             }
             else {
                 break;
@@ -349,7 +583,7 @@ impl Parser {
         if !self.check(&TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
-                    return Err(self.error(self.peek(), "Can't have more than 255 arguments."));
+                    return Err(self.error(self.peek().clone(), "Can't have more than 255 arguments."));
                 }
                 arguments.push(self.expression()?);
 
@@ -360,43 +594,156 @@ impl Parser {
         }
 
         let paren = self.consume(TokenType::RightParen, "Expect ')' after arguments.")?.clone();
-        Ok(Expr::Call(Call::new(Box::new(callee), paren, arguments.into_iter().map(Box::new).collect())))
+        Ok(Expr::Call(Call::new(self.next_id(), callee.span().to(paren.span()), Box::new(callee), paren, arguments.into_iter().map(Box::new).collect())))
+    }
+
+    fn finish_index(&mut self, object: Expr) -> Result<Expr, ParseError> {
+        let bracket = self.previous().clone();
+
+        let start = if !self.check(&TokenType::Colon) {
+            Some(Box::new(self.expression()?))
+        } else {
+            None
+        };
+
+        if self.match_token(&[TokenType::Colon]) {
+            let end = if !self.check(&TokenType::RightBracket) {
+                Some(Box::new(self.expression()?))
+            } else {
+                None
+            };
+            self.consume(TokenType::RightBracket, "Expect ']' after slice.")?;
+            let span = self.span_from(object.span());
+            return Ok(Expr::Slice(Slice::new(self.next_id(), span, Box::new(object), bracket, start, end)));
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+        let span = self.span_from(object.span());
+        match start {
+            Some(index) => Ok(Expr::Index(Index::new(self.next_id(), span, Box::new(object), bracket, index))),
+            None => Err(self.error(bracket, "Expect index expression.")),
+        }
+    }
+
+    fn list(&mut self) -> Result<Expr, ParseError> {
+        let start = *self.previous().span();
+        let mut elements = vec![];
+        while !self.check(&TokenType::RightBracket) && !self.is_at_end() {
+            elements.push(Box::new(self.expression()?));
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+        Ok(Expr::List(List::new(self.next_id(), self.span_from(&start), elements)))
+    }
+
+    fn map(&mut self) -> Result<Expr, ParseError> {
+        let brace = self.previous().clone();
+
+        let mut keys = vec![];
+        let mut values = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            keys.push(Box::new(self.expression()?));
+            self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+            values.push(Box::new(self.expression()?));
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Ok(Expr::Map(Map::new(self.next_id(), self.span_from(brace.span()), brace, keys, values)))
+    }
+
+    /// Parses an interpolated string. The scanner emits one `Interpolation` token for each segment
+    /// that is followed by `${`, and a final `String` token for the rest of the literal.
+    fn interpolation(&mut self) -> Result<Expr, ParseError> {
+        let start = *self.previous().span();
+        let mut parts = vec![];
+
+        loop {
+            self.push_string_segment(&mut parts);
+            parts.push(Box::new(self.expression()?));
+
+            if !self.match_token(&[TokenType::Interpolation]) {
+                break;
+            }
+        }
+
+        self.consume(TokenType::String, "Expect '}' after interpolated expression.")?;
+        self.push_string_segment(&mut parts);
+
+        Ok(Expr::Interpolation(Interpolation::new(self.next_id(), self.span_from(&start), parts)))
+    }
+
+    fn push_string_segment(&self, parts: &mut Vec<Box<Expr>>) {
+        if let Some(LiteralValue::String(segment)) = self.previous().literal()
+            && !segment.is_empty() {
+            parts.push(Box::new(Expr::Literal(Literal::new(self.next_id(), *self.previous().span(), LiteralValue::String(segment.clone())))));
+        }
     }
 
     fn primary(&mut self) -> Result<Expr, ParseError> {
         if self.match_token(&[TokenType::False]) {
-            return Ok(Expr::Literal(Literal::new(LiteralValue::Boolean(false))));
+            return Ok(Expr::Literal(Literal::new(self.next_id(), *self.previous().span(), LiteralValue::Boolean(false))));
         }
         if self.match_token(&[TokenType::True]) {
-            return Ok(Expr::Literal(Literal::new(LiteralValue::Boolean(true))));
+            return Ok(Expr::Literal(Literal::new(self.next_id(), *self.previous().span(), LiteralValue::Boolean(true))));
         }
         if self.match_token(&[TokenType::Nil]) {
-            return Ok(Expr::Literal(Literal::new(LiteralValue::Nil)));
+            return Ok(Expr::Literal(Literal::new(self.next_id(), *self.previous().span(), LiteralValue::Nil)));
         }
         if self.match_token(&[TokenType::Number]) {
             let value = match self.previous().literal().clone().unwrap() {
                 LiteralValue::Number(n) => n,
                 _ => panic!("Expected number literal."),
             };
-            return Ok(Expr::Literal(Literal::new(LiteralValue::Number(value))));
+            return Ok(Expr::Literal(Literal::new(self.next_id(), *self.previous().span(), LiteralValue::Number(value))));
         }
         if self.match_token(&[TokenType::String]) {
             let value = match self.previous().literal().clone().unwrap() {
                 LiteralValue::String(s) => s,
                 _ => panic!("Expected string literal."),
             };
-            return Ok(Expr::Literal(Literal::new(LiteralValue::String(value))));
+            return Ok(Expr::Literal(Literal::new(self.next_id(), *self.previous().span(), LiteralValue::String(value))));
+        }
+        if self.match_token(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?.clone();
+            return Ok(Expr::Super(Super::new(self.next_id(), keyword.span().to(method.span()), keyword, method)));
+        }
+        if self.match_token(&[TokenType::Interpolation]) {
+            return self.interpolation();
+        }
+        if self.match_token(&[TokenType::This]) {
+            return Ok(Expr::This(This::new(self.next_id(), *self.previous().span(), self.previous().clone())));
         }
         if self.match_token(&[TokenType::Identifier]) {
-            return Ok(Expr::Variable(Variable::new(self.previous().clone())));
+            return Ok(Expr::Variable(Variable::new(self.next_id(), *self.previous().span(), self.previous().clone())));
+        }
+        if self.match_token(&[TokenType::Fun]) {
+            return self.lambda();
+        }
+        if self.check(&TokenType::LeftParen) && self.is_arrow_function() {
+            return self.arrow_function();
         }
         if self.match_token(&[TokenType::LeftParen]) {
+            let start = *self.previous().span();
             let expr = self.expression()?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-            return Ok(Expr::Grouping(Grouping::new(Box::new(expr))));
+            return Ok(Expr::Grouping(Grouping::new(self.next_id(), self.span_from(&start), Box::new(expr))));
+        }
+        if self.match_token(&[TokenType::LeftBracket]) {
+            return self.list();
+        }
+        if self.match_token(&[TokenType::LeftBrace]) {
+            return self.map();
         }
 
-        Err(self.error(self.peek(), "Expect expression."))
+        Err(self.error(self.peek().clone(), "Expect expression."))
     }
 
     fn peek(&self) -> &Token {
@@ -407,13 +754,30 @@ impl Parser {
         if self.is_at_end() {
             return false;
         }
-        &self.peek().token_type() == &token_type
+        self.peek().token_type() == token_type
+    }
+
+    /// Checks for an identifier that acts as a keyword only in certain places, like `as` and `from`.
+    fn check_contextual(&self, keyword: &str) -> bool {
+        self.check(&TokenType::Identifier) && self.peek().lexeme() == keyword
+    }
+
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type() == token_type,
+            None => false,
+        }
     }
 
     fn previous(&self) -> &Token {
         &self.tokens[self.current - 1]
     }
 
+    /// Returns the span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: &Span) -> Span {
+        start.to(self.previous().span())
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -430,7 +794,7 @@ impl Parser {
             return Ok(self.advance());
         }
 
-        Err(self.error(self.peek(), message))
+        Err(self.error(self.peek().clone(), message))
     }
 
     fn match_token(&mut self, types: &[TokenType]) -> bool {
@@ -443,23 +807,48 @@ impl Parser {
         false
     }
     
-    fn error(&self, token: &Token, message: &str) -> ParseError {
-        let parse_error = ParseError::new(token.clone(), message.to_string());
-        lox::Lox::parse_error(&parse_error);
+    /// Records a syntax error. Once there are too many, the rest of the tokens are skipped.
+    fn error(&mut self, token: Token, message: &str) -> ParseError {
+        let parse_error = ParseError::new(token, message.to_string());
+        if self.errors.len() < MAX_ERRORS {
+            self.diagnostics.parse_error(&parse_error);
+            self.errors.push(parse_error.clone());
+
+            if self.errors.len() == MAX_ERRORS {
+                let token = parse_error.token();
+                self.diagnostics.error(DiagnosticKind::Syntax, token.line(), *token.span(), "Too many errors, stopping.");
+                self.current = self.tokens.len() - 1;
+            }
+        }
         parse_error
     }
 
+    /// Skips tokens until the start of the next statement: past a ';' or a block, or up to a
+    /// keyword that starts a statement or the '}' that closes the enclosing block. A keyword
+    /// the error was found at, such as a stray `class` in an expression, is skipped too, since
+    /// parsing would fail on it again.
     fn synchronize(&mut self) {
-        self.advance();
+        let start = self.current;
+        let mut depth = 0;
 
         while !self.is_at_end() {
-            if self.previous().token_type() == &TokenType::Semicolon {
-                return;
-            }
-
             match self.peek().token_type() {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace if depth == 0 => return,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                TokenType::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
                 TokenType::Class | TokenType::Fun | TokenType::Var | TokenType::For |
-                TokenType::If | TokenType::While | TokenType::Print | TokenType::Return => {
+                TokenType::If | TokenType::While | TokenType::Print | TokenType::Return |
+                TokenType::Throw | TokenType::Try | TokenType::Import if depth == 0 && self.current > start => {
                     return;
                 }
                 _ => {}
@@ -469,4 +858,173 @@ impl Parser {
         }
     }
 
-}
\ No newline at end of file
+    /// Skips tokens until the start of the next method of a class: past the body of the
+    /// method with the error, or up to the '}' that closes the class.
+    fn synchronize_method(&mut self) {
+        let mut depth = 0;
+
+        while !self.is_at_end() {
+            match self.peek().token_type() {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace if depth == 0 => return,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => {}
+            }
+
+            self.advance();
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::diagnostics::Diagnostics;
+    use crate::expr::Expr;
+    use crate::parse_error::ParseError;
+    use crate::scanner::Scanner;
+    use crate::span::Span;
+    use crate::stmt::Stmt;
+    use crate::testing::{diagnostics, globals_both};
+    use crate::Backend;
+
+    #[test]
+    fn anonymous_functions_are_values() {
+        let values = globals_both("
+            var add = fun (a, b) { return a + b; };
+            var sum = add(1, 2);
+            var now = (fun () { return \"now\"; })();
+        ", &["sum", "now", "add"]);
+        assert_eq!(values, ["3", "now", "<fn anonymous>"]);
+    }
+
+    #[test]
+    fn arrow_functions_return_their_expression_or_run_their_block() {
+        let values = globals_both("
+            var double = (x) => x * 2;
+            var counter = () => {
+                var n = 0;
+                return () => { n = n + 1; return n; };
+            };
+            var count = counter();
+            count();
+            var doubled = double(4);
+            var counted = count();
+        ", &["doubled", "counted"]);
+        assert_eq!(values, ["8", "2"]);
+    }
+
+    #[test]
+    fn arrow_functions_can_be_passed_as_arguments() {
+        let values = globals_both("
+            fun apply(f, x) { return f(x); }
+            var a = apply((x) => x + 1, 1);
+            var b = apply((x) => (x + 1) * 2, 1);
+        ", &["a", "b"]);
+        assert_eq!(values, ["2", "4"]);
+    }
+
+    #[test]
+    fn parenthesized_expressions_are_not_arrow_functions() {
+        let values = globals_both("var a = 1; var b = (a) + 2; var c = (1 + 2) * 3;", &["b", "c"]);
+        assert_eq!(values, ["3", "9"]);
+    }
+
+    #[allow(clippy::vec_box)]
+    fn parse(source: &str) -> (Vec<Box<Stmt>>, Vec<ParseError>) {
+        let mut diagnostics = Diagnostics::new();
+        let tokens = Scanner::new(source.to_string(), &mut diagnostics).scan_tokens();
+        Parser::new(tokens, &mut diagnostics).parse()
+    }
+
+    fn variable_ids(source: &str) -> Vec<usize> {
+        parse(source).0.iter().map(|statement| match &**statement {
+            Stmt::Print(print) => match &**print.expression() {
+                Expr::Variable(variable) => *variable.id(),
+                expr => panic!("expected a variable, got {:?}", expr),
+            },
+            statement => panic!("expected a print statement, got {:?}", statement),
+        }).collect()
+    }
+
+    #[test]
+    fn identical_expressions_get_different_ids() {
+        let first = variable_ids("print a; print a;");
+        let second = variable_ids("print a;");
+        assert_ne!(first[0], first[1]);
+        assert!(!first.contains(&second[0]));
+    }
+
+    #[test]
+    fn nodes_span_the_source_they_were_parsed_from() {
+        let source = "var x = 1;\nprint (x + 2) * foo(3, 4);";
+        let (statements, errors) = parse(source);
+        assert!(errors.is_empty());
+        let text = |span: &Span| &source[span.start()..span.end()];
+        assert_eq!(text(statements[0].span()), "var x = 1;");
+
+        let Stmt::Print(print) = &*statements[1] else {
+            panic!("expected a print statement, got {:?}", statements[1]);
+        };
+        assert_eq!(text(print.span()), "print (x + 2) * foo(3, 4);");
+        assert_eq!((print.span().line(), print.span().column()), (2, 1));
+        let Expr::Binary(product) = &**print.expression() else {
+            panic!("expected a binary expression, got {:?}", print.expression());
+        };
+        assert_eq!(text(product.span()), "(x + 2) * foo(3, 4)");
+        assert_eq!(text(product.left().span()), "(x + 2)");
+        assert_eq!(text(product.right().span()), "foo(3, 4)");
+        assert_eq!(product.right().span().column(), 17);
+    }
+
+    #[test]
+    fn parse_errors_carry_the_span_of_their_token() {
+        let (_, errors) = parse("var x = 1;\nprint x +;");
+        let span = errors[0].span();
+        assert_eq!((span.line(), span.column(), span.len()), (2, 10, 1));
+    }
+
+    fn syntax_errors(source: &str) -> Vec<(u32, String)> {
+        diagnostics(Backend::Tree, source).iter()
+            .map(|diagnostic| (diagnostic.line(), diagnostic.message().clone()))
+            .collect()
+    }
+
+    #[test]
+    fn parsing_resumes_after_each_error() {
+        let errors = syntax_errors("
+            var x = 1
+            print 2;
+            print 3 +;
+            { print 4 + }
+            fun f() { return 5 +; }
+            print 6;
+        ");
+        let lines: Vec<_> = errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [3, 4, 5, 6], "{:?}", errors);
+    }
+
+    #[test]
+    fn stray_keywords_in_expressions_do_not_cascade() {
+        let errors = syntax_errors("
+            print 1 + class;
+            var x = if;
+            print 3;
+        ");
+        assert_eq!(errors, [(2, "Expect expression.".to_string()), (3, "Expect expression.".to_string())]);
+    }
+
+    #[test]
+    fn parsing_stops_after_too_many_errors() {
+        let errors = syntax_errors(&"print +;\n".repeat(30));
+        assert_eq!(errors.len(), 21);
+        assert_eq!(errors[20], (20, "Too many errors, stopping.".to_string()));
+    }
+}
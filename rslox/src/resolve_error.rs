@@ -0,0 +1,49 @@
+use crate::diagnostics::Note;
+use crate::span::Span;
+use crate::token::Token;
+
+/// A static error found by the resolver, such as `return` outside of a function.
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    token: Token,
+    message: String,
+    notes: Vec<Note>,
+}
+
+impl ResolveError {
+    pub fn new(token: Token, message: String) -> Self {
+        ResolveError {
+            token,
+            message,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Adds a message pointing at related source, shown along with the error.
+    pub fn with_note(mut self, span: Span, message: &str) -> Self {
+        self.notes.push(Note::new(span, message));
+        self
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+
+    pub fn span(&self) -> &Span {
+        self.token.span()
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Error at '{}': {}", self.token.line(), self.token.lexeme(), self.message)
+    }
+}
@@ -1,21 +1,45 @@
 use std::collections::HashMap;
 use crate::{expr, stmt};
-use crate::expr::{Assign, Binary, Call, Get, Grouping, Literal, Logical, Set, Unary, Variable};
+use crate::expr::{Assign, Binary, Call, Get, Grouping, Index, IndexSet, Interpolation, Lambda, List, Literal, Logical, Map, Set, Slice, Super, This, Unary, Variable};
 use crate::interpreter::Interpreter;
-use crate::runtime_error::{LoxRuntime, RuntimeError};
-use crate::stmt::{Block, Class, Expression, Function, If, Print, Return, Stmt, Var, While};
+use crate::resolve_error::ResolveError;
+use crate::runtime_error::LoxRuntime;
+use crate::span::Span;
+use crate::stmt::{Block, Break, Class, Continue, Expression, Function, If, Import, Print, Return, Stmt, Throw, Try, Var, While};
 use crate::token::Token;
 
 pub struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Local>>,
     current_function: FunctionType,
+    current_class: ClassType,
+    loops: Vec<Option<String>>,
+    errors: Vec<ResolveError>,
+}
+
+/// A variable declared in a local scope.
+struct Local {
+    /// Whether the variable's initializer has been resolved.
+    defined: bool,
+    /// Index of the variable in its scope's environment.
+    slot: usize,
+    /// Where the variable is declared, or an empty span for implicit variables.
+    span: Span,
 }
 
 #[derive(Clone, Copy, PartialEq)]
 enum FunctionType {
     None,
     Function,
+    Initializer,
+    Method,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
 }
 
 impl<'a> Resolver<'a> {
@@ -24,10 +48,25 @@ impl<'a> Resolver<'a> {
             interpreter,
             scopes: Vec::new(),
             current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loops: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
-    pub fn resolve(&mut self, stmts: &Vec<Box<Stmt>>) -> anyhow::Result<(), LoxRuntime> {
+    /// Resolves the statements, going on after errors so that all of them are reported.
+    pub fn resolve(&mut self, stmts: &[Box<Stmt>]) -> Result<(), Vec<ResolveError>> {
+        // Errors are collected in `errors`; the visitor methods only return an error type
+        // because the visitor trait requires one.
+        let _ = self.resolve_statements(stmts);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn resolve_statements(&mut self, stmts: &[Box<Stmt>]) -> anyhow::Result<(), LoxRuntime> {
         for stmt in stmts {
             self.resolve_stmt(stmt)?;
         }
@@ -50,49 +89,77 @@ impl<'a> Resolver<'a> {
         self.scopes.pop();
     }
 
-    fn declare(&mut self, name: &Token) -> Result<(), LoxRuntime> {
-        if !self.scopes.is_empty() {
-            if self.scopes.last().unwrap().contains_key(name.lexeme()) {
-                return Err(LoxRuntime::Error(RuntimeError::new(
-                    name.clone(),
-                    "Variable with this name already declared in this scope.".to_string(),
-                )));
-            }
-            self.scopes.last_mut().unwrap().insert(name.lexeme().to_string(), false);
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        if let Some(previous) = scope.get(name.lexeme()) {
+            let error = ResolveError::new(
+                name.clone(),
+                "Variable with this name already declared in this scope.".to_string(),
+            ).with_note(previous.span, "previously declared here");
+            self.errors.push(error);
+            return;
         }
+        let slot = scope.len();
+        scope.insert(name.lexeme().to_string(), Local { defined: false, slot, span: *name.span() });
+    }
 
-        Ok(())
+    fn error(&mut self, token: &Token, message: String) {
+        self.errors.push(ResolveError::new(token.clone(), message));
     }
 
     fn define(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme().to_string(), true);
+        if let Some(local) = self.scopes.last_mut().and_then(|scope| scope.get_mut(name.lexeme())) {
+            local.defined = true;
         }
     }
 
-    fn resolve_local(&mut self, expr: &expr::Expr, name: &Token) {
+    /// Declares and defines a variable the interpreter binds implicitly, such as `this`.
+    fn define_implicit(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        let slot = scope.len();
+        scope.insert(name.to_string(), Local { defined: true, slot, span: Span::default() });
+    }
+
+    fn resolve_local(&mut self, id: usize, name: &Token) {
         for (i, scope) in self.scopes.iter().enumerate().rev() {
-            if scope.contains_key(name.lexeme()) {
+            if let Some(local) = scope.get(name.lexeme()) {
                 let depth = self.scopes.len() - 1 - i;
-                self.interpreter.resolve(expr, depth);
+                self.interpreter.resolve(id, depth, local.slot);
                 return;
             }
         }
     }
 
+    fn resolve_loop_control(&mut self, keyword: &Token, label: &Option<Token>) -> Result<(), LoxRuntime> {
+        if self.loops.is_empty() {
+            self.error(keyword, format!("Cannot use '{}' outside of a loop.", keyword.lexeme()));
+        } else if let Some(label) = label
+            && !self.loops.iter().any(|l| l.as_deref() == Some(label.lexeme().as_str())) {
+            self.error(label, format!("No enclosing loop labeled '{}'.", label.lexeme()));
+        }
+
+        Ok(())
+    }
+
     fn resolve_function(&mut self, function: &Function, func_type: FunctionType) -> anyhow::Result<(), LoxRuntime> {
         let enclosing_function = self.current_function;
         self.current_function = func_type;
+        // Loop control can't cross a function boundary.
+        let enclosing_loops = std::mem::take(&mut self.loops);
 
         self.begin_scope();
         for param in function.params() {
-            self.declare(param)?;
+            self.declare(param);
             self.define(param);
         }
-        self.resolve(function.body())?;
+        self.resolve_statements(function.body())?;
         self.end_scope();
 
         self.current_function = enclosing_function;
+        self.loops = enclosing_loops;
         Ok(())
     }
 }
@@ -100,7 +167,7 @@ impl<'a> Resolver<'a> {
 impl<'a> expr::Visitor<()> for Resolver<'a> {
     fn visit_assign_expr(&mut self, expr: &Assign) -> anyhow::Result<(), LoxRuntime> {
         self.resolve_expr(expr.value())?;
-        self.resolve_local(&expr::Expr::Assign(expr.clone()), expr.name());
+        self.resolve_local(*expr.id(), expr.name());
         Ok(())
     }
 
@@ -128,7 +195,38 @@ impl<'a> expr::Visitor<()> for Resolver<'a> {
         Ok(())
     }
 
-    fn visit_literal_expr(&mut self, expr: &Literal) -> anyhow::Result<(), LoxRuntime> {
+    fn visit_index_expr(&mut self, expr: &Index) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_expr(expr.object())?;
+        self.resolve_expr(expr.index())?;
+        Ok(())
+    }
+
+    fn visit_index_set_expr(&mut self, expr: &IndexSet) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_expr(expr.value())?;
+        self.resolve_expr(expr.object())?;
+        self.resolve_expr(expr.index())?;
+        Ok(())
+    }
+
+    fn visit_interpolation_expr(&mut self, expr: &Interpolation) -> anyhow::Result<(), LoxRuntime> {
+        for part in expr.parts() {
+            self.resolve_expr(part)?;
+        }
+        Ok(())
+    }
+
+    fn visit_lambda_expr(&mut self, expr: &Lambda) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_function(expr.function(), FunctionType::Function)
+    }
+
+    fn visit_list_expr(&mut self, expr: &List) -> anyhow::Result<(), LoxRuntime> {
+        for element in expr.elements() {
+            self.resolve_expr(element)?;
+        }
+        Ok(())
+    }
+
+    fn visit_literal_expr(&mut self, _expr: &Literal) -> anyhow::Result<(), LoxRuntime> {
         Ok(())
     }
 
@@ -138,27 +236,69 @@ impl<'a> expr::Visitor<()> for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_map_expr(&mut self, expr: &Map) -> anyhow::Result<(), LoxRuntime> {
+        for (key, value) in expr.keys().iter().zip(expr.values()) {
+            self.resolve_expr(key)?;
+            self.resolve_expr(value)?;
+        }
+        Ok(())
+    }
+
     fn visit_set_expr(&mut self, expr: &Set) -> anyhow::Result<(), LoxRuntime> {
         self.resolve_expr(expr.value())?;
         self.resolve_expr(expr.object())?;
         Ok(())
     }
 
+    fn visit_slice_expr(&mut self, expr: &Slice) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_expr(expr.object())?;
+        if let Some(start) = expr.start() {
+            self.resolve_expr(start)?;
+        }
+        if let Some(end) = expr.end() {
+            self.resolve_expr(end)?;
+        }
+        Ok(())
+    }
+
+    fn visit_super_expr(&mut self, expr: &Super) -> anyhow::Result<(), LoxRuntime> {
+        if self.current_class == ClassType::None {
+            self.error(expr.keyword(), "Cannot use 'super' outside of a class.".to_string());
+            return Ok(());
+        } else if self.current_class != ClassType::Subclass {
+            self.error(expr.keyword(), "Cannot use 'super' in a class with no superclass.".to_string());
+            return Ok(());
+        }
+
+        self.resolve_local(*expr.id(), expr.keyword());
+        Ok(())
+    }
+
+    fn visit_this_expr(&mut self, expr: &This) -> anyhow::Result<(), LoxRuntime> {
+        if self.current_class == ClassType::None {
+            self.error(expr.keyword(), "Cannot use 'this' outside of a class.".to_string());
+            return Ok(());
+        }
+
+        self.resolve_local(*expr.id(), expr.keyword());
+        Ok(())
+    }
+
     fn visit_unary_expr(&mut self, expr: &Unary) -> anyhow::Result<(), LoxRuntime> {
         self.resolve_expr(expr.right())?;
         Ok(())
     }
 
     fn visit_variable_expr(&mut self, expr: &Variable) -> anyhow::Result<(), LoxRuntime> {
-        if let Some(scope) = self.scopes.last() {
-            if let Some(false) = scope.get(expr.name().lexeme()) {
-                return Err(LoxRuntime::Error(RuntimeError::new(
-                    expr.name().clone(),
-                    "Cannot read local variable in its own initializer.".to_string(),
-                )));
-            }
+        if let Some(scope) = self.scopes.last()
+            && let Some(Local { defined: false, span, .. }) = scope.get(expr.name().lexeme()) {
+            let error = ResolveError::new(
+                expr.name().clone(),
+                "Cannot read local variable in its own initializer.".to_string(),
+            ).with_note(*span, "variable declared here");
+            self.errors.push(error);
         }
-        self.resolve_local(&expr::Expr::Variable(expr.clone()), expr.name());
+        self.resolve_local(*expr.id(), expr.name());
 
         Ok(())
     }
@@ -167,24 +307,68 @@ impl<'a> expr::Visitor<()> for Resolver<'a> {
 impl<'a> stmt::Visitor<()> for Resolver<'a> {
     fn visit_block_stmt(&mut self, stmt: &Block) -> anyhow::Result<(), LoxRuntime> {
         self.begin_scope();
-        self.resolve(stmt.statements())?;
+        self.resolve_statements(stmt.statements())?;
         self.end_scope();
         Ok(())
     }
 
+    fn visit_break_stmt(&mut self, stmt: &Break) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_loop_control(stmt.keyword(), stmt.label())
+    }
+
     fn visit_class_stmt(&mut self, stmt: &Class) -> anyhow::Result<(), LoxRuntime> {
-        self.declare(stmt.name())?;
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(stmt.name());
         self.define(stmt.name());
+
+        if let Some(superclass) = stmt.superclass() {
+            if let expr::Expr::Variable(variable) = superclass.as_ref()
+                && variable.name().lexeme() == stmt.name().lexeme() {
+                self.error(variable.name(), "A class cannot inherit from itself.".to_string());
+            }
+
+            self.current_class = ClassType::Subclass;
+            self.resolve_expr(superclass)?;
+
+            self.begin_scope();
+            self.define_implicit("super");
+        }
+
+        self.begin_scope();
+        self.define_implicit("this");
+
+        for method in stmt.methods() {
+            let declaration = if method.name().lexeme() == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, declaration)?;
+        }
+
+        self.end_scope();
+
+        if stmt.superclass().is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
         Ok(())
     }
 
+    fn visit_continue_stmt(&mut self, stmt: &Continue) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_loop_control(stmt.keyword(), stmt.label())
+    }
+
     fn visit_expression_stmt(&mut self, stmt: &Expression) -> anyhow::Result<(), LoxRuntime> {
         self.resolve_expr(stmt.expression())?;
         Ok(())
     }
 
     fn visit_function_stmt(&mut self, stmt: &Function) -> anyhow::Result<(), LoxRuntime> {
-        self.declare(stmt.name())?;
+        self.declare(stmt.name());
         self.define(stmt.name());
         
         self.resolve_function(stmt, FunctionType::Function)?;
@@ -201,6 +385,14 @@ impl<'a> stmt::Visitor<()> for Resolver<'a> {
         Ok(())
     }
 
+    fn visit_import_stmt(&mut self, stmt: &Import) -> anyhow::Result<(), LoxRuntime> {
+        for name in stmt.alias().iter().chain(stmt.names()) {
+            self.declare(name);
+            self.define(name);
+        }
+        Ok(())
+    }
+
     fn visit_print_stmt(&mut self, stmt: &Print) -> anyhow::Result<(), LoxRuntime> {
         self.resolve_expr(stmt.expression())?;
         Ok(())
@@ -208,20 +400,48 @@ impl<'a> stmt::Visitor<()> for Resolver<'a> {
 
     fn visit_return_stmt(&mut self, stmt: &Return) -> anyhow::Result<(), LoxRuntime> {
         if self.current_function == FunctionType::None {
-            return Err(LoxRuntime::Error(RuntimeError::new(
-                stmt.keyword().clone(),
-                "Cannot return from top-level code.".to_string(),
-            )));
+            self.error(stmt.keyword(), "Cannot return from top-level code.".to_string());
         }
         
         if let Some(value) = stmt.value() {
+            if self.current_function == FunctionType::Initializer {
+                self.error(stmt.keyword(), "Cannot return a value from an initializer.".to_string());
+            }
             self.resolve_expr(value)?;
         }
         Ok(())
     }
 
+    fn visit_throw_stmt(&mut self, stmt: &Throw) -> anyhow::Result<(), LoxRuntime> {
+        self.resolve_expr(stmt.value())?;
+        Ok(())
+    }
+
+    fn visit_try_stmt(&mut self, stmt: &Try) -> anyhow::Result<(), LoxRuntime> {
+        self.begin_scope();
+        self.resolve_statements(stmt.body())?;
+        self.end_scope();
+
+        if let Some(catch_body) = stmt.catch_body() {
+            self.begin_scope();
+            if let Some(name) = stmt.catch_name() {
+                self.declare(name);
+                self.define(name);
+            }
+            self.resolve_statements(catch_body)?;
+            self.end_scope();
+        }
+
+        if let Some(finally_body) = stmt.finally_body() {
+            self.begin_scope();
+            self.resolve_statements(finally_body)?;
+            self.end_scope();
+        }
+        Ok(())
+    }
+
     fn visit_var_stmt(&mut self, stmt: &Var) -> anyhow::Result<(), LoxRuntime> {
-        self.declare(stmt.name())?;
+        self.declare(stmt.name());
         if let Some(initializer) = stmt.initializer() {
             self.resolve_expr(initializer)?;
         }
@@ -230,8 +450,83 @@ impl<'a> stmt::Visitor<()> for Resolver<'a> {
     }
 
     fn visit_while_stmt(&mut self, stmt: &While) -> anyhow::Result<(), LoxRuntime> {
+        if let Some(label) = stmt.label()
+            && self.loops.iter().any(|l| l.as_deref() == Some(label.lexeme().as_str())) {
+            self.error(label, format!("Label '{}' is already used by an enclosing loop.", label.lexeme()));
+        }
+
+        self.loops.push(stmt.label().as_ref().map(|label| label.lexeme().clone()));
         self.resolve_expr(stmt.condition())?;
         self.resolve_stmt(stmt.body())?;
+        if let Some(increment) = stmt.increment() {
+            self.resolve_expr(increment)?;
+        }
+        self.loops.pop();
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, DiagnosticKind};
+    use crate::testing::{diagnostics, globals_both, run_both, static_error};
+
+    #[test]
+    fn loop_control_needs_an_enclosing_loop() {
+        assert_eq!(static_error("break;"), "Cannot use 'break' outside of a loop.");
+        assert_eq!(static_error("while (true) { continue missing; }"), "No enclosing loop labeled 'missing'.");
+    }
+
+    #[test]
+    fn nested_loops_cannot_reuse_a_label() {
+        let error = static_error("a: while (true) { a: while (true) { break a; } }");
+        assert_eq!(error, "Label 'a' is already used by an enclosing loop.");
+    }
+
+    #[test]
+    fn identical_expressions_resolve_by_where_they_are() {
+        let values = globals_both("
+            var a = \"global\";
+            var seen;
+            {
+                fun before() { return a; }
+                var a = \"local\";
+                fun after() { return a; }
+                seen = before() + \" \" + after() + \" \" + a;
+            }
+        ", &["seen", "a"]);
+        assert_eq!(values, ["global local local", "global"]);
+    }
+
+    #[test]
+    fn variables_on_one_line_resolve_to_their_own_scopes() {
+        let values = globals_both("var sum = 0; { var i = 0; while (i < 3) { var j = i; sum = sum + j; i = i + 1; } }", &["sum"]);
+        assert_eq!(values, ["3"]);
+    }
+
+    #[test]
+    fn every_static_error_is_reported() {
+        let source = "
+            print \"not run\";
+            return 1;
+            { var a = a; }
+            { var b = 1; var b = 2; }
+            class A < A {}
+            fun f() { print this; }
+        ";
+        for backend in [Backend::Tree, Backend::Vm] {
+            let diagnostics = diagnostics(backend, source);
+            let errors: Vec<_> = diagnostics.iter().map(|diagnostic| (diagnostic.line(), diagnostic.message().as_str())).collect();
+            assert_eq!(errors, [
+                (3, "Cannot return from top-level code."),
+                (4, "Cannot read local variable in its own initializer."),
+                (5, "Variable with this name already declared in this scope."),
+                (6, "A class cannot inherit from itself."),
+                (7, "Cannot use 'this' outside of a class."),
+            ], "{:?}", backend);
+            assert!(diagnostics.iter().all(|diagnostic| diagnostic.kind() == DiagnosticKind::Resolve), "{:?}", backend);
+            assert!(diagnostics.has_errors() && !diagnostics.has_runtime_errors(), "{:?}", backend);
+        }
+        assert!(!run_both(source).contains("not run"));
+    }
+}
@@ -1,15 +1,55 @@
+use std::rc::Rc;
+use crate::diagnostics::Note;
+use crate::span::Span;
+use crate::stack_frame::StackFrame;
 use crate::token::Token;
 
 #[derive(Debug, Clone)]
 pub enum LoxRuntime {
     Error(RuntimeError),
-    Return(RuntimeReturn)
+    Return(RuntimeReturn),
+    Throw(RuntimeThrow),
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+impl LoxRuntime {
+    /// Records that an error or exception unwound out of a call of a function declared in `file`.
+    pub fn push_frame(&mut self, frame: StackFrame, file: &Rc<str>) {
+        match self {
+            LoxRuntime::Error(error) => error.context_mut().push_frame(frame, file),
+            LoxRuntime::Throw(throw) => throw.context_mut().push_frame(frame, file),
+            LoxRuntime::Return(_) | LoxRuntime::Break(_) | LoxRuntime::Continue(_) => {},
+        }
+    }
+}
+
+/// What is known about an error besides where it was raised. Boxed, and only allocated when
+/// there is something to record, to keep errors small: they are returned through every visitor.
+#[derive(Debug, Clone, Default)]
+struct ErrorContext {
+    notes: Vec<Note>,
+    /// Calls the error unwound out of, innermost first.
+    traceback: Vec<StackFrame>,
+    /// File the error was raised in, known once it unwinds out of a call. Errors raised
+    /// outside of any call are in the file that is running.
+    file: Option<Rc<str>>,
+}
+
+impl ErrorContext {
+    fn push_frame(&mut self, frame: StackFrame, file: &Rc<str>) {
+        if self.file.is_none() {
+            self.file = Some(file.clone());
+        }
+        self.traceback.push(frame);
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RuntimeError {
     token: Token,
     message: String,
+    context: Option<Box<ErrorContext>>,
 }
 
 impl RuntimeError {
@@ -17,7 +57,27 @@ impl RuntimeError {
         RuntimeError {
             token,
             message,
+            context: None,
+        }
+    }
+
+    /// Adds a message pointing at related source, shown along with the error.
+    pub fn with_note(mut self, span: Span, message: &str) -> Self {
+        self.context_mut().notes.push(Note::new(span, message));
+        self
+    }
+
+    /// Starts the traceback with the calls another error unwound out of, for errors raised
+    /// because of it.
+    pub fn with_traceback(mut self, traceback: &[StackFrame]) -> Self {
+        if !traceback.is_empty() {
+            self.context_mut().traceback.extend_from_slice(traceback);
         }
+        self
+    }
+
+    fn context_mut(&mut self) -> &mut ErrorContext {
+        self.context.get_or_insert_with(Default::default)
     }
 
     pub fn token(&self) -> &Token {
@@ -27,6 +87,25 @@ impl RuntimeError {
     pub fn message(&self) -> &String {
         &self.message
     }
+
+    /// Where in the source the error is, taken from its token.
+    pub fn span(&self) -> &Span {
+        self.token.span()
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        self.context.as_ref().map_or(&[], |context| &context.notes)
+    }
+
+    /// Returns the calls the error unwound out of, innermost first.
+    pub fn traceback(&self) -> &[StackFrame] {
+        self.context.as_ref().map_or(&[], |context| &context.traceback)
+    }
+
+    /// Returns the file the error was raised in, if it unwound out of a call.
+    pub fn file(&self) -> Option<&str> {
+        self.context.as_ref().and_then(|context| context.file.as_deref())
+    }
 }
 
 impl std::fmt::Display for RuntimeError {
@@ -57,3 +136,52 @@ impl std::fmt::Display for RuntimeReturn {
         write!(f, "Return with value: {}", self.value)
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct RuntimeThrow {
+    token: Token,
+    value: crate::value::Value,
+    context: Option<Box<ErrorContext>>,
+}
+
+impl RuntimeThrow {
+    pub fn new(token: Token, value: crate::value::Value) -> Self {
+        RuntimeThrow {
+            token,
+            value,
+            context: None,
+        }
+    }
+
+    /// Turns an exception that was never caught into an error.
+    pub fn to_error(&self) -> RuntimeError {
+        RuntimeError {
+            token: self.token.clone(),
+            message: format!("Uncaught exception: {}", self.value),
+            context: self.context.clone(),
+        }
+    }
+
+    /// Returns the calls the exception unwound out of, innermost first.
+    pub fn traceback(&self) -> &[StackFrame] {
+        self.context.as_ref().map_or(&[], |context| &context.traceback)
+    }
+
+    fn context_mut(&mut self) -> &mut ErrorContext {
+        self.context.get_or_insert_with(Default::default)
+    }
+
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    pub fn value(&self) -> &crate::value::Value {
+        &self.value
+    }
+}
+
+impl std::fmt::Display for RuntimeThrow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Uncaught exception: {}", self.token.line(), self.value)
+    }
+}
@@ -1,35 +1,64 @@
 ﻿use crate::literal::LiteralValue;
-use crate::lox::Lox;
+use crate::diagnostics::{DiagnosticKind, Diagnostics};
+use crate::span::Span;
 use crate::token::Token;
 use crate::token_type::TokenType;
 
-pub struct Scanner {
+pub struct Scanner<'a> {
     source: String,
+    /// Byte offset of each character of the source, and of its end. `start` and `current`
+    /// count characters.
+    offsets: Vec<usize>,
     tokens: Vec<Token>,
 
     start: usize,
     current: usize,
     line: u32,
+    /// Index of the first character of the current line.
+    line_start: usize,
+    /// Line and column where the current token starts.
+    start_line: u32,
+    start_column: u32,
+
+    /// Open brace counts for each `${` interpolation we are currently inside of, together with
+    /// the kind of string to continue once it is closed.
+    interpolations: Vec<(usize, StringKind)>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StringKind {
+    Regular,
+    /// A triple-quoted string, with the common indentation to strip from each line.
+    Multiline(usize),
 }
 
-impl Scanner { 
-    pub fn new(source: String) -> Self {
+impl<'a> Scanner<'a> {
+    pub fn new(source: String, diagnostics: &'a mut Diagnostics) -> Self {
+        let offsets = source.char_indices().map(|(offset, _)| offset).chain([source.len()]).collect();
         Scanner {
             source,
+            offsets,
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            interpolations: Vec::new(),
+            diagnostics,
         }
     }
     
     pub fn scan_tokens(&mut self) -> Vec<Token> {
         while !self.is_at_end() {
-            self.start = self.current;
+            self.begin_token();
             self.scan_token();
         }
 
-        self.tokens.push(Token::new(TokenType::Eof, "".to_string(), None, self.line));
+        self.begin_token();
+        self.push_token(TokenType::Eof, "".to_string(), None);
         self.tokens.clone()
     }
 
@@ -38,8 +67,30 @@ impl Scanner {
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some((depth, _)) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace);
+            }
+            '}' => {
+                match self.interpolations.last_mut() {
+                    // This brace closes an interpolated expression, so the string continues.
+                    Some((0, kind)) => {
+                        let kind = *kind;
+                        self.interpolations.pop();
+                        self.string(kind);
+                    }
+                    Some((depth, _)) => {
+                        *depth -= 1;
+                        self.add_token(TokenType::RightBrace);
+                    }
+                    None => self.add_token(TokenType::RightBrace),
+                }
+            }
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
@@ -57,6 +108,8 @@ impl Scanner {
             '=' => {
                 let token_type = if self.match_char('=') {
                     TokenType::EqualEqual
+                } else if self.match_char('>') {
+                    TokenType::Arrow
                 } else {
                     TokenType::Equal
                 };
@@ -92,10 +145,19 @@ impl Scanner {
                 // Ignore whitespace.
             }
             '\n' => {
-                self.line += 1;
+                self.new_line();
             }
             '"' => {
-                self.string();
+                if self.peek() == '"' && self.peek_next() == '"' {
+                    self.current += 2;
+                    self.multiline_string();
+                } else {
+                    self.string(StringKind::Regular);
+                }
+            }
+            'r' if self.peek() == '"' => {
+                self.advance();
+                self.raw_string();
             }
             _ => {
                 if self.is_digit(c) {
@@ -105,14 +167,14 @@ impl Scanner {
                     self.identifier();
                 }
                 else {
-                    Lox::error_line(self.line, "Unexpected character.");
+                    self.error("Unexpected character.");
                 }
             }
         }
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.source.len()
+        self.current + 1 >= self.offsets.len()
     }
 
     fn peek(&self) -> char {
@@ -122,6 +184,10 @@ impl Scanner {
         self.source.chars().nth(self.current).unwrap()
     }
 
+    fn peek_next(&self) -> char {
+        self.source.chars().nth(self.current + 1).unwrap_or('\0')
+    }
+
     fn advance(&mut self) -> char {
         let c = self.source.chars().nth(self.current).unwrap();
         self.current += 1;
@@ -140,30 +206,189 @@ impl Scanner {
     }
 
     fn is_digit(&self, c: char) -> bool {
-        c >= '0' && c <= '9'
+        c.is_ascii_digit()
     }
     
-    fn string(&mut self) {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+    /// Scans a string literal, or the next segment of one after an interpolated expression.
+    /// A segment that ends with `${` is emitted as an `Interpolation` token and the scanner goes
+    /// back to scanning regular tokens until the matching `}`.
+    fn string(&mut self, kind: StringKind) {
+        let mut value = String::new();
+        // Where the current line of a multi-line string starts in `value`, while it is still blank.
+        let mut blank_line_start = None;
+
+        while !self.is_at_end() && !self.is_string_end(kind) {
+            let c = self.advance();
+            match c {
+                '\n' => {
+                    self.new_line();
+                    if let StringKind::Multiline(indent) = kind {
+                        blank_line_start = Some(value.len());
+                        self.skip_indent(indent);
+                    }
+                    value.push(c);
+                }
+                '\\' => {
+                    if let Some(escaped) = self.escape() {
+                        value.push(escaped);
+                    }
+                    blank_line_start = None;
+                }
+                '$' if self.peek() == '{' => {
+                    self.advance();
+                    self.push_token(TokenType::Interpolation, value.clone(), Some(LiteralValue::String(value)));
+                    self.interpolations.push((0, kind));
+                    return;
+                }
+                ' ' | '\t' | '\r' => value.push(c),
+                _ => {
+                    value.push(c);
+                    blank_line_start = None;
+                }
+            }
+        }
+
+        if self.is_at_end() {
+            self.error("Unterminated string.");
+            return;
+        }
+
+        // The closing quotes.
+        match kind {
+            StringKind::Regular => {
+                self.advance();
+            }
+            StringKind::Multiline(_) => {
+                self.current += 3;
+                // Drop the line holding only the closing delimiter.
+                if let Some(line_start) = blank_line_start {
+                    value.truncate(line_start);
+                }
+            }
+        }
+
+        self.push_token(TokenType::String, value.clone(), Some(LiteralValue::String(value)));
+    }
+
+    /// Scans the body of a triple-quoted string. The opening quotes have already been consumed.
+    fn multiline_string(&mut self) {
+        let indent = self.multiline_indent();
+
+        // A line break right after the opening quotes is not part of the string.
+        if self.peek() == '\n' {
+            self.advance();
+            self.new_line();
+            self.skip_indent(indent);
+        }
+
+        self.string(StringKind::Multiline(indent));
+    }
+
+    /// Computes the indentation shared by all non-blank lines of a triple-quoted string.
+    fn multiline_indent(&self) -> usize {
+        let rest: String = self.source.chars().skip(self.current).collect();
+        let body = match rest.find("\"\"\"") {
+            Some(end) => &rest[..end],
+            None => &rest,
+        };
+
+        // The first line continues the opening quotes, so it doesn't count.
+        body.split('\n')
+            .skip(1)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn skip_indent(&mut self, indent: usize) {
+        for _ in 0..indent {
+            if self.peek() != ' ' && self.peek() != '\t' {
+                break;
             }
             self.advance();
         }
+    }
+
+    fn is_string_end(&self, kind: StringKind) -> bool {
+        match kind {
+            StringKind::Regular => self.peek() == '"',
+            StringKind::Multiline(_) => self.peek() == '"' && self.peek_next() == '"' && self.source.chars().nth(self.current + 2) == Some('"'),
+        }
+    }
 
+    /// Decodes the escape sequence following a backslash. Invalid sequences are reported and skipped.
+    fn escape(&mut self) -> Option<char> {
         if self.is_at_end() {
-            Lox::error_line(self.line, "Unterminated string.");
+            return None;
+        }
+
+        let start = self.previous_position();
+        let c = self.advance();
+        match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' | '\\' | '\'' | '$' => Some(c),
+            'u' => self.unicode_escape(start),
+            _ => {
+                self.error_from(start, &format!("Invalid escape sequence '\\{}'.", c.escape_default()));
+                if c == '\n' {
+                    self.new_line();
+                }
+                None
+            }
+        }
+    }
+
+    /// Decodes a `\u{...}` escape sequence, which starts at `start`.
+    fn unicode_escape(&mut self, start: Span) -> Option<char> {
+        if !self.match_char('{') {
+            self.error_from(start, "Expect '{' after '\\u'.");
+            return None;
+        }
+
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() && digits.len() < 6 {
+            digits.push(self.advance());
+        }
+
+        if !self.match_char('}') {
+            self.error_from(start, "Expect '}' after unicode escape digits.");
+            return None;
+        }
+
+        match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+            Some(c) => Some(c),
+            None => {
+                self.error_from(start, &format!("Invalid unicode escape '\\u{{{}}}'.", digits));
+                None
+            }
+        }
+    }
+
+    /// Scans a raw string such as `r"C:\path"`, where backslashes and `${` have no special meaning.
+    fn raw_string(&mut self) {
+        let mut value = String::new();
+        while self.peek() != '"' && !self.is_at_end() {
+            let c = self.advance();
+            if c == '\n' {
+                self.new_line();
+            }
+            value.push(c);
+        }
+
+        if self.is_at_end() {
+            self.error("Unterminated string.");
             return;
         }
 
         // The closing ".
         self.advance();
-
-        // Trim the surrounding quotes.
-        let value: String = self.source[self.start + 1..self.current - 1].to_string();
-        self.tokens.push(Token::new(TokenType::String, value.clone(), Some(LiteralValue::String(value)), self.line));
+        self.push_token(TokenType::String, value.clone(), Some(LiteralValue::String(value)));
     }
-    
+
     fn number(&mut self) {
         while self.is_digit(self.peek()) {
             self.advance();
@@ -179,9 +404,9 @@ impl Scanner {
             }
         }
 
-        let value: String = self.source[self.start..self.current].to_string();
+        let value: String = self.lexeme();
         let number_value: f64 = value.parse().unwrap();
-        self.tokens.push(Token::new(TokenType::Number, value.clone(), Some(LiteralValue::Number(number_value)), self.line));
+        self.push_token(TokenType::Number, value.clone(), Some(LiteralValue::Number(number_value)));
     }
     
     fn identifier(&mut self) {
@@ -189,22 +414,29 @@ impl Scanner {
             self.advance();
         }
 
-        let text: String = self.source[self.start..self.current].to_string();
+        let text: String = self.lexeme();
         let token_type = match text.as_str() {
             "and" => TokenType::And,
+            "break" => TokenType::Break,
+            "catch" => TokenType::Catch,
             "class" => TokenType::Class,
+            "continue" => TokenType::Continue,
             "else" => TokenType::Else,
             "false" => TokenType::False,
+            "finally" => TokenType::Finally,
             "for" => TokenType::For,
             "fun" => TokenType::Fun,
             "if" => TokenType::If,
+            "import" => TokenType::Import,
             "nil" => TokenType::Nil,
             "or" => TokenType::Or,
             "print" => TokenType::Print,
             "return" => TokenType::Return,
             "super" => TokenType::Super,
             "this" => TokenType::This,
+            "throw" => TokenType::Throw,
             "true" => TokenType::True,
+            "try" => TokenType::Try,
             "var" => TokenType::Var,
             "while" => TokenType::While,
             _ => TokenType::Identifier,
@@ -213,7 +445,146 @@ impl Scanner {
     }
     
     fn add_token(&mut self, token_type: TokenType) {
-        let text: String = self.source[self.start..self.current].to_string();
-        self.tokens.push(Token::new(token_type, text, None, self.line));
+        let text: String = self.lexeme();
+        self.push_token(token_type, text, None);
+    }
+
+    /// Returns the text of the current token.
+    fn lexeme(&self) -> String {
+        self.source[self.offsets[self.start]..self.offsets[self.current]].to_string()
+    }
+
+    fn push_token(&mut self, token_type: TokenType, lexeme: String, literal: Option<LiteralValue>) {
+        let span = self.span();
+        self.tokens.push(Token::new(token_type, lexeme, literal, self.line).with_span(span));
+    }
+
+    /// Returns the span of the current token so far.
+    fn span(&self) -> Span {
+        Span::new(self.offsets[self.start], self.offsets[self.current], self.start_line, self.start_column)
+    }
+
+    fn error(&mut self, message: &str) {
+        let span = self.span();
+        self.diagnostics.error(DiagnosticKind::Syntax, self.line, span, message);
+    }
+
+    /// Reports an error about the source from `start` up to the current character, rather than
+    /// about the whole token.
+    fn error_from(&mut self, start: Span, message: &str) {
+        let end = self.offsets[self.current];
+        let span = Span::new(start.start(), end.max(start.end()), start.line(), start.column());
+        self.diagnostics.error(DiagnosticKind::Syntax, start.line(), span, message);
     }
-}
\ No newline at end of file
+
+    /// Returns an empty span at the previous character.
+    fn previous_position(&self) -> Span {
+        let index = self.current - 1;
+        Span::new(self.offsets[index], self.offsets[index], self.line, (index - self.line_start + 1) as u32)
+    }
+
+    fn begin_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = (self.start - self.line_start + 1) as u32;
+    }
+
+    /// Moves to the next line. Called after consuming the line break.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scanner;
+    use crate::diagnostics::Diagnostics;
+    use crate::testing::{globals_both, static_error};
+
+    #[test]
+    fn interpolated_expressions_are_evaluated_and_converted() {
+        let values = globals_both("
+            var name = \"Lox\";
+            var n = 2;
+            var greeting = \"Hello, ${name}!\";
+            var sum = \"${n} + ${n} = ${n + n}\";
+            var others = \"${nil} ${true} ${[1, 2]}\";
+        ", &["greeting", "sum", "others"]);
+        assert_eq!(values, ["Hello, Lox!", "2 + 2 = 4", "nil true [1, 2]"]);
+    }
+
+    #[test]
+    fn interpolations_nest_and_may_contain_braces() {
+        let values = globals_both("
+            var n = 2;
+            var nested = \"nested ${\"inner ${n * 3}\"} done\";
+            var map = \"map ${ {\"a\": 1}[\"a\"] }\";
+        ", &["nested", "map"]);
+        assert_eq!(values, ["nested inner 6 done", "map 1"]);
+    }
+
+    #[test]
+    fn escaped_dollar_is_not_interpolated() {
+        assert_eq!(globals_both("var s = \"cost \\${n}\";", &["s"]), ["cost ${n}"]);
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        let values = globals_both(r#"var s = "a\tb\\c\"d\u{48}\u{1F600}";"#, &["s"]);
+        assert_eq!(values, ["a\tb\\c\"dH\u{1F600}"]);
+    }
+
+    #[test]
+    fn raw_strings_keep_backslashes() {
+        assert_eq!(globals_both(r#"var s = r"C:\new\${x}";"#, &["s"]), ["C:\\new\\${x}"]);
+    }
+
+    #[test]
+    fn triple_quoted_strings_drop_common_indentation() {
+        let values = globals_both("var s = \"\"\"\n    one\n      two\n    \"\"\";", &["s"]);
+        assert_eq!(values, ["one\n  two"]);
+    }
+
+    #[test]
+    fn invalid_escapes_are_reported_at_the_escape() {
+        let source = "print \"ok \\q then\";";
+        let mut diagnostics = Diagnostics::new();
+        Scanner::new(source.to_string(), &mut diagnostics).scan_tokens();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.message(), "Invalid escape sequence '\\q'.");
+        let span = diagnostic.span().unwrap();
+        assert_eq!(&source[span.start()..span.end()], "\\q");
+        assert_eq!(span.column(), 11);
+    }
+
+    #[test]
+    fn invalid_unicode_escapes_are_reported_at_the_escape() {
+        let source = "print \"x \\u{110000} y\";";
+        let mut diagnostics = Diagnostics::new();
+        Scanner::new(source.to_string(), &mut diagnostics).scan_tokens();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.message(), "Invalid unicode escape '\\u{110000}'.");
+        let span = diagnostic.span().unwrap();
+        assert_eq!(&source[span.start()..span.end()], "\\u{110000}");
+    }
+
+    #[test]
+    fn unterminated_interpolation_is_an_error() {
+        assert_eq!(static_error("print \"a ${1"), "Expect '}' after interpolated expression.");
+    }
+
+    #[test]
+    fn tokens_record_byte_offsets_lines_and_character_columns() {
+        let source = "var é = \"ü\";\n  print é;";
+        let mut diagnostics = Diagnostics::new();
+        let tokens = Scanner::new(source.to_string(), &mut diagnostics).scan_tokens();
+        let spans: Vec<_> = tokens.iter()
+            .map(|token| (&source[token.span().start()..token.span().end()], token.span().line(), token.span().column()))
+            .collect();
+        assert_eq!(spans, [
+            ("var", 1, 1), ("é", 1, 5), ("=", 1, 7), ("\"ü\"", 1, 9), (";", 1, 12),
+            ("print", 2, 3), ("é", 2, 9), (";", 2, 10), ("", 2, 11),
+        ]);
+    }
+}
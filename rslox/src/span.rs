@@ -0,0 +1,60 @@
+/// A range of source code, as byte offsets into the source, together with the line and column
+/// where it starts. Lines and columns count from 1; columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+    start: usize,
+    end: usize,
+    line: u32,
+    column: u32,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: u32, column: u32) -> Self {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Byte offset of the first byte of the range.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset just past the last byte of the range.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the span from the start of this one to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..*self
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,145 @@
+use std::rc::Rc;
+
+/// A call of a Lox function that had not returned when an error was raised, as shown in
+/// tracebacks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    function: Rc<str>,
+    file: Rc<str>,
+    line: u32,
+}
+
+impl StackFrame {
+    pub fn new(function: Rc<str>, file: Rc<str>, line: u32) -> Self {
+        StackFrame {
+            function,
+            file,
+            line,
+        }
+    }
+
+    /// Name of the called function.
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// File the call was made from.
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Line the call was made from, or 0 if the host program made it.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub(crate) fn file_rc(&self) -> Rc<str> {
+        self.file.clone()
+    }
+}
+
+impl std::fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "in {}", self.function)
+        } else {
+            write!(f, "in {}, called from {}:{}", self.function, self.file, self.line)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{diagnostics, run_both, run_files};
+    use crate::{Backend, BufferOutput, Lox, LoxError};
+
+    #[test]
+    fn runtime_errors_show_the_calls_they_unwound_out_of() {
+        let output = run_both("
+            fun inner(x) {
+                return x + nil;
+            }
+            fun outer() { return inner(1); }
+            outer();
+        ");
+        assert!(output.contains("\n3 |                 return x + nil;\n  |                          ^\n"), "{}", output);
+        assert!(output.ends_with("
+traceback (innermost call first):
+  in inner, called from <stdin>:5
+  in outer, called from <stdin>:6
+"), "{}", output);
+    }
+
+    #[test]
+    fn uncaught_exceptions_show_the_calls_they_unwound_out_of() {
+        let output = run_both("
+            class Parser {
+                init(source) { this.parse(source); }
+                parse(source) { throw \"bad \" + source; }
+            }
+            var make = fun () { return Parser(\"x\"); };
+            make();
+        ");
+        assert!(output.starts_with("runtime error: Uncaught exception: bad x"), "{}", output);
+        assert!(output.ends_with("
+  in parse, called from <stdin>:3
+  in init, called from <stdin>:6
+  in anonymous, called from <stdin>:7
+"), "{}", output);
+    }
+
+    #[test]
+    fn recursion_is_shown_once_per_run_of_frames() {
+        let output = run_both("
+            fun countdown(n) {
+                if (n == 0) return nil + 1;
+                return countdown(n - 1);
+            }
+            countdown(10);
+        ");
+        assert!(output.ends_with("
+  in countdown, called from <stdin>:4
+  in countdown, called from <stdin>:4
+  in countdown, called from <stdin>:4
+  [previous frame repeated 7 more times]
+  in countdown, called from <stdin>:6
+"), "{}", output);
+    }
+
+    #[test]
+    fn tracebacks_name_the_files_of_imported_functions() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let output = run_files(backend, &[
+                ("main.lox", "import \"lib.lox\" as lib;\nfun run() { lib.fail(); }\nrun();"),
+                ("lib.lox", "fun fail() {\n  return nil - 1;\n}"),
+            ]);
+            let lines: Vec<_> = output.lines().collect();
+            assert_eq!(lines.len(), 5, "{:?}: {}", backend, output);
+            assert_eq!(lines[0], "runtime error: Operand must be a number.", "{:?}", backend);
+            assert!(lines[1].ends_with("lib.lox:2:14"), "{:?}: {}", backend, output);
+            assert!(lines[3].starts_with("  in fail, called from ") && lines[3].ends_with("main.lox:2"), "{:?}: {}", backend, output);
+            assert!(lines[4].starts_with("  in run, called from ") && lines[4].ends_with("main.lox:3"), "{:?}: {}", backend, output);
+        }
+    }
+
+    #[test]
+    fn vm_errors_point_at_the_offending_code() {
+        let source = "var list = [1, 2];\nprint list[2];";
+        let diagnostics = diagnostics(Backend::Vm, source);
+        let span = diagnostics.iter().next().unwrap().span().copied().unwrap();
+        assert_eq!((span.line(), span.column()), (2, 11));
+    }
+
+    #[test]
+    fn errors_in_calls_from_the_host_have_a_traceback() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let mut lox = Lox::new(backend);
+            lox.set_output(BufferOutput::new());
+            lox.run("fun fail() { return helper(); }\nfun helper() { throw \"no\"; }");
+            let error = lox.call("fail", Vec::new()).unwrap_err();
+            assert!(matches!(error, LoxError::Uncaught { line: 2, .. }), "{:?}: {}", backend, error);
+            let frames: Vec<_> = error.traceback().iter().map(|frame| (frame.function(), frame.line())).collect();
+            assert_eq!(frames, [("helper", 1), ("fail", 0)], "{:?}", backend);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 //[ Appendix II stmt
+#![allow(dead_code, clippy::vec_box, clippy::borrowed_box)]
 use crate::runtime_error::LoxRuntime;
+use crate::span::Span;
 use crate::token::Token;
 use crate::expr::Expr;
 use anyhow::Result;
@@ -7,58 +9,142 @@ use anyhow::Result;
 // Block
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Block {
+    span: Span,
     statements: Vec<Box<Stmt>>,
 }
 
 impl Block {
-    pub fn new(statements: Vec<Box<Stmt>>) -> Self {
+    pub fn new(span: Span, statements: Vec<Box<Stmt>>) -> Self {
         Block {
+            span,
             statements,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn statements(&self) -> &Vec<Box<Stmt>> {
         &self.statements
     }
 }
 
+// Break
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Break {
+    span: Span,
+    keyword: Token,
+    label: Option<Token>,
+}
+
+impl Break {
+    pub fn new(span: Span, keyword: Token, label: Option<Token>) -> Self {
+        Break {
+            span,
+            keyword,
+            label,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn keyword(&self) -> &Token {
+        &self.keyword
+    }
+
+    pub fn label(&self) -> &Option<Token> {
+        &self.label
+    }
+}
+
 // Class
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Class {
+    span: Span,
     name: Token,
+    superclass: Option<Box<Expr>>,
     methods: Vec<Box<Function>>,
 }
 
 impl Class {
-    pub fn new(name: Token, methods: Vec<Box<Function>>) -> Self {
+    pub fn new(span: Span, name: Token, superclass: Option<Box<Expr>>, methods: Vec<Box<Function>>) -> Self {
         Class {
+            span,
             name,
+            superclass,
             methods,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn name(&self) -> &Token {
         &self.name
     }
 
+    pub fn superclass(&self) -> &Option<Box<Expr>> {
+        &self.superclass
+    }
+
     pub fn methods(&self) -> &Vec<Box<Function>> {
         &self.methods
     }
 }
 
+// Continue
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Continue {
+    span: Span,
+    keyword: Token,
+    label: Option<Token>,
+}
+
+impl Continue {
+    pub fn new(span: Span, keyword: Token, label: Option<Token>) -> Self {
+        Continue {
+            span,
+            keyword,
+            label,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn keyword(&self) -> &Token {
+        &self.keyword
+    }
+
+    pub fn label(&self) -> &Option<Token> {
+        &self.label
+    }
+}
+
 // Expression
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Expression {
+    span: Span,
     expression: Box<Expr>,
 }
 
 impl Expression {
-    pub fn new(expression: Box<Expr>) -> Self {
+    pub fn new(span: Span, expression: Box<Expr>) -> Self {
         Expression {
+            span,
             expression,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn expression(&self) -> &Box<Expr> {
         &self.expression
     }
@@ -67,20 +153,26 @@ impl Expression {
 // Function
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Function {
+    span: Span,
     name: Token,
     params: Vec<Token>,
     body: Vec<Box<Stmt>>,
 }
 
 impl Function {
-    pub fn new(name: Token, params: Vec<Token>, body: Vec<Box<Stmt>>) -> Self {
+    pub fn new(span: Span, name: Token, params: Vec<Token>, body: Vec<Box<Stmt>>) -> Self {
         Function {
+            span,
             name,
             params,
             body,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn name(&self) -> &Token {
         &self.name
     }
@@ -97,20 +189,26 @@ impl Function {
 // If
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct If {
+    span: Span,
     condition: Box<Expr>,
     then_branch: Box<Stmt>,
     else_branch: Option<Box<Stmt>>,
 }
 
 impl If {
-    pub fn new(condition: Box<Expr>, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>>) -> Self {
+    pub fn new(span: Span, condition: Box<Expr>, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>>) -> Self {
         If {
+            span,
             condition,
             then_branch,
             else_branch,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn condition(&self) -> &Box<Expr> {
         &self.condition
     }
@@ -124,19 +222,61 @@ impl If {
     }
 }
 
+// Import
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Import {
+    span: Span,
+    path: Token,
+    alias: Option<Token>,
+    names: Vec<Token>,
+}
+
+impl Import {
+    pub fn new(span: Span, path: Token, alias: Option<Token>, names: Vec<Token>) -> Self {
+        Import {
+            span,
+            path,
+            alias,
+            names,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn path(&self) -> &Token {
+        &self.path
+    }
+
+    pub fn alias(&self) -> &Option<Token> {
+        &self.alias
+    }
+
+    pub fn names(&self) -> &Vec<Token> {
+        &self.names
+    }
+}
+
 // Print
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Print {
+    span: Span,
     expression: Box<Expr>,
 }
 
 impl Print {
-    pub fn new(expression: Box<Expr>) -> Self {
+    pub fn new(span: Span, expression: Box<Expr>) -> Self {
         Print {
+            span,
             expression,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn expression(&self) -> &Box<Expr> {
         &self.expression
     }
@@ -145,18 +285,24 @@ impl Print {
 // Return
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Return {
+    span: Span,
     keyword: Token,
     value: Option<Box<Expr>>,
 }
 
 impl Return {
-    pub fn new(keyword: Token, value: Option<Box<Expr>>) -> Self {
+    pub fn new(span: Span, keyword: Token, value: Option<Box<Expr>>) -> Self {
         Return {
+            span,
             keyword,
             value,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn keyword(&self) -> &Token {
         &self.keyword
     }
@@ -166,21 +312,99 @@ impl Return {
     }
 }
 
+// Throw
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Throw {
+    span: Span,
+    keyword: Token,
+    value: Box<Expr>,
+}
+
+impl Throw {
+    pub fn new(span: Span, keyword: Token, value: Box<Expr>) -> Self {
+        Throw {
+            span,
+            keyword,
+            value,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn keyword(&self) -> &Token {
+        &self.keyword
+    }
+
+    pub fn value(&self) -> &Box<Expr> {
+        &self.value
+    }
+}
+
+// Try
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Try {
+    span: Span,
+    body: Vec<Box<Stmt>>,
+    catch_name: Option<Token>,
+    catch_body: Option<Vec<Box<Stmt>>>,
+    finally_body: Option<Vec<Box<Stmt>>>,
+}
+
+impl Try {
+    pub fn new(span: Span, body: Vec<Box<Stmt>>, catch_name: Option<Token>, catch_body: Option<Vec<Box<Stmt>>>, finally_body: Option<Vec<Box<Stmt>>>) -> Self {
+        Try {
+            span,
+            body,
+            catch_name,
+            catch_body,
+            finally_body,
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn body(&self) -> &Vec<Box<Stmt>> {
+        &self.body
+    }
+
+    pub fn catch_name(&self) -> &Option<Token> {
+        &self.catch_name
+    }
+
+    pub fn catch_body(&self) -> &Option<Vec<Box<Stmt>>> {
+        &self.catch_body
+    }
+
+    pub fn finally_body(&self) -> &Option<Vec<Box<Stmt>>> {
+        &self.finally_body
+    }
+}
+
 // Var
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Var {
+    span: Span,
     name: Token,
     initializer: Option<Box<Expr>>,
 }
 
 impl Var {
-    pub fn new(name: Token, initializer: Option<Box<Expr>>) -> Self {
+    pub fn new(span: Span, name: Token, initializer: Option<Box<Expr>>) -> Self {
         Var {
+            span,
             name,
             initializer,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn name(&self) -> &Token {
         &self.name
     }
@@ -193,18 +417,28 @@ impl Var {
 // While
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct While {
+    span: Span,
     condition: Box<Expr>,
     body: Box<Stmt>,
+    increment: Option<Box<Expr>>,
+    label: Option<Token>,
 }
 
 impl While {
-    pub fn new(condition: Box<Expr>, body: Box<Stmt>) -> Self {
+    pub fn new(span: Span, condition: Box<Expr>, body: Box<Stmt>, increment: Option<Box<Expr>>, label: Option<Token>) -> Self {
         While {
+            span,
             condition,
             body,
+            increment,
+            label,
         }
     }
 
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
     pub fn condition(&self) -> &Box<Expr> {
         &self.condition
     }
@@ -212,18 +446,31 @@ impl While {
     pub fn body(&self) -> &Box<Stmt> {
         &self.body
     }
+
+    pub fn increment(&self) -> &Option<Box<Expr>> {
+        &self.increment
+    }
+
+    pub fn label(&self) -> &Option<Token> {
+        &self.label
+    }
 }
 
 // Expression enum
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub enum Stmt {
     Block(Block),
+    Break(Break),
     Class(Class),
+    Continue(Continue),
     Expression(Expression),
     Function(Function),
     If(If),
+    Import(Import),
     Print(Print),
     Return(Return),
+    Throw(Throw),
+    Try(Try),
     Var(Var),
     While(While),
 }
@@ -231,12 +478,17 @@ pub enum Stmt {
 // Visitor trait
 pub trait Visitor<T> {
     fn visit_block_stmt(&mut self, stmt: &Block) -> Result<T, LoxRuntime>;
+    fn visit_break_stmt(&mut self, stmt: &Break) -> Result<T, LoxRuntime>;
     fn visit_class_stmt(&mut self, stmt: &Class) -> Result<T, LoxRuntime>;
+    fn visit_continue_stmt(&mut self, stmt: &Continue) -> Result<T, LoxRuntime>;
     fn visit_expression_stmt(&mut self, stmt: &Expression) -> Result<T, LoxRuntime>;
     fn visit_function_stmt(&mut self, stmt: &Function) -> Result<T, LoxRuntime>;
     fn visit_if_stmt(&mut self, stmt: &If) -> Result<T, LoxRuntime>;
+    fn visit_import_stmt(&mut self, stmt: &Import) -> Result<T, LoxRuntime>;
     fn visit_print_stmt(&mut self, stmt: &Print) -> Result<T, LoxRuntime>;
     fn visit_return_stmt(&mut self, stmt: &Return) -> Result<T, LoxRuntime>;
+    fn visit_throw_stmt(&mut self, stmt: &Throw) -> Result<T, LoxRuntime>;
+    fn visit_try_stmt(&mut self, stmt: &Try) -> Result<T, LoxRuntime>;
     fn visit_var_stmt(&mut self, stmt: &Var) -> Result<T, LoxRuntime>;
     fn visit_while_stmt(&mut self, stmt: &While) -> Result<T, LoxRuntime>;
 }
@@ -246,16 +498,40 @@ impl Stmt {
     pub fn accept<T>(&self, visitor: &mut dyn Visitor<T>) -> Result<T, LoxRuntime> {
         match self {
             Stmt::Block(stmt) => visitor.visit_block_stmt(stmt),
+            Stmt::Break(stmt) => visitor.visit_break_stmt(stmt),
             Stmt::Class(stmt) => visitor.visit_class_stmt(stmt),
+            Stmt::Continue(stmt) => visitor.visit_continue_stmt(stmt),
             Stmt::Expression(stmt) => visitor.visit_expression_stmt(stmt),
             Stmt::Function(stmt) => visitor.visit_function_stmt(stmt),
             Stmt::If(stmt) => visitor.visit_if_stmt(stmt),
+            Stmt::Import(stmt) => visitor.visit_import_stmt(stmt),
             Stmt::Print(stmt) => visitor.visit_print_stmt(stmt),
             Stmt::Return(stmt) => visitor.visit_return_stmt(stmt),
+            Stmt::Throw(stmt) => visitor.visit_throw_stmt(stmt),
+            Stmt::Try(stmt) => visitor.visit_try_stmt(stmt),
             Stmt::Var(stmt) => visitor.visit_var_stmt(stmt),
             Stmt::While(stmt) => visitor.visit_while_stmt(stmt),
         }
     }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Stmt::Block(stmt) => stmt.span(),
+            Stmt::Break(stmt) => stmt.span(),
+            Stmt::Class(stmt) => stmt.span(),
+            Stmt::Continue(stmt) => stmt.span(),
+            Stmt::Expression(stmt) => stmt.span(),
+            Stmt::Function(stmt) => stmt.span(),
+            Stmt::If(stmt) => stmt.span(),
+            Stmt::Import(stmt) => stmt.span(),
+            Stmt::Print(stmt) => stmt.span(),
+            Stmt::Return(stmt) => stmt.span(),
+            Stmt::Throw(stmt) => stmt.span(),
+            Stmt::Try(stmt) => stmt.span(),
+            Stmt::Var(stmt) => stmt.span(),
+            Stmt::While(stmt) => stmt.span(),
+        }
+    }
 }
 
 //] Appendix II stmt
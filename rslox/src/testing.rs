@@ -0,0 +1,117 @@
+//! Helpers for tests that run Lox source.
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::{Backend, BufferOutput, Diagnostics, Lox};
+
+/// Runs source with the given backend and returns everything it printed, errors included.
+pub fn run(backend: Backend, source: &str) -> String {
+    let output = BufferOutput::new();
+    let mut lox = Lox::new(backend);
+    lox.set_output(output.clone());
+    lox.run(source);
+    output.take()
+}
+
+/// Runs source with the given backend and returns the errors found in it.
+pub fn diagnostics(backend: Backend, source: &str) -> Diagnostics {
+    let mut lox = Lox::new(backend);
+    lox.set_output(BufferOutput::new());
+    lox.run(source)
+}
+
+/// Runs source with both backends, checks that they print the same and returns it.
+pub fn run_both(source: &str) -> String {
+    let tree = run(Backend::Tree, source);
+    assert_eq!(tree, run(Backend::Vm, source), "the backends disagree on:\n{}", source);
+    tree
+}
+
+/// Runs source with both backends, checks that they agree and returns the values of the given
+/// global variables, formatted the way `print` shows them.
+pub fn globals_both(source: &str, names: &[&str]) -> Vec<String> {
+    globals_both_with(|_| {}, source, names)
+}
+
+/// Like `globals_both`, on interpreters set up by `setup`, e.g. with native functions.
+pub fn globals_both_with(setup: fn(&mut Lox), source: &str, names: &[&str]) -> Vec<String> {
+    let [tree, vm] = [Backend::Tree, Backend::Vm].map(|backend| {
+        let output = BufferOutput::new();
+        let mut lox = Lox::new(backend);
+        lox.set_output(output.clone());
+        setup(&mut lox);
+        let diagnostics = lox.run(source);
+        assert!(diagnostics.is_empty(), "{:?}: {}", backend, output.take());
+        print_globals(&mut lox, &output, names)
+    });
+    assert_eq!(tree, vm, "the backends disagree on:\n{}", source);
+    tree
+}
+
+/// Writes `files` into a new temporary directory, runs the first one with the given backend and
+/// returns everything it printed.
+pub fn run_files(backend: Backend, files: &[(&str, &str)]) -> String {
+    let directory = write_files(files);
+    let (main, source) = files[0];
+    let output = BufferOutput::new();
+    let mut lox = Lox::new(backend);
+    lox.set_output(output.clone());
+    lox.set_script_path(&directory.join(main).display().to_string());
+    lox.run(source);
+    std::fs::remove_dir_all(&directory).unwrap();
+    output.take()
+}
+
+/// Writes `files` into a new temporary directory, runs the first one with both backends, checks
+/// that they agree and returns the values of the given global variables of it.
+pub fn file_globals(files: &[(&str, &str)], names: &[&str]) -> Vec<String> {
+    let directory = write_files(files);
+    let (main, source) = files[0];
+    let [tree, vm] = [Backend::Tree, Backend::Vm].map(|backend| {
+        let output = BufferOutput::new();
+        let mut lox = Lox::new(backend);
+        lox.set_output(output.clone());
+        lox.set_script_path(&directory.join(main).display().to_string());
+        let diagnostics = lox.run(source);
+        assert!(diagnostics.is_empty(), "{:?}: {}", backend, output.take());
+        print_globals(&mut lox, &output, names)
+    });
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(tree, vm, "the backends disagree on:\n{:?}", files);
+    tree
+}
+
+/// Writes each file, named by a path relative to a new temporary directory, and returns the
+/// directory.
+fn write_files(files: &[(&str, &str)]) -> PathBuf {
+    static NEXT_DIRECTORY: AtomicUsize = AtomicUsize::new(0);
+    let directory = std::env::temp_dir().join(format!(
+        "rslox-test-{}-{}",
+        std::process::id(),
+        NEXT_DIRECTORY.fetch_add(1, Ordering::Relaxed),
+    ));
+    for (name, source) in files {
+        let path = directory.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+    directory
+}
+
+/// Prints each global variable in a run of its own and returns what was printed.
+fn print_globals(lox: &mut Lox, output: &BufferOutput, names: &[&str]) -> Vec<String> {
+    output.take();
+    names.iter()
+        .map(|name| {
+            lox.run(&format!("print {};", name));
+            output.take().trim_end_matches('\n').to_string()
+        })
+        .collect()
+}
+
+/// Parses and resolves source and returns the message of the first error found in it.
+pub fn static_error(source: &str) -> String {
+    let diagnostics = diagnostics(Backend::Tree, source);
+    let diagnostic = diagnostics.iter().next().expect("The source has errors.");
+    assert!(diagnostics.has_errors(), "The source has no static errors: {}", diagnostic);
+    diagnostic.message().clone()
+}
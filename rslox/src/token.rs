@@ -1,5 +1,6 @@
 ﻿use std::hash::Hash;
 use crate::literal::LiteralValue;
+use crate::span::Span;
 pub(crate) use crate::token_type::TokenType;
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
@@ -8,6 +9,7 @@ pub struct Token {
     lexeme: String,
     literal: Option<LiteralValue>,
     line: u32,
+    span: Span,
 } 
 
 impl Token {
@@ -17,8 +19,15 @@ impl Token {
             lexeme,
             literal,
             line,
+            span: Span::default(),
         }
     }
+
+    /// Sets where the token is in the source. Tokens made up by the interpreter have an empty span.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
     
     pub fn token_type(&self) -> &TokenType {
         &self.token_type
@@ -35,6 +44,10 @@ impl Token {
     pub fn line(&self) -> u32 {
         self.line
     }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 impl std::fmt::Display for Token {
@@ -5,6 +5,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -14,6 +17,7 @@ pub enum TokenType {
     Star,
 
     // One or two character tokens.
+    Arrow,
     Bang,
     BangEqual,
     Equal,
@@ -26,23 +30,31 @@ pub enum TokenType {
     // Literals.
     Identifier,
     String,
+    Interpolation,
     Number,
 
     // Keywords.
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
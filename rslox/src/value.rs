@@ -1,19 +1,142 @@
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
+use crate::gc::GcRef;
+use crate::lox::LoxError;
 use crate::lox_callable::LoxCallable;
 use crate::lox_class::LoxClass;
 use crate::lox_instance::LoxInstance;
+use crate::lox_map::LoxMap;
+use crate::lox_module::LoxModule;
+use crate::lox_native_class::NativeInstance;
+use crate::vm_object::VmObject;
 
+/// A value of a Lox program.
+///
+/// Numbers, booleans, strings, nil, lists and native instances are the same on both backends.
+/// The other variants are how each backend represents functions, classes, instances, maps and
+/// modules, and are not part of the public interface; `is_callable`, `is_instance` and
+/// `is_module` tell those objects apart on either backend.
 #[derive(Clone)]
-pub enum
-Value {
+#[non_exhaustive]
+pub enum Value {
     Number(f64),
     Boolean(bool),
     String(String),
     Nil,
+    #[doc(hidden)]
     LoxCallable(Rc<dyn LoxCallable>),
+    #[doc(hidden)]
     LoxClass(Rc<LoxClass>),
+    #[doc(hidden)]
     LoxInstance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    #[doc(hidden)]
+    Map(Rc<RefCell<LoxMap>>),
+    #[doc(hidden)]
+    LoxModule(Rc<LoxModule>),
+    NativeInstance(Rc<NativeInstance>),
+    #[doc(hidden)]
+    VmObject(VmObject),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Boolean(b) => *b,
+            _ => true,
+        }
+    }
+
+    /// Returns true for functions, methods, classes and native functions.
+    pub fn is_callable(&self) -> bool {
+        match self {
+            Value::LoxCallable(_) | Value::LoxClass(_) => true,
+            Value::VmObject(object) => matches!(
+                object,
+                VmObject::Function(_) | VmObject::Closure(_) | VmObject::Class(_) | VmObject::BoundMethod(_),
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns true for instances of classes, whether defined by the script or by the host.
+    pub fn is_instance(&self) -> bool {
+        matches!(self, Value::LoxInstance(_) | Value::NativeInstance(_) | Value::VmObject(VmObject::Instance(_)))
+    }
+
+    /// Returns true for imported modules.
+    pub fn is_module(&self) -> bool {
+        matches!(self, Value::LoxModule(_) | Value::VmObject(VmObject::Module(_)))
+    }
+
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Nil, _) => false,
+            (Value::Number(x), Value::Number(y)) => x == y,
+            (Value::Boolean(x), Value::Boolean(y)) => x == y,
+            (Value::String(x), Value::String(y)) => x == y,
+            (Value::List(x), Value::List(y)) => Rc::ptr_eq(x, y),
+            (Value::Map(x), Value::Map(y)) => Rc::ptr_eq(x, y),
+            (Value::LoxCallable(x), Value::LoxCallable(y)) => Rc::ptr_eq(x, y),
+            (Value::LoxClass(x), Value::LoxClass(y)) => Rc::ptr_eq(x, y),
+            (Value::LoxInstance(x), Value::LoxInstance(y)) => Rc::ptr_eq(x, y),
+            (Value::LoxModule(x), Value::LoxModule(y)) => Rc::ptr_eq(x, y),
+            (Value::NativeInstance(x), Value::NativeInstance(y)) => Rc::ptr_eq(x, y),
+            (Value::VmObject(x), Value::VmObject(y)) => x.address() == y.address(),
+            _ => false,
+        }
+    }
+
+    /// Adds the heap object this value refers to, if any.
+    pub fn trace(&self, refs: &mut Vec<GcRef>) {
+        match self {
+            Value::LoxCallable(callable) => refs.push(GcRef::Callable(Rc::downgrade(callable))),
+            Value::LoxClass(class_) => refs.push(GcRef::Class(Rc::downgrade(class_))),
+            Value::LoxInstance(instance) => refs.push(GcRef::Instance(Rc::downgrade(instance))),
+            Value::List(list) => refs.push(GcRef::List(Rc::downgrade(list))),
+            Value::Map(map) => refs.push(GcRef::Map(Rc::downgrade(map))),
+            Value::LoxModule(module) => refs.push(GcRef::Module(Rc::downgrade(module))),
+            // Native instances wrap Rust data, which cannot refer to Lox values.
+            Value::Number(_) | Value::Boolean(_) | Value::String(_) | Value::Nil | Value::NativeInstance(_) | Value::VmObject(_) => {},
+        }
+    }
+
+    fn fmt_list(f: &mut std::fmt::Formatter<'_>, list: &Rc<RefCell<Vec<Value>>>) -> std::fmt::Result {
+        fmt_once(f, Rc::as_ptr(list) as usize, "[...]", |f| {
+            write!(f, "[")?;
+            for (i, element) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", element)?;
+            }
+            write!(f, "]")
+        })
+    }
+}
+
+thread_local! {
+    /// Addresses of the lists and maps being formatted.
+    static FORMATTING: RefCell<HashSet<usize>> = RefCell::new(HashSet::new());
+}
+
+/// Formats the list or map at `address` with `fmt`, unless it is already being formatted
+/// because it contains itself, in which case `placeholder` is written instead.
+pub(crate) fn fmt_once(
+    f: &mut std::fmt::Formatter<'_>,
+    address: usize,
+    placeholder: &str,
+    fmt: impl FnOnce(&mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+) -> std::fmt::Result {
+    if !FORMATTING.with(|formatting| formatting.borrow_mut().insert(address)) {
+        return write!(f, "{}", placeholder);
+    }
+    let result = fmt(f);
+    FORMATTING.with(|formatting| formatting.borrow_mut().remove(&address));
+    result
 }
 
 impl std::fmt::Display for Value {
@@ -26,6 +149,11 @@ impl std::fmt::Display for Value {
             Value::LoxCallable(callable) => write!(f, "{}", callable),
             Value::LoxClass(class) => write!(f, "{}", class),
             Value::LoxInstance(instance) => write!(f, "{}", instance.borrow()),
+            Value::List(list) => Value::fmt_list(f, list),
+            Value::Map(map) => write!(f, "{}", map.borrow()),
+            Value::LoxModule(module) => write!(f, "{}", module),
+            Value::NativeInstance(instance) => write!(f, "{}", instance),
+            Value::VmObject(object) => write!(f, "{}", object),
         }
     }
 }
@@ -40,6 +168,176 @@ impl std::fmt::Debug for Value {
             Value::LoxCallable(callable) => write!(f, "LoxCallable({})", callable),
             Value::LoxClass(class) => write!(f, "LoxClass({})", class),
             Value::LoxInstance(instance) => write!(f, "LoxInstance({})", instance.borrow()),
+            Value::List(list) => {
+                write!(f, "List(")?;
+                Value::fmt_list(f, list)?;
+                write!(f, ")")
+            },
+            Value::Map(map) => write!(f, "Map({})", map.borrow()),
+            Value::LoxModule(module) => write!(f, "LoxModule({})", module),
+            Value::NativeInstance(instance) => write!(f, "NativeInstance({})", instance),
+            Value::VmObject(object) => write!(f, "VmObject({})", object),
+        }
+    }
+}
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Self {
+        Value::List(Rc::new(RefCell::new(elements.into_iter().map(Into::into).collect())))
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(n),
+            _ => Err(LoxError::Type { expected: "a number", value }),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(LoxError::Type { expected: "a boolean", value }),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            _ => Err(LoxError::Type { expected: "a string", value }),
+        }
+    }
+}
+
+impl<T: TryFrom<Value, Error = LoxError>> TryFrom<Value> for Vec<T> {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::List(list) => list.borrow().iter().cloned().map(T::try_from).collect(),
+            _ => Err(LoxError::Type { expected: "a list", value }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::lox::LoxError;
+    use crate::testing::run_both;
+    use crate::value::Value;
+    use crate::{Backend, Lox};
+
+    #[test]
+    fn values_convert_to_and_from_rust_types() {
+        assert_eq!(f64::try_from(Value::from(1.5)).unwrap(), 1.5);
+        assert!(bool::try_from(Value::from(true)).unwrap());
+        assert_eq!(String::try_from(Value::from("text")).unwrap(), "text");
+        assert!(matches!(Value::from(None::<f64>), Value::Nil));
+        let numbers: Vec<f64> = Vec::try_from(Value::from(vec![1.0, 2.0])).unwrap();
+        assert_eq!(numbers, [1.0, 2.0]);
+    }
+
+    #[test]
+    fn converting_to_the_wrong_type_is_an_error() {
+        let error = f64::try_from(Value::from("one")).unwrap_err();
+        assert!(matches!(error, LoxError::Type { expected: "a number", .. }));
+        assert_eq!(error.to_string(), "Expected a number but got 'one'.");
+        let error = Vec::<String>::try_from(Value::from(vec![1.0])).unwrap_err();
+        assert_eq!(error.to_string(), "Expected a string but got '1'.");
+    }
+
+    #[test]
+    fn objects_are_equal_only_to_themselves() {
+        let output = run_both("
+            class Foo {}
+            fun f() {}
+            var q = Foo();
+            print q == q;
+            print q == Foo();
+            print Foo == Foo;
+            print f == f;
+            print q != q;
+        ");
+        assert_eq!(output, "true\nfalse\ntrue\ntrue\nfalse\n");
+    }
+
+    #[test]
+    fn lists_containing_themselves_are_printed_once() {
+        let output = run_both("
+            var xs = [1];
+            xs.push(xs);
+            print xs;
+            print \"${xs}\";
+            var ys = [xs, xs];
+            print ys;
+        ");
+        assert_eq!(output, "[1, [...]]\n[1, [...]]\n[[1, [...]], [1, [...]]]\n");
+    }
+
+    #[test]
+    fn lists_containing_themselves_are_debug_formatted_once() {
+        let list = Rc::new(RefCell::new(vec![Value::Number(1.0)]));
+        list.borrow_mut().push(Value::List(list.clone()));
+        let value = Value::List(list);
+        assert_eq!(format!("{:?}", value), "List([1, [...]])");
+        assert_eq!(value.to_string(), "[1, [...]]");
+    }
+
+    #[test]
+    fn objects_are_told_apart_the_same_way_on_both_backends() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let mut lox = Lox::new(backend);
+            lox.run("class Foo { bar() {} } fun f() {} var foo = Foo(); var bar = foo.bar; var now = clock; var xs = [];");
+            let kinds: Vec<_> = ["Foo", "f", "bar", "now", "foo", "xs"].iter()
+                .map(|name| {
+                    let value = lox.get_global(name).unwrap();
+                    (value.is_callable(), value.is_instance(), value.is_module())
+                })
+                .collect();
+            let callable = (true, false, false);
+            assert_eq!(kinds, [callable, callable, callable, callable, (false, true, false), (false, false, false)], "{:?}", backend);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,817 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::chunk::OpCode;
+use crate::compiler::Compiler;
+use crate::diagnostics::{Diagnostics, ErrorFormat};
+use crate::interpreter::Interpreter;
+use crate::literal::LiteralValue;
+use crate::lox_callable::Arity;
+use crate::lox_list::{list_index, slice_bound, ListMethod};
+use crate::lox_map::{LoxMap, MapKey, MapMethod};
+use crate::lox_module;
+use crate::lox_native_class::NativeInstance;
+use crate::output::Output;
+use crate::runtime_error::{LoxRuntime, RuntimeError, RuntimeThrow};
+use crate::stack_frame::StackFrame;
+use crate::token::{Token, TokenType};
+use crate::value::Value;
+use crate::vm_object::{BoundMethod, Closure, Upvalue, VmClass, VmFunction, VmInstance, VmModule, VmObject};
+
+/// How deep calls may nest unless configured otherwise. Frames live on the heap, so this is far
+/// deeper than the tree backend allows and only stops runaway recursion.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100_000;
+
+/// Stack-based virtual machine that runs the bytecode produced by the `Compiler`.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    /// Native functions, visible in every module unless it defines a global of the same name.
+    builtins: HashMap<String, Value>,
+    /// The module of the scripts run by `interpret`, which keeps their globals across runs.
+    main: Rc<VmModule>,
+    /// Imported modules by canonical path. Each module runs once, the first time it is imported.
+    modules: HashMap<PathBuf, Rc<VmModule>>,
+    /// The files being run, from the main script to the innermost import.
+    import_stack: Vec<PathBuf>,
+    /// Upvalues that still point into the stack, ordered by slot.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    /// What handlers caught, by the stack slot the caught value was put in. Finally blocks
+    /// unwind with it again, so that errors keep their message, location and traceback.
+    caught: HashMap<usize, LoxRuntime>,
+    /// Native callables are written against the tree-walking interpreter. They are passed this
+    /// one when called. It also resolves the variables of imported modules, and has the output
+    /// and error format their syntax errors are reported with.
+    host: Interpreter,
+    /// Class of the objects that native runtime errors are turned into when they are caught.
+    runtime_error_class: Rc<VmClass>,
+    /// Where `print` statements and runtime errors are written.
+    output: Rc<RefCell<dyn Output>>,
+    /// Number of nested calls at which further calls fail with a stack overflow.
+    max_call_depth: usize,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of the frame's slot 0.
+    base: usize,
+}
+
+/// An active try or finally block.
+struct Handler {
+    frame: usize,
+    stack_height: usize,
+    target: usize,
+}
+
+impl Vm {
+    /// Creates a VM whose globals start out as the given builtins.
+    pub fn new(builtins: Vec<(String, Value)>, output: Rc<RefCell<dyn Output>>) -> Self {
+        let mut host = Interpreter::new();
+        host.set_output(output.clone());
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            builtins: builtins.into_iter().collect(),
+            main: Rc::new(VmModule::new("main".to_string(), Rc::from("<stdin>"))),
+            modules: HashMap::new(),
+            import_stack: Vec::new(),
+            open_upvalues: Vec::new(),
+            handlers: Vec::new(),
+            caught: HashMap::new(),
+            host,
+            runtime_error_class: Rc::new(VmClass::new("RuntimeError".to_string())),
+            output,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    pub fn set_output(&mut self, output: Rc<RefCell<dyn Output>>) {
+        self.host.set_output(output.clone());
+        self.output = output;
+    }
+
+    /// Sets how errors in imported modules are written.
+    pub fn set_error_format(&mut self, error_format: ErrorFormat) {
+        self.host.set_error_format(error_format);
+    }
+
+    /// Sets the file the main script was loaded from. Imports are resolved relative to it.
+    pub fn set_script_path(&mut self, path: &Path) {
+        self.import_stack = vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())];
+        self.main.set_file(Rc::from(path.display().to_string()));
+    }
+
+    /// Sets how deep calls may nest before they fail with a stack overflow error.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    pub fn define_builtin(&mut self, name: &str, value: Value) {
+        self.builtins.insert(name.to_string(), value);
+    }
+
+    /// Returns the value of a global variable of the main script.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.main.global(name)
+    }
+
+    /// Defines a global variable of the main script, or assigns it if it is already defined.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.main.define_global(name.to_string(), value);
+    }
+
+    /// Runs a compiled script, recording the runtime error that stops it, if any, in `diagnostics`.
+    pub fn interpret(&mut self, function: Rc<VmFunction>, diagnostics: &mut Diagnostics) {
+        if let Err(unwind) = self.evaluate(function) {
+            diagnostics.runtime_error(&Self::unwind_error(unwind));
+        }
+    }
+
+    /// Runs a compiled script and returns the value its top-level code returns.
+    pub fn evaluate(&mut self, function: Rc<VmFunction>) -> Result<Value, LoxRuntime> {
+        let closure = Rc::new(Closure::new(function, Vec::new(), self.main.clone()));
+        self.stack.push(Value::VmObject(VmObject::Closure(closure)));
+        self.run_call(0)
+    }
+
+    /// Calls a global function or class of the main script, or a builtin, from outside of any
+    /// script.
+    pub fn call_global(&mut self, name: &str, arguments: Vec<Value>) -> Result<Value, LoxRuntime> {
+        let callee = match self.main.global(name).or_else(|| self.builtins.get(name).cloned()) {
+            Some(callee) => callee,
+            None => return Err(self.error(format!("Undefined variable '{}'.", name))),
+        };
+        let argument_count = arguments.len();
+        self.stack.push(callee);
+        self.stack.extend(arguments);
+        self.run_call(argument_count)
+    }
+
+    /// Calls the callee below the given number of arguments on top of the stack, and runs until
+    /// the call returns. Returns the result, or the error that was not caught within the call,
+    /// after dropping what the call left on the stack.
+    fn run_call(&mut self, argument_count: usize) -> Result<Value, LoxRuntime> {
+        let depth = self.frames.len();
+        let stack_height = self.stack.len() - 1 - argument_count;
+        let handlers = self.handlers.len();
+
+        let mut result = self.call_value(argument_count);
+        // Native functions and classes without an initializer have returned already.
+        if result.is_ok() && self.frames.len() > depth {
+            result = self.run(depth);
+        }
+        match result {
+            Ok(()) => Ok(self.stack.pop().unwrap()),
+            Err(mut unwind) => {
+                self.record_frames(&mut unwind, depth);
+                self.frames.truncate(depth);
+                self.close_upvalues(stack_height);
+                self.stack.truncate(stack_height);
+                self.handlers.truncate(handlers);
+                Err(unwind)
+            },
+        }
+    }
+
+    /// Records the calls of the frames above the first `depth` ones in the traceback of what
+    /// unwinds out of them, innermost first, as the tree-walking interpreter does.
+    fn record_frames(&self, unwind: &mut LoxRuntime, depth: usize) {
+        for index in (depth..self.frames.len()).rev() {
+            let closure = &self.frames[index].closure;
+            // The top-level code of scripts and modules is not a call.
+            let Some(name) = closure.function().name() else {
+                continue;
+            };
+            let (caller_file, line) = match index.checked_sub(1).map(|caller| &self.frames[caller]) {
+                Some(caller) => (caller.closure.module().file(), caller.closure.function().chunk().line(caller.ip - 1)),
+                // Called by the host program.
+                None => (self.main.file(), 0),
+            };
+            unwind.push_frame(StackFrame::new(Rc::from(name), caller_file, line), &closure.module().file());
+        }
+    }
+
+    /// Converts anything that unwinds out of a script into an error.
+    fn unwind_error(unwind: LoxRuntime) -> RuntimeError {
+        match unwind {
+            LoxRuntime::Error(error) => error,
+            LoxRuntime::Throw(throw) => throw.to_error(),
+            LoxRuntime::Return(_) | LoxRuntime::Break(_) | LoxRuntime::Continue(_) => {
+                unreachable!("The VM returns and loops with jumps.")
+            },
+        }
+    }
+
+    /// Runs until the frames above the first `depth` ones have returned.
+    fn run(&mut self, depth: usize) -> Result<(), LoxRuntime> {
+        loop {
+            if let Err(unwind) = self.step() {
+                self.catch(unwind, depth)?;
+            }
+            if self.frames.len() == depth {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Transfers control to the innermost handler of the frames above the first `depth` ones,
+    /// or gives the error back if there is none.
+    fn catch(&mut self, mut unwind: LoxRuntime, depth: usize) -> Result<(), LoxRuntime> {
+        let value = match &unwind {
+            LoxRuntime::Error(error) => self.error_value(error),
+            LoxRuntime::Throw(throw) => throw.value().clone(),
+            _ => return Err(unwind),
+        };
+        let handler = match self.handlers.pop_if(|handler| handler.frame >= depth) {
+            Some(handler) => handler,
+            None => return Err(unwind),
+        };
+
+        // A finally block may unwind with it again.
+        self.record_frames(&mut unwind, handler.frame + 1);
+        self.frames.truncate(handler.frame + 1);
+        self.close_upvalues(handler.stack_height);
+        self.stack.truncate(handler.stack_height);
+        self.stack.push(value);
+        self.caught.insert(handler.stack_height, unwind);
+        self.frames.last_mut().unwrap().ip = handler.target;
+        Ok(())
+    }
+
+    /// Turns a native runtime error into an object that can be caught, with `message` and `line` fields.
+    fn error_value(&self, error: &RuntimeError) -> Value {
+        let mut instance = VmInstance::new(self.runtime_error_class.clone());
+        instance.set_field("message".to_string(), Value::String(error.message().clone()));
+        instance.set_field("line".to_string(), Value::Number(error.token().line() as f64));
+        Value::VmObject(VmObject::Instance(Rc::new(RefCell::new(instance))))
+    }
+
+    fn step(&mut self) -> Result<(), LoxRuntime> {
+        let op = OpCode::from_byte(self.read_byte());
+        match op {
+            OpCode::Constant => {
+                let constant = self.read_constant();
+                self.stack.push(constant);
+            },
+            OpCode::Nil => self.stack.push(Value::Nil),
+            OpCode::True => self.stack.push(Value::Boolean(true)),
+            OpCode::False => self.stack.push(Value::Boolean(false)),
+            OpCode::Pop => {
+                self.stack.pop();
+            },
+            OpCode::GetLocal => {
+                let slot = self.read_byte() as usize;
+                let value = self.stack[self.frame().base + slot].clone();
+                self.stack.push(value);
+            },
+            OpCode::SetLocal => {
+                let slot = self.read_byte() as usize;
+                let base = self.frame().base;
+                self.stack[base + slot] = self.peek(0).clone();
+            },
+            OpCode::GetGlobal => {
+                // Looked up through a borrow of the constant, since this is the hottest name lookup.
+                let index = self.read_short();
+                let closure = &self.frame().closure;
+                let name = match closure.function().chunk().constant(index) {
+                    Value::String(name) => name,
+                    _ => unreachable!("Names are string constants."),
+                };
+                match closure.module().global(name).or_else(|| self.builtins.get(name).cloned()) {
+                    Some(value) => self.stack.push(value),
+                    None => return Err(self.error(format!("Undefined variable '{}'.", name))),
+                }
+            },
+            OpCode::DefineGlobal => {
+                let name = self.read_string();
+                let value = self.stack.pop().unwrap();
+                self.frame().closure.module().define_global(name, value);
+            },
+            OpCode::SetGlobal => {
+                let name = self.read_string();
+                let value = self.peek(0).clone();
+                if !self.frame().closure.module().assign_global(&name, value.clone()) {
+                    match self.builtins.get_mut(&name) {
+                        Some(builtin) => *builtin = value,
+                        None => return Err(self.error(format!("Undefined variable '{}'.", name))),
+                    }
+                }
+            },
+            OpCode::GetUpvalue => {
+                let index = self.read_byte() as usize;
+                let upvalue = self.frame().closure.upvalue(index).clone();
+                let value = match &*upvalue.borrow() {
+                    Upvalue::Open(slot) => self.stack[*slot].clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                };
+                self.stack.push(value);
+            },
+            OpCode::SetUpvalue => {
+                let index = self.read_byte() as usize;
+                let upvalue = self.frame().closure.upvalue(index).clone();
+                let value = self.peek(0).clone();
+                match &mut *upvalue.borrow_mut() {
+                    Upvalue::Open(slot) => self.stack[*slot] = value,
+                    Upvalue::Closed(closed) => *closed = value,
+                }
+            },
+            OpCode::GetProperty => {
+                let name = self.read_string();
+                let object = self.stack.pop().unwrap();
+                let value = self.get_property(object, &name)?;
+                self.stack.push(value);
+            },
+            OpCode::SetProperty => {
+                let name = self.read_string();
+                let value = self.stack.pop().unwrap();
+                match self.stack.pop().unwrap() {
+                    Value::VmObject(VmObject::Instance(instance)) => {
+                        instance.borrow_mut().set_field(name, value.clone());
+                        self.stack.push(value);
+                    },
+                    Value::NativeInstance(instance) => {
+                        NativeInstance::set(&instance, &self.token(&name), value.clone())?;
+                        self.stack.push(value);
+                    },
+                    _ => return Err(self.error("Only instances have fields.".to_string())),
+                }
+            },
+            OpCode::GetSuper => {
+                let name = self.read_string();
+                let superclass = match self.stack.pop().unwrap() {
+                    Value::VmObject(VmObject::Class(class_)) => class_,
+                    _ => unreachable!("'super' is always bound to a class."),
+                };
+                let receiver = self.stack.pop().unwrap();
+                match superclass.find_method(&name) {
+                    Some(method) => {
+                        let bound = BoundMethod::new(receiver, method);
+                        self.stack.push(Value::VmObject(VmObject::BoundMethod(Rc::new(bound))));
+                    },
+                    None => return Err(self.error(format!("Undefined property '{}'.", name))),
+                }
+            },
+            OpCode::GetIndex => {
+                let index = self.stack.pop().unwrap();
+                let object = self.stack.pop().unwrap();
+                let value = self.get_index(object, index)?;
+                self.stack.push(value);
+            },
+            OpCode::SetIndex => {
+                let value = self.stack.pop().unwrap();
+                let index = self.stack.pop().unwrap();
+                let object = self.stack.pop().unwrap();
+                self.set_index(object, index, value.clone())?;
+                self.stack.push(value);
+            },
+            OpCode::Slice => {
+                let flags = self.read_byte();
+                let end = if flags & 2 != 0 { self.stack.pop() } else { None };
+                let start = if flags & 1 != 0 { self.stack.pop() } else { None };
+                let object = self.stack.pop().unwrap();
+                let value = self.slice(object, start, end)?;
+                self.stack.push(value);
+            },
+            OpCode::Equal => {
+                let right = self.stack.pop().unwrap();
+                let left = self.stack.pop().unwrap();
+                self.stack.push(Value::Boolean(left.equals(&right)));
+            },
+            OpCode::Greater => self.comparison(|l, r| l > r)?,
+            OpCode::GreaterEqual => self.comparison(|l, r| l >= r)?,
+            OpCode::Less => self.comparison(|l, r| l < r)?,
+            OpCode::LessEqual => self.comparison(|l, r| l <= r)?,
+            OpCode::Add => {
+                let right = self.stack.pop().unwrap();
+                let left = self.stack.pop().unwrap();
+                match (left, right) {
+                    (Value::Number(l), Value::Number(r)) => self.stack.push(Value::Number(l + r)),
+                    (Value::String(l), Value::String(r)) => self.stack.push(Value::String(l + &r)),
+                    _ => return Err(self.error("Operands must be two numbers or two strings.".to_string())),
+                }
+            },
+            OpCode::Subtract => self.arithmetic(|l, r| l - r)?,
+            OpCode::Multiply => self.arithmetic(|l, r| l * r)?,
+            OpCode::Divide => self.arithmetic(|l, r| l / r)?,
+            OpCode::Not => {
+                let value = self.stack.pop().unwrap();
+                self.stack.push(Value::Boolean(!value.is_truthy()));
+            },
+            OpCode::Negate => {
+                let n = self.number_operand(0)?;
+                self.stack.pop();
+                self.stack.push(Value::Number(-n));
+            },
+            OpCode::Print => {
+                let value = self.stack.pop().unwrap();
+                self.output.borrow_mut().print(&value.to_string());
+            },
+            OpCode::Jump => {
+                let offset = self.read_short();
+                self.frame_mut().ip += offset;
+            },
+            OpCode::JumpIfFalse => {
+                let offset = self.read_short();
+                if !self.peek(0).is_truthy() {
+                    self.frame_mut().ip += offset;
+                }
+            },
+            OpCode::Loop => {
+                let offset = self.read_short();
+                self.frame_mut().ip -= offset;
+            },
+            OpCode::Call => {
+                let argument_count = self.read_byte() as usize;
+                self.call_value(argument_count)?;
+            },
+            OpCode::Closure => {
+                let function = match self.read_constant() {
+                    Value::VmObject(VmObject::Function(function)) => function,
+                    _ => unreachable!("Closures are made from function constants."),
+                };
+                let mut upvalues = Vec::with_capacity(function.upvalue_count());
+                for _ in 0..function.upvalue_count() {
+                    let is_local = self.read_byte() == 1;
+                    let index = self.read_byte() as usize;
+                    if is_local {
+                        let slot = self.frame().base + index;
+                        upvalues.push(self.capture_upvalue(slot));
+                    } else {
+                        upvalues.push(self.frame().closure.upvalue(index).clone());
+                    }
+                }
+                let closure = Closure::new(function, upvalues, self.frame().closure.module().clone());
+                self.stack.push(Value::VmObject(VmObject::Closure(Rc::new(closure))));
+            },
+            OpCode::CloseUpvalue => {
+                self.close_upvalues(self.stack.len() - 1);
+                self.stack.pop();
+            },
+            OpCode::Return => {
+                let result = self.stack.pop().unwrap();
+                let frame = self.frames.pop().unwrap();
+                self.close_upvalues(frame.base);
+                // Returning from inside a try block leaves it.
+                while self.handlers.last().is_some_and(|handler| handler.frame >= self.frames.len()) {
+                    self.handlers.pop();
+                }
+                self.stack.truncate(frame.base);
+                self.stack.push(result);
+            },
+            OpCode::Class => {
+                let name = self.read_string();
+                let class_ = VmClass::new(name);
+                self.stack.push(Value::VmObject(VmObject::Class(Rc::new(class_))));
+            },
+            OpCode::Inherit => {
+                let superclass = match self.peek(1) {
+                    Value::VmObject(VmObject::Class(class_)) => class_.clone(),
+                    _ => return Err(self.error("Superclass must be a class.".to_string())),
+                };
+                if let Value::VmObject(VmObject::Class(subclass)) = self.peek(0) {
+                    subclass.inherit(&superclass);
+                }
+                self.stack.pop();
+            },
+            OpCode::Method => {
+                let name = self.read_string();
+                let method = match self.stack.pop().unwrap() {
+                    Value::VmObject(VmObject::Closure(closure)) => closure,
+                    _ => unreachable!("Methods are closures."),
+                };
+                if let Value::VmObject(VmObject::Class(class_)) = self.peek(0) {
+                    class_.add_method(name, method);
+                }
+            },
+            OpCode::List => {
+                let count = self.read_short();
+                let elements = self.stack.split_off(self.stack.len() - count);
+                self.stack.push(Value::List(Rc::new(RefCell::new(elements))));
+            },
+            OpCode::Map => {
+                let count = self.read_short();
+                let entries = self.stack.split_off(self.stack.len() - 2 * count);
+                let mut map = LoxMap::new();
+                for entry in entries.chunks(2) {
+                    let key = MapKey::from_value(&self.token(""), &entry[0])?;
+                    map.insert(key, entry[1].clone());
+                }
+                self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+            },
+            OpCode::Interpolate => {
+                let count = self.read_short();
+                let parts = self.stack.split_off(self.stack.len() - count);
+                let result: String = parts.iter().map(|part| part.to_string()).collect();
+                self.stack.push(Value::String(result));
+            },
+            OpCode::Throw => {
+                let value = self.stack.pop().unwrap();
+                return Err(LoxRuntime::Throw(RuntimeThrow::new(self.token("throw"), value)));
+            },
+            OpCode::PushHandler => {
+                let offset = self.read_short();
+                let handler = Handler {
+                    frame: self.frames.len() - 1,
+                    stack_height: self.stack.len(),
+                    target: self.frame().ip + offset,
+                };
+                self.handlers.push(handler);
+            },
+            OpCode::PopHandler => {
+                self.handlers.pop();
+            },
+            OpCode::Import => {
+                let path = self.read_string();
+                let module = self.import_module(path)?;
+                self.stack.push(Value::VmObject(VmObject::Module(module)));
+            },
+            OpCode::Rethrow => {
+                let slot = self.frame().base + self.read_byte() as usize;
+                return Err(self.caught.remove(&slot).expect("Finally handlers rethrow what they caught."));
+            },
+        }
+        Ok(())
+    }
+
+    /// Loads the module at a path, running it the first time it is imported.
+    fn import_module(&mut self, relative_path: String) -> Result<Rc<VmModule>, LoxRuntime> {
+        // The compiler puts the import instruction at the path, so errors point at it.
+        let location = self.token("");
+        let import = Token::new(TokenType::String, relative_path.clone(), Some(LiteralValue::String(relative_path)), location.line())
+            .with_span(*location.span());
+        let path = lox_module::module_path(&self.import_stack, &import)?;
+        if let Some(module) = self.modules.get(&path) {
+            return Ok(module.clone());
+        }
+        lox_module::check_circular_import(&self.import_stack, &path, &import)?;
+
+        self.import_stack.push(path.clone());
+        let result = self.run_module(&path);
+        self.import_stack.pop();
+
+        let module = result.map_err(|e| lox_module::module_error(&import, &path, e))?;
+        self.modules.insert(path, module.clone());
+        Ok(module)
+    }
+
+    /// Compiles and runs a module file with its own globals, on top of the frames running now.
+    fn run_module(&mut self, path: &Path) -> Result<Rc<VmModule>, RuntimeError> {
+        let statements = lox_module::parse_module(path, &mut self.host)?;
+        let function = Compiler::new().compile(&statements).map_err(|e| RuntimeError::new(
+            Token::new(TokenType::String, lox_module::display_path(path), None, e.line()),
+            e.message().clone(),
+        ))?;
+
+        let module = Rc::new(VmModule::new(lox_module::module_name(path), Rc::from(lox_module::display_path(path))));
+        let closure = Rc::new(Closure::new(function, Vec::new(), module.clone()));
+        self.stack.push(Value::VmObject(VmObject::Closure(closure)));
+        self.run_call(0).map_err(Self::unwind_error)?;
+        Ok(module)
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().unwrap();
+        let byte = frame.closure.function().chunk().code()[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> usize {
+        let frame = self.frames.last_mut().unwrap();
+        let value = frame.closure.function().chunk().read_short(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_short();
+        self.frame().closure.function().chunk().constant(index).clone()
+    }
+
+    fn read_string(&mut self) -> String {
+        match self.read_constant() {
+            Value::String(name) => name,
+            _ => unreachable!("Names are string constants."),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// Returns a token at the source of the current instruction, for errors and natives that
+    /// need one. Outside of any script, as when the host calls a function, the line is 0.
+    fn token(&self, lexeme: &str) -> Token {
+        let Some(frame) = self.frames.last() else {
+            return Token::new(TokenType::Identifier, lexeme.to_string(), None, 0);
+        };
+        let chunk = frame.closure.function().chunk();
+        Token::new(TokenType::Identifier, lexeme.to_string(), None, chunk.line(frame.ip - 1))
+            .with_span(chunk.span(frame.ip - 1))
+    }
+
+    fn error(&self, message: String) -> LoxRuntime {
+        LoxRuntime::Error(RuntimeError::new(self.token(""), message))
+    }
+
+    fn number_operand(&self, distance: usize) -> Result<f64, LoxRuntime> {
+        match self.peek(distance) {
+            Value::Number(n) => Ok(*n),
+            _ => Err(self.error("Operand must be a number.".to_string())),
+        }
+    }
+
+    fn arithmetic(&mut self, op: fn(f64, f64) -> f64) -> Result<(), LoxRuntime> {
+        let l = self.number_operand(1)?;
+        let r = self.number_operand(0)?;
+        self.stack.truncate(self.stack.len() - 2);
+        self.stack.push(Value::Number(op(l, r)));
+        Ok(())
+    }
+
+    fn comparison(&mut self, op: fn(f64, f64) -> bool) -> Result<(), LoxRuntime> {
+        let l = self.number_operand(1)?;
+        let r = self.number_operand(0)?;
+        self.stack.truncate(self.stack.len() - 2);
+        self.stack.push(Value::Boolean(op(l, r)));
+        Ok(())
+    }
+
+    fn call_value(&mut self, argument_count: usize) -> Result<(), LoxRuntime> {
+        let callee_slot = self.stack.len() - 1 - argument_count;
+        match self.stack[callee_slot].clone() {
+            Value::VmObject(VmObject::Closure(closure)) => self.call(closure, argument_count),
+            Value::VmObject(VmObject::BoundMethod(bound)) => {
+                self.stack[callee_slot] = bound.receiver().clone();
+                self.call(bound.method().clone(), argument_count)
+            },
+            Value::VmObject(VmObject::Class(class_)) => {
+                let instance = VmInstance::new(class_.clone());
+                self.stack[callee_slot] = Value::VmObject(VmObject::Instance(Rc::new(RefCell::new(instance))));
+                match class_.find_method("init") {
+                    Some(initializer) => self.call(initializer, argument_count),
+                    None if argument_count != 0 => Err(self.arity_error(Arity::Fixed(0), argument_count)),
+                    None => Ok(()),
+                }
+            },
+            Value::LoxCallable(native) => {
+                if !native.arity().accepts(argument_count) {
+                    return Err(self.arity_error(native.arity(), argument_count));
+                }
+                let arguments = self.stack.split_off(callee_slot + 1);
+                self.stack.pop();
+                self.host.set_call_line(self.token("").line());
+                let result = native.call(&mut self.host, arguments)?;
+                self.stack.push(result);
+                Ok(())
+            },
+            _ => Err(self.error("Can only call functions and classes.".to_string())),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, argument_count: usize) -> Result<(), LoxRuntime> {
+        let function = closure.function();
+        if argument_count != function.arity() {
+            let error = RuntimeError::new(
+                self.token(""),
+                format!("Expected {} arguments but got {}.", function.arity(), argument_count),
+            );
+            return Err(LoxRuntime::Error(match function.definition() {
+                Some(span) => error.with_note(span, "function defined here"),
+                None => error,
+            }));
+        }
+        // The script itself has the first frame.
+        if self.frames.len() > self.max_call_depth {
+            return Err(self.error("Stack overflow.".to_string()));
+        }
+        let base = self.stack.len() - 1 - argument_count;
+        self.frames.push(CallFrame { closure, ip: 0, base });
+        Ok(())
+    }
+
+    fn arity_error(&self, arity: Arity, argument_count: usize) -> LoxRuntime {
+        self.error(format!("Expected {} arguments but got {}.", arity, argument_count))
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self.open_upvalues.iter().position(|upvalue| match &*upvalue.borrow() {
+            Upvalue::Open(open_slot) => *open_slot >= slot,
+            Upvalue::Closed(_) => false,
+        });
+
+        if let Some(position) = position
+            && let Upvalue::Open(open_slot) = &*self.open_upvalues[position].borrow()
+            && *open_slot == slot {
+            return self.open_upvalues[position].clone();
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position.unwrap_or(self.open_upvalues.len()), upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of the open upvalues at or above `from` off the stack.
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(slot) => *slot,
+                Upvalue::Closed(_) => unreachable!("Closed upvalues are removed from the open list."),
+            };
+            if slot < from {
+                break;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn get_property(&self, object: Value, name: &str) -> Result<Value, LoxRuntime> {
+        match object {
+            Value::VmObject(VmObject::Instance(instance)) => {
+                if let Some(value) = instance.borrow().field(name) {
+                    return Ok(value);
+                }
+                let method = instance.borrow().class_().find_method(name);
+                match method {
+                    Some(method) => {
+                        let receiver = Value::VmObject(VmObject::Instance(instance));
+                        Ok(Value::VmObject(VmObject::BoundMethod(Rc::new(BoundMethod::new(receiver, method)))))
+                    },
+                    None => Err(self.error(format!("Undefined property '{}'.", name))),
+                }
+            },
+            Value::List(list) => Ok(Value::LoxCallable(Rc::new(ListMethod::new(list, &self.token(name))?))),
+            Value::Map(map) => Ok(Value::LoxCallable(Rc::new(MapMethod::new(map, &self.token(name))?))),
+            Value::NativeInstance(instance) => NativeInstance::get(&instance, &self.token(name)),
+            Value::VmObject(VmObject::Module(module)) => match module.global(name) {
+                Some(value) => Ok(value),
+                None => Err(self.error(format!("Module '{}' has no member '{}'.", module.name(), name))),
+            },
+            _ => Err(self.error("Only instances have properties.".to_string())),
+        }
+    }
+
+    fn get_index(&self, object: Value, index: Value) -> Result<Value, LoxRuntime> {
+        let bracket = self.token("[");
+        match object {
+            Value::List(list) => {
+                let list = list.borrow();
+                let position = list_index(&bracket, &index, list.len())?;
+                Ok(list[position].clone())
+            },
+            Value::Map(map) => {
+                let key = MapKey::from_value(&bracket, &index)?;
+                match map.borrow().get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(self.error(format!("Undefined key '{}'.", index))),
+                }
+            },
+            _ => Err(self.error("Only lists and maps can be indexed.".to_string())),
+        }
+    }
+
+    fn set_index(&self, object: Value, index: Value, value: Value) -> Result<(), LoxRuntime> {
+        let bracket = self.token("[");
+        match object {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                let position = list_index(&bracket, &index, list.len())?;
+                list[position] = value;
+                Ok(())
+            },
+            Value::Map(map) => {
+                let key = MapKey::from_value(&bracket, &index)?;
+                map.borrow_mut().insert(key, value);
+                Ok(())
+            },
+            _ => Err(self.error("Only lists and maps can be indexed.".to_string())),
+        }
+    }
+
+    fn slice(&self, object: Value, start: Option<Value>, end: Option<Value>) -> Result<Value, LoxRuntime> {
+        let bracket = self.token("[");
+        match object {
+            Value::List(list) => {
+                let list = list.borrow();
+                let start = slice_bound(&bracket, start, list.len(), 0)?;
+                let end = slice_bound(&bracket, end, list.len(), list.len())?;
+                let elements = if start < end { list[start..end].to_vec() } else { Vec::new() };
+                Ok(Value::List(Rc::new(RefCell::new(elements))))
+            },
+            _ => Err(self.error("Only lists can be sliced.".to_string())),
+        }
+    }
+}
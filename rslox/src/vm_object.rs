@@ -0,0 +1,285 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use crate::chunk::Chunk;
+use crate::span::Span;
+use crate::value::Value;
+
+/// Heap objects of the bytecode VM.
+#[derive(Clone)]
+pub enum VmObject {
+    Function(Rc<VmFunction>),
+    Closure(Rc<Closure>),
+    Class(Rc<VmClass>),
+    Instance(Rc<RefCell<VmInstance>>),
+    BoundMethod(Rc<BoundMethod>),
+    Module(Rc<VmModule>),
+}
+
+impl VmObject {
+    /// Returns the address of the object, which identifies it.
+    pub fn address(&self) -> usize {
+        match self {
+            VmObject::Function(function) => Rc::as_ptr(function) as usize,
+            VmObject::Closure(closure) => Rc::as_ptr(closure) as usize,
+            VmObject::Class(class_) => Rc::as_ptr(class_) as usize,
+            VmObject::Instance(instance) => Rc::as_ptr(instance) as usize,
+            VmObject::BoundMethod(bound) => Rc::as_ptr(bound) as usize,
+            VmObject::Module(module) => Rc::as_ptr(module) as usize,
+        }
+    }
+}
+
+impl Display for VmObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmObject::Function(function) => write!(f, "{}", function),
+            VmObject::Closure(closure) => write!(f, "{}", closure.function()),
+            VmObject::Class(class_) => write!(f, "{}", class_),
+            VmObject::Instance(instance) => write!(f, "{}", instance.borrow()),
+            VmObject::BoundMethod(bound) => write!(f, "{}", bound.method().function()),
+            VmObject::Module(module) => write!(f, "{}", module),
+        }
+    }
+}
+
+/// A compiled function. The top-level code of a script is compiled into one without a name.
+pub struct VmFunction {
+    name: Option<String>,
+    arity: usize,
+    upvalue_count: usize,
+    chunk: Chunk,
+    /// Span of the function's name, which arity errors point at.
+    definition: Option<Span>,
+}
+
+impl VmFunction {
+    pub fn new(name: Option<String>, arity: usize, upvalue_count: usize, chunk: Chunk) -> Self {
+        VmFunction {
+            name,
+            arity,
+            upvalue_count,
+            chunk,
+            definition: None,
+        }
+    }
+
+    /// Sets where the function is declared.
+    pub fn with_definition(mut self, span: Span) -> Self {
+        self.definition = Some(span).filter(|span| span.line() > 0);
+        self
+    }
+
+    pub fn definition(&self) -> Option<Span> {
+        self.definition
+    }
+
+    /// Returns the name of the function, or None for the top-level code of a script.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn upvalue_count(&self) -> usize {
+        self.upvalue_count
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+}
+
+impl Display for VmFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// A variable captured by a closure. It points at the stack slot while the variable's scope is
+/// active and holds the value itself once the scope has ended.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A function together with the variables it captured and the module whose globals it uses.
+pub struct Closure {
+    function: Rc<VmFunction>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    module: Rc<VmModule>,
+}
+
+impl Closure {
+    pub fn new(function: Rc<VmFunction>, upvalues: Vec<Rc<RefCell<Upvalue>>>, module: Rc<VmModule>) -> Self {
+        Closure {
+            function,
+            upvalues,
+            module,
+        }
+    }
+
+    pub fn function(&self) -> &Rc<VmFunction> {
+        &self.function
+    }
+
+    pub fn module(&self) -> &Rc<VmModule> {
+        &self.module
+    }
+
+    pub fn upvalue(&self, index: usize) -> &Rc<RefCell<Upvalue>> {
+        &self.upvalues[index]
+    }
+}
+
+/// A loaded source file of the bytecode VM. Its members are the globals the file defined when it ran.
+pub struct VmModule {
+    name: String,
+    /// File the module was loaded from, as shown in errors and tracebacks.
+    file: RefCell<Rc<str>>,
+    globals: RefCell<HashMap<String, Value>>,
+}
+
+impl VmModule {
+    pub fn new(name: String, file: Rc<str>) -> Self {
+        VmModule {
+            name,
+            file: RefCell::new(file),
+            globals: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn file(&self) -> Rc<str> {
+        self.file.borrow().clone()
+    }
+
+    /// Sets the file of the main script, which is only known once it is loaded.
+    pub fn set_file(&self, file: Rc<str>) {
+        *self.file.borrow_mut() = file;
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get(name).cloned()
+    }
+
+    pub fn define_global(&self, name: String, value: Value) {
+        self.globals.borrow_mut().insert(name, value);
+    }
+
+    /// Assigns a global the module has defined. Returns false if it has not defined it.
+    pub fn assign_global(&self, name: &str, value: Value) -> bool {
+        match self.globals.borrow_mut().get_mut(name) {
+            Some(global) => {
+                *global = value;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl Display for VmModule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+/// A class of the bytecode VM. Inherited methods are copied into the subclass when it is created.
+pub struct VmClass {
+    name: String,
+    methods: RefCell<HashMap<String, Rc<Closure>>>,
+}
+
+impl VmClass {
+    pub fn new(name: String) -> Self {
+        VmClass {
+            name,
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<Closure>> {
+        self.methods.borrow().get(name).cloned()
+    }
+
+    pub fn add_method(&self, name: String, method: Rc<Closure>) {
+        self.methods.borrow_mut().insert(name, method);
+    }
+
+    /// Copies all methods of the superclass into this class.
+    pub fn inherit(&self, superclass: &VmClass) {
+        let methods = superclass.methods.borrow().clone();
+        self.methods.borrow_mut().extend(methods);
+    }
+}
+
+impl Display for VmClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<class {} instance>", self.name)
+    }
+}
+
+pub struct VmInstance {
+    class_: Rc<VmClass>,
+    fields: HashMap<String, Value>,
+}
+
+impl VmInstance {
+    pub fn new(class_: Rc<VmClass>) -> Self {
+        VmInstance {
+            class_,
+            fields: HashMap::new(),
+        }
+    }
+
+    pub fn class_(&self) -> &Rc<VmClass> {
+        &self.class_
+    }
+
+    pub fn field(&self, name: &str) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+
+    pub fn set_field(&mut self, name: String, value: Value) {
+        self.fields.insert(name, value);
+    }
+}
+
+impl Display for VmInstance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<instance of {}>", self.class_.name)
+    }
+}
+
+/// A method read from an instance, remembering the instance it was read from.
+pub struct BoundMethod {
+    receiver: Value,
+    method: Rc<Closure>,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: Rc<Closure>) -> Self {
+        BoundMethod {
+            receiver,
+            method,
+        }
+    }
+
+    pub fn receiver(&self) -> &Value {
+        &self.receiver
+    }
+
+    pub fn method(&self) -> &Rc<Closure> {
+        &self.method
+    }
+}
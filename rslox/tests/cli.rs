@@ -0,0 +1,108 @@
+//! Tests of the command-line interface, running the built binary.
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Writes the script to a temporary file and runs the binary on it with the given arguments.
+fn run_script(name: &str, source: &str, arguments: &[&str]) -> Output {
+    let path = script_path(name);
+    std::fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rslox"))
+        .args(arguments)
+        .arg("--file")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+fn script_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rslox-cli-test-{}-{}.lox", std::process::id(), name))
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[test]
+fn static_errors_exit_with_65() {
+    let output = run_script("resolve", "print \"not run\";\nreturn 1;\n{ var a = a; }", &[]);
+    assert_eq!(output.status.code(), Some(65));
+    let stdout = text(&output.stdout);
+    assert!(!stdout.contains("not run"), "{}", stdout);
+    let stderr = text(&output.stderr);
+    assert!(stderr.contains("Cannot return from top-level code."), "{}", stderr);
+    assert!(stderr.contains("Cannot read local variable in its own initializer."), "{}", stderr);
+}
+
+#[test]
+fn json_diagnostics_go_to_stderr_without_banners() {
+    for backend in ["tree", "vm"] {
+        let output = run_script(
+            &format!("json-{}", backend),
+            "print \"before\";\nprint nil + 1;",
+            &["--error-format=json", "--backend", backend],
+        );
+        assert_eq!(output.status.code(), Some(70), "{}", backend);
+        assert_eq!(text(&output.stdout), "before\n", "{}", backend);
+
+        let stderr = text(&output.stderr);
+        let lines: Vec<_> = stderr.lines().collect();
+        assert_eq!(lines.len(), 1, "{}: {}", backend, stderr);
+        assert!(lines[0].starts_with("{\"kind\":\"runtime\",\"message\":\"Operands must be two numbers or two strings.\""), "{}: {}", backend, stderr);
+        assert!(!stderr.contains('\u{1b}'), "{}: colored JSON: {}", backend, stderr);
+    }
+}
+
+#[test]
+fn syntax_errors_in_json_exit_with_65() {
+    let output = run_script("json-syntax", "print 1 +;\nvar = 2;", &["--error-format=json"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(text(&output.stdout), "");
+    let stderr = text(&output.stderr);
+    assert_eq!(stderr.lines().count(), 2, "{}", stderr);
+    assert!(stderr.lines().all(|line| line.starts_with("{\"kind\":\"syntax\"")), "{}", stderr);
+}
+
+#[test]
+fn human_diagnostics_go_to_stderr_and_output_to_stdout() {
+    let output = run_script("human", "print \"before\";\nprint nil + 1;", &[]);
+    assert_eq!(output.status.code(), Some(70));
+    let stdout = text(&output.stdout);
+    assert!(stdout.contains("Running script from file:"), "{}", stdout);
+    assert!(stdout.contains("before\n"), "{}", stdout);
+    assert!(!stdout.contains("Operands"), "{}", stdout);
+
+    // Standard error is a pipe rather than a terminal, so it is not colored.
+    let stderr = text(&output.stderr);
+    assert!(stderr.starts_with("runtime error: Operands must be two numbers or two strings."), "{}", stderr);
+    assert!(!stderr.contains('\u{1b}'), "{}", stderr);
+}
+
+#[test]
+fn deep_recursion_is_a_runtime_error() {
+    let source = "
+        fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); }
+        print f(150);
+        print f(1000);
+        print f(20000);
+    ";
+    for backend in ["tree", "vm"] {
+        let output = run_script(&format!("deep-{}", backend), source, &["--backend", backend]);
+        assert_eq!(output.status.code(), Some(70), "{}", backend);
+        assert!(text(&output.stdout).ends_with("150\n1000\n"), "{}: {}", backend, text(&output.stdout));
+        let stderr = text(&output.stderr);
+        assert!(stderr.starts_with("runtime error: Stack overflow."), "{}: {}", backend, stderr);
+    }
+}
+
+#[test]
+fn gc_stats_are_rejected_with_the_vm() {
+    for arguments in [["--backend", "vm"].as_slice(), &["--disassemble"]] {
+        let output = run_script("gc-stats", "print 1;", &[&["--gc-stats"], arguments].concat());
+        assert_eq!(output.status.code(), Some(2), "{:?}", arguments);
+        assert_eq!(text(&output.stdout), "", "{:?}", arguments);
+        let stderr = text(&output.stderr);
+        assert!(stderr.contains("--gc-stats needs the tree backend"), "{:?}: {}", arguments, stderr);
+    }
+}
@@ -17,6 +17,9 @@ impl GenerateAst {
         let mut file = File::create(&file_path)?;
 
         file.write_all(format!("//[ Appendix II {}\n", base_name.to_lowercase()).as_bytes())?;
+        // Accessors are generated for every field, so not all of them are used, and return
+        // the boxed nodes and vectors of boxed nodes the tree is made of.
+        file.write_all("#![allow(dead_code, clippy::vec_box, clippy::borrowed_box)]\n".as_bytes())?;
 
         for import in imports {
             file.write_all(import.as_bytes())?;
@@ -44,7 +47,7 @@ impl GenerateAst {
         file.write_all(format!("pub struct {} {{\n", class_name).as_bytes())?;
 
         for field in fields.split(',') {
-            let field_parts: Vec<&str> = field.trim().split_whitespace().collect();
+            let field_parts: Vec<&str> = field.split_whitespace().collect();
             let field_type = field_parts[0];
             let field_name = Self::safe_ident(field_parts[1]);
             file.write_all(format!("    {}: {},\n", field_name, field_type).as_bytes())?;
@@ -57,7 +60,7 @@ impl GenerateAst {
         // split fields to collection of tuples with field type and field name
         let field_list: Vec<(&str, String)> = fields.split(',')
             .map(|field| {
-                let parts: Vec<&str> = field.trim().split_whitespace().collect();
+                let parts: Vec<&str> = field.split_whitespace().collect();
                 (parts[0], Self::safe_ident(parts[1]))
             })
             .collect();
@@ -112,7 +115,7 @@ impl GenerateAst {
             // let safe_type_name = Self::safe_ident(&type_name.to_lowercase());
             file.write_all(format!(
                 "    fn visit_{}_{}(&mut self, {}: &{}) -> Result<T, LoxRuntime>;\n",
-                type_name.to_lowercase(), base_name.to_lowercase(), base_name.to_lowercase(), type_name
+                Self::snake_case(type_name), base_name.to_lowercase(), base_name.to_lowercase(), type_name
             ).as_bytes())?;
         }
         file.write_all("}\n".as_bytes())?;
@@ -128,7 +131,21 @@ impl GenerateAst {
             // let safe_type_name = Self::safe_ident(&type_name.to_lowercase());
             file.write_all(format!(
                 "            {}::{}({}) => visitor.visit_{}_{}({}),\n",
-                base_name, type_name, base_name.to_lowercase(), type_name.to_lowercase(), base_name.to_lowercase(), base_name.to_lowercase()
+                base_name, type_name, base_name.to_lowercase(), Self::snake_case(type_name), base_name.to_lowercase(), base_name.to_lowercase()
+            ).as_bytes())?;
+        }
+        file.write_all("        }\n".as_bytes())?;
+        file.write_all("    }\n".as_bytes())?;
+
+        // Every node has a span, so the enum can return the span of whichever node it holds.
+        file.write_all("\n    pub fn span(&self) -> &Span {\n".as_bytes())?;
+        file.write_all("        match self {\n".as_bytes())?;
+        for t in types {
+            let type_descr: Vec<&str> = t.split(':').collect();
+            let type_name = type_descr[0].trim();
+            file.write_all(format!(
+                "            {}::{}({}) => {}.span(),\n",
+                base_name, type_name, base_name.to_lowercase(), base_name.to_lowercase()
             ).as_bytes())?;
         }
         file.write_all("        }\n".as_bytes())?;
@@ -138,6 +155,18 @@ impl GenerateAst {
         Ok(())
     }
 
+    /// Converts a type name such as `IndexSet` to its snake case form `index_set`.
+    fn snake_case(name: &str) -> String {
+        let mut result = String::new();
+        for (i, c) in name.chars().enumerate() {
+            if c.is_uppercase() && i > 0 {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        }
+        result
+    }
+
     /// Returns a safe Rust identifier, appending an underscore if the name is a keyword.
     fn safe_ident(name: &str) -> String {
         const KEYWORDS: &[&str] = &[
@@ -171,46 +200,61 @@ fn main() -> Result<()> {
 
     let _ = GenerateAst::define_ast(
         vec![
+             "use std::rc::Rc;\n",
              "use crate::literal::LiteralValue;\n",
              "use crate::runtime_error::LoxRuntime;\n",
+             "use crate::span::Span;\n",
+             "use crate::stmt::Function;\n",
              "use crate::token::Token;\n",
              "use anyhow::Result;\n"],
         "Expr",
         &args.output,
         vec![
-            "Assign   : Token name, Box<Expr> value",
-            "Binary   : Box<Expr> left, Token operator, Box<Expr> right",
-            "Call     : Box<Expr> callee, Token paren, Vec<Box<Expr>> arguments",
-            "Get      : Box<Expr> object, Token name",
-            "Grouping : Box<Expr> expression",
-            "Literal  : LiteralValue value",
-            "Logical  : Box<Expr> left, Token operator, Box<Expr> right",
-            "Set      : Box<Expr> object, Token name, Box<Expr> value",
-            // "Super    : Token keyword, Token method",
-            // "This     : Token keyword",
-            "Unary    : Token operator, Box<Expr> right",
-            "Variable : Token name"
+            "Assign   : usize id, Span span, Token name, Box<Expr> value",
+            "Binary   : usize id, Span span, Box<Expr> left, Token operator, Box<Expr> right",
+            "Call     : usize id, Span span, Box<Expr> callee, Token paren, Vec<Box<Expr>> arguments",
+            "Get      : usize id, Span span, Box<Expr> object, Token name",
+            "Grouping : usize id, Span span, Box<Expr> expression",
+            "Index    : usize id, Span span, Box<Expr> object, Token bracket, Box<Expr> index",
+            "IndexSet : usize id, Span span, Box<Expr> object, Token bracket, Box<Expr> index, Box<Expr> value",
+            "Interpolation : usize id, Span span, Vec<Box<Expr>> parts",
+            "Lambda   : usize id, Span span, Rc<Function> function",
+            "List     : usize id, Span span, Vec<Box<Expr>> elements",
+            "Literal  : usize id, Span span, LiteralValue value",
+            "Logical  : usize id, Span span, Box<Expr> left, Token operator, Box<Expr> right",
+            "Map      : usize id, Span span, Token brace, Vec<Box<Expr>> keys, Vec<Box<Expr>> values",
+            "Set      : usize id, Span span, Box<Expr> object, Token name, Box<Expr> value",
+            "Slice    : usize id, Span span, Box<Expr> object, Token bracket, Option<Box<Expr>> start, Option<Box<Expr>> end",
+            "Super    : usize id, Span span, Token keyword, Token method",
+            "This     : usize id, Span span, Token keyword",
+            "Unary    : usize id, Span span, Token operator, Box<Expr> right",
+            "Variable : usize id, Span span, Token name"
         ]);
 
         let _ = GenerateAst::define_ast(
         vec![
              "use crate::runtime_error::LoxRuntime;\n",
+             "use crate::span::Span;\n",
              "use crate::token::Token;\n",
              "use crate::expr::Expr;\n",
              "use anyhow::Result;\n"],
         "Stmt",
         &args.output,
         vec![
-            "Block      : Vec<Box<Stmt>> statements",
-            // "Class      : Token name, Option<Box<Expr>> superclass, Vec<Box<Function>> methods",
-            "Class      : Token name, Vec<Box<Function>> methods",
-            "Expression : Box<Expr> expression",
-            "Function   : Token name, Vec<Token> params, Vec<Box<Stmt>> body",
-            "If         : Box<Expr> condition, Box<Stmt> then_branch, Option<Box<Stmt>> else_branch",
-            "Print      : Box<Expr> expression",
-            "Return     : Token keyword, Option<Box<Expr>> value",
-            "Var        : Token name, Option<Box<Expr>> initializer",
-            "While      : Box<Expr> condition, Box<Stmt> body"
+            "Block      : Span span, Vec<Box<Stmt>> statements",
+            "Break      : Span span, Token keyword, Option<Token> label",
+            "Class      : Span span, Token name, Option<Box<Expr>> superclass, Vec<Box<Function>> methods",
+            "Continue   : Span span, Token keyword, Option<Token> label",
+            "Expression : Span span, Box<Expr> expression",
+            "Function   : Span span, Token name, Vec<Token> params, Vec<Box<Stmt>> body",
+            "If         : Span span, Box<Expr> condition, Box<Stmt> then_branch, Option<Box<Stmt>> else_branch",
+            "Import     : Span span, Token path, Option<Token> alias, Vec<Token> names",
+            "Print      : Span span, Box<Expr> expression",
+            "Return     : Span span, Token keyword, Option<Box<Expr>> value",
+            "Throw      : Span span, Token keyword, Box<Expr> value",
+            "Try        : Span span, Vec<Box<Stmt>> body, Option<Token> catch_name, Option<Vec<Box<Stmt>>> catch_body, Option<Vec<Box<Stmt>>> finally_body",
+            "Var        : Span span, Token name, Option<Box<Expr>> initializer",
+            "While      : Span span, Box<Expr> condition, Box<Stmt> body, Option<Box<Expr>> increment, Option<Token> label"
         ]);
     
     cprintln!(colored::Color::Green, "Finished.");
@@ -65,11 +65,11 @@ impl Expression {
 pub struct ExpressionPrintingVisitor {}
 
 impl Visitor<()> for ExpressionPrintingVisitor {
-    fn visit_literal_expression(&self, literal: &Literal) -> () {
+    fn visit_literal_expression(&self, literal: &Literal) {
         print!("{}", literal.value);
     }
 
-    fn visit_addition_expression(&self, addition: &Addition) -> () {
+    fn visit_addition_expression(&self, addition: &Addition) {
         print!("(");
         addition.left.accept(self);
         print!(" + ");
@@ -77,7 +77,7 @@ impl Visitor<()> for ExpressionPrintingVisitor {
         print!(")");
     }
 
-    fn visit_subtraction_expression(&self, subtraction: &Subtraction) -> () {
+    fn visit_subtraction_expression(&self, subtraction: &Subtraction) {
         print!("(");
         subtraction.left.accept(self);
         print!(" - ");